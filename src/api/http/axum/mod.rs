@@ -1,4 +1,8 @@
-use std::{convert::Infallible, marker::PhantomData};
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+};
 
 use async_graphql::{
     http::{playground_source, GraphQLPlaygroundConfig},
@@ -7,11 +11,36 @@ use async_graphql::{
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 use axum::{
     extract,
+    http::{header, HeaderMap},
     response::{self, IntoResponse},
     routing::get,
     AddExtensionLayer, Router,
 };
 
+use crate::{api::http::graphql::BearerToken, core::limit};
+
+const FORWARDED_USER: &str = "x-forwarded-user";
+
+/// The addresses of the authenticating proxies allowed to name the user with
+/// `X-Forwarded-User`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    /// The user named by a trusted proxy, or else the address of the peer,
+    /// as anyone can set the header.
+    fn user(&self, peer: SocketAddr, headers: &HeaderMap) -> limit::UserId {
+        let forwarded_user = headers
+            .get(FORWARDED_USER)
+            .and_then(|user| user.to_str().ok())
+            .filter(|user| !user.is_empty());
+        match forwarded_user {
+            Some(user) if self.0.contains(&peer.ip()) => user.to_owned(),
+            _ => peer.ip().to_string(),
+        }
+    }
+}
+
 pub struct GraphQL<Query, Mutation, Subscription>(
     PhantomData<(Query, Mutation, Subscription)>,
     Infallible,
@@ -23,11 +52,26 @@ where
     Mutation: ObjectType + 'static,
     Subscription: SubscriptionType + 'static,
 {
+    /// Run a query on behalf of its user, for the provider calls to be queued
+    /// fairly between users. The bearer token, if any, is handed to the
+    /// resolvers to check.
     async fn handler(
         schema: extract::Extension<Schema<Query, Mutation, Subscription>>,
+        trusted_proxies: extract::Extension<TrustedProxies>,
+        extract::ConnectInfo(peer): extract::ConnectInfo<SocketAddr>,
         req: GraphQLRequest,
+        headers: HeaderMap,
     ) -> GraphQLResponse {
-        schema.execute(req.into_inner()).await.into()
+        let user = trusted_proxies.user(peer, &headers);
+        let mut req = req.into_inner();
+        if let Some(token) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            req = req.data(BearerToken(token.to_owned()));
+        }
+        limit::as_user(user, schema.execute(req)).await.into()
     }
 
     async fn playground() -> impl IntoResponse {
@@ -36,10 +80,66 @@ where
         ))
     }
 
-    pub fn routes(router: Router, schema: Schema<Query, Mutation, Subscription>) -> Router {
+    /// The routes, to be served with the peer address as connection info.
+    pub fn routes(
+        router: Router,
+        schema: Schema<Query, Mutation, Subscription>,
+        trusted_proxies: TrustedProxies,
+    ) -> Router {
         router
             .route("/", get(Self::playground).post(Self::handler))
             .route("/ws", GraphQLSubscription::new(schema.clone()))
             .layer(AddExtensionLayer::new(schema))
+            .layer(AddExtensionLayer::new(trusted_proxies))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::{EmptyMutation, EmptySubscription, Object};
+
+    use super::*;
+
+    struct Query;
+
+    #[Object]
+    impl Query {
+        async fn ping(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_queries_with_the_peer_address() {
+        let schema = Schema::new(Query, EmptyMutation, EmptySubscription);
+        let app = GraphQL::routes(Router::new(), schema, TrustedProxies::default());
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service_with_connect_info::<SocketAddr, _>());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let res: serde_json::Value = reqwest::Client::new()
+            .post(format!("http://{}/", addr))
+            .json(&serde_json::json!({"query": "{ ping }"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(res["data"]["ping"], true);
+    }
+
+    #[test]
+    fn trusts_forwarded_users_from_proxies_only() {
+        let proxies = TrustedProxies(vec![IpAddr::from([10, 0, 0, 1])]);
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_USER, "alice".parse().unwrap());
+
+        let via_proxy = SocketAddr::from(([10, 0, 0, 1], 40000));
+        let direct = SocketAddr::from(([10, 0, 0, 2], 40000));
+        assert_eq!(proxies.user(via_proxy, &headers), "alice");
+        assert_eq!(proxies.user(direct, &headers), "10.0.0.2");
+        assert_eq!(proxies.user(via_proxy, &HeaderMap::new()), "10.0.0.1");
     }
 }
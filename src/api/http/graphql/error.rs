@@ -1,7 +1,182 @@
+use async_graphql::{indexmap::IndexMap, ErrorExtensions, Name, Value};
+use tracing::{error, warn};
+
+use crate::core::{reload, ErrorKind, IdParsingError, ProviderError, ProviderErrorDetails};
+
+/// The error codes reported to the clients in `extensions.code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    ProviderUnavailable,
+    Conflict,
+    RateLimited,
+    InvalidId,
+    Internal,
+}
+
+impl Code {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NotFound => "NOT_FOUND",
+            Self::Unauthorized => "UNAUTHORIZED",
+            Self::Forbidden => "FORBIDDEN",
+            Self::ProviderUnavailable => "PROVIDER_UNAVAILABLE",
+            Self::Conflict => "CONFLICT",
+            Self::RateLimited => "RATE_LIMITED",
+            Self::InvalidId => "INVALID_ID",
+            Self::Internal => "INTERNAL",
+        }
+    }
+
+    /// The message shown to the clients; never contains internal details.
+    fn message(self) -> &'static str {
+        match self {
+            Self::NotFound => "Not found",
+            Self::Unauthorized => "The provider rejected our credentials",
+            Self::Forbidden => "The provider denied access",
+            Self::ProviderUnavailable => "The provider is unavailable",
+            Self::Conflict => "The operation conflicts with the current state",
+            Self::RateLimited => "The provider is rate limiting requests",
+            Self::InvalidId => "Invalid ID",
+            Self::Internal => "Internal error",
+        }
+    }
+}
+
+impl From<ErrorKind> for Code {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::Unauthorized => Self::Unauthorized,
+            ErrorKind::Forbidden => Self::Forbidden,
+            ErrorKind::Unavailable => Self::ProviderUnavailable,
+            ErrorKind::Conflict => Self::Conflict,
+            ErrorKind::RateLimited => Self::RateLimited,
+            ErrorKind::InvalidId => Self::InvalidId,
+            ErrorKind::Other => Self::Internal,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("Unknown provider")]
 pub struct UnknownProvider;
 
+impl ErrorExtensions for UnknownProvider {
+    fn extend(self) -> async_graphql::Error {
+        with_code(self.to_string(), Code::NotFound)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("Instance is gone")]
 pub struct InstanceGone;
+
+impl ErrorExtensions for InstanceGone {
+    fn extend(self) -> async_graphql::Error {
+        with_code(self.to_string(), Code::NotFound)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Only administrators may do this")]
+pub struct AdminOnly;
+
+impl ErrorExtensions for AdminOnly {
+    fn extend(self) -> async_graphql::Error {
+        with_code(self.to_string(), Code::Forbidden)
+    }
+}
+
+fn with_code(message: String, code: Code) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, ext| ext.set("code", code.as_str()))
+}
+
+/// Classify an error returned by a provider.
+fn classify(err: &anyhow::Error) -> (Code, Option<&ProviderErrorDetails>) {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<ProviderError>() {
+            return (err.kind.into(), err.details.as_ref());
+        }
+        if cause.is::<IdParsingError>() {
+            return (Code::InvalidId, None);
+        }
+    }
+    (Code::Internal, None)
+}
+
+/// Convert an error returned by a provider into a GraphQL error.
+///
+/// The full error is logged under a correlation id that is also sent to the
+/// client, while the response itself only carries the code and the details
+/// reported by the provider.
+pub fn provider(err: &anyhow::Error) -> async_graphql::Error {
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    let (code, details) = classify(err);
+
+    if code == Code::Internal {
+        error!(%correlation_id, code = code.as_str(), "{:#}", err);
+    } else {
+        warn!(%correlation_id, code = code.as_str(), "{:#}", err);
+    }
+
+    async_graphql::Error::new(code.message()).extend_with(|_, ext| {
+        ext.set("code", code.as_str());
+        ext.set("correlationId", correlation_id);
+        if let Some(details) = details {
+            ext.set("provider", details_to_value(details));
+        }
+    })
+}
+
+/// Convert a failed reload into a GraphQL error; as with provider errors,
+/// the details are only logged.
+pub fn reload(err: &reload::Error) -> async_graphql::Error {
+    let correlation_id = uuid::Uuid::new_v4().to_string();
+    error!(%correlation_id, "{}", err);
+
+    async_graphql::Error::new("Unable to reload the providers").extend_with(|_, ext| {
+        ext.set("code", Code::Internal.as_str());
+        ext.set("correlationId", correlation_id);
+    })
+}
+
+fn details_to_value(details: &ProviderErrorDetails) -> Value {
+    let mut map = IndexMap::new();
+    if let Some(status_code) = details.status_code {
+        map.insert(Name::new("status"), Value::from(status_code));
+    }
+    if let Some(code) = &details.code {
+        map.insert(Name::new("code"), Value::from(code.as_str()));
+    }
+    if let Some(message) = &details.message {
+        map.insert(Name::new("message"), Value::from(message.as_str()));
+    }
+    if let Some(request_id) = &details.request_id {
+        map.insert(Name::new("requestId"), Value::from(request_id.as_str()));
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_errors() {
+        let err = anyhow::Error::new(ProviderError {
+            kind: ErrorKind::RateLimited,
+            details: None,
+            source: "too many requests".into(),
+        });
+        assert_eq!(classify(&err).0, Code::RateLimited);
+
+        let err = anyhow::Error::new(IdParsingError);
+        assert_eq!(classify(&err).0, Code::InvalidId);
+
+        let err = anyhow::anyhow!("something internal");
+        assert_eq!(classify(&err).0, Code::Internal);
+    }
+}
@@ -5,12 +5,41 @@ use std::{
 
 use async_graphql::dataloader::Loader;
 
-use crate::core::{Core, Id, ProviderKey};
+use crate::core::{Core, Id, Provider, ProviderKey};
 
 use super::model::Instance;
 
 pub struct InstanceLoader {
     pub core: Arc<Core>,
+    /// Above this many IDs per provider a batch is served by a single `list`
+    /// instead of individual `get` calls, unless the provider has an
+    /// efficient `get_many`.
+    pub max_gets_per_batch: usize,
+}
+
+impl InstanceLoader {
+    async fn load_from_provider(
+        &self,
+        provider: &dyn Provider,
+        ids: &BTreeSet<&Id>,
+    ) -> Result<HashMap<Id, crate::core::Instance>, anyhow::Error> {
+        let capabilities = provider.capabilities();
+
+        let use_get_many = capabilities.efficient_get_many
+            || (capabilities.efficient_get && ids.len() <= self.max_gets_per_batch);
+        if use_get_many {
+            let ids: Vec<Id> = ids.iter().map(|&id| id.clone()).collect();
+            return provider.get_many(&ids).await;
+        }
+
+        let instances = provider.list().await?;
+        let instances = instances
+            .into_iter()
+            .filter(|instance| ids.contains(&instance.id))
+            .map(|instance| (instance.id.clone(), instance))
+            .collect();
+        Ok(instances)
+    }
 }
 
 #[async_trait::async_trait]
@@ -39,12 +68,12 @@ impl Loader<(ProviderKey, Id)> for InstanceLoader {
                 continue;
             };
 
-            let instances = provider.list().await.map_err(Arc::new)?;
-            for instance in instances
-                .into_iter()
-                .filter(|instance| ids.contains(&instance.id))
-            {
-                let key = (provider_key.clone(), instance.id.clone());
+            let instances = self
+                .load_from_provider(provider.as_ref(), &ids)
+                .await
+                .map_err(Arc::new)?;
+            for (id, instance) in instances {
+                let key = (provider_key.clone(), id);
                 all_instances.insert(key, Instance::from(instance));
             }
         }
@@ -52,3 +81,150 @@ impl Loader<(ProviderKey, Id)> for InstanceLoader {
         Ok(all_instances)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::core::{Capabilities, IdRef, State};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingProvider {
+        lists: AtomicUsize,
+        gets: AtomicUsize,
+    }
+
+    fn instance(id: &IdRef) -> crate::core::Instance {
+        crate::core::Instance {
+            id: id.to_owned(),
+            display_name: id.to_owned(),
+            resource_type: "test".to_owned(),
+            state: State::On,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for CountingProvider {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                efficient_get: true,
+                efficient_get_many: false,
+                max_concurrent_gets: 2,
+            }
+        }
+
+        async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+            self.lists.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![instance("a"), instance("b"), instance("c")])
+        }
+
+        async fn get(&self, id: &IdRef) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            Ok((id != "missing").then(|| instance(id)))
+        }
+
+        async fn start(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn stop(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn picks_gets_or_list_by_batch_size() {
+        let loader = InstanceLoader {
+            core: Arc::new(Core::new(HashMap::new())),
+            max_gets_per_batch: 2,
+        };
+        let provider = CountingProvider::default();
+
+        let a = "a".to_owned();
+        let missing = "missing".to_owned();
+        let ids = [&a, &missing].into_iter().collect();
+        let instances = loader.load_from_provider(&provider, &ids).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(provider.gets.load(Ordering::SeqCst), 2);
+        assert_eq!(provider.lists.load(Ordering::SeqCst), 0);
+
+        let (b, c) = ("b".to_owned(), "c".to_owned());
+        let ids = [&a, &b, &c].into_iter().collect();
+        let instances = loader.load_from_provider(&provider, &ids).await.unwrap();
+        assert_eq!(instances.len(), 3);
+        assert_eq!(provider.lists.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_small_azure_batches_from_resource_manager() {
+        use axum::{
+            extract::Path,
+            routing::{get, post},
+            Json, Router,
+        };
+
+        use crate::azure::{testing, ListBackend};
+
+        let gets = Arc::new(AtomicUsize::new(0));
+        let queries = Arc::new(AtomicUsize::new(0));
+        let vm = |name: &str| {
+            serde_json::json!({
+                "id": format!(
+                    "/subscriptions/{}/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/{}",
+                    testing::SUBSCRIPTION_ID, name
+                ),
+                "name": name,
+                "powerState": "PowerState/running",
+                "properties": {"instanceView": {"statuses": [{"code": "PowerState/running"}]}},
+            })
+        };
+        let app = Router::new()
+            .route(
+                "/subscriptions/:subscription/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/:name",
+                get({
+                    let gets = Arc::clone(&gets);
+                    move |Path((_, name)): Path<(String, String)>| async move {
+                        gets.fetch_add(1, Ordering::SeqCst);
+                        Json(vm(&name))
+                    }
+                }),
+            )
+            .route(
+                "/providers/Microsoft.ResourceGraph/resources",
+                post({
+                    let queries = Arc::clone(&queries);
+                    move || async move {
+                        queries.fetch_add(1, Ordering::SeqCst);
+                        let rows: Vec<_> = (0..32).map(|i| vm(&format!("vm{}", i))).collect();
+                        Json(serde_json::json!({ "data": rows }))
+                    }
+                }),
+            );
+        let provider = testing::fake_provider(app, ListBackend::ResourceGraph);
+        let loader = InstanceLoader {
+            core: Arc::new(Core::new(HashMap::new())),
+            max_gets_per_batch: 2,
+        };
+
+        let few: Vec<Id> = (0..2).map(|i| format!("rg/vm{}", i)).collect();
+        let instances = loader
+            .load_from_provider(&provider, &few.iter().collect())
+            .await
+            .unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(gets.load(Ordering::SeqCst), 2);
+        assert_eq!(queries.load(Ordering::SeqCst), 0);
+
+        let many: Vec<Id> = (0..32).map(|i| format!("rg/vm{}", i)).collect();
+        let instances = loader
+            .load_from_provider(&provider, &many.iter().collect())
+            .await
+            .unwrap();
+        assert_eq!(instances.len(), 32);
+        assert_eq!(gets.load(Ordering::SeqCst), 2);
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+}
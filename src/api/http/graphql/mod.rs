@@ -12,3 +12,61 @@ pub type Schema = async_graphql::Schema<QueryRoot, MutationRoot, EmptySubscripti
 pub fn schema() -> SchemaBuilder<QueryRoot, MutationRoot, EmptySubscription> {
     async_graphql::Schema::build(QueryRoot, MutationRoot, EmptySubscription)
 }
+
+/// The token granting the administrative mutations, such as
+/// `reloadProviders`, as schema data; they are disabled without one.
+pub struct AdminToken(pub String);
+
+/// The bearer token of the `Authorization` header, as request data.
+pub struct BearerToken(pub String);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_graphql::Request;
+
+    use super::*;
+    use crate::core::{
+        reload::{ProviderFactory, Reloader},
+        Core, Providers,
+    };
+
+    struct NoProviders;
+
+    #[async_trait::async_trait]
+    impl ProviderFactory for NoProviders {
+        async fn build(&self) -> Result<Providers, anyhow::Error> {
+            Ok(Providers::new())
+        }
+    }
+
+    /// Run `reloadProviders`, returning the error code if it failed.
+    async fn reload(admin_token: Option<&str>, bearer_token: Option<&str>) -> Option<String> {
+        let core = Arc::new(Core::new(Providers::new()));
+        let reloader = Arc::new(Reloader::new(Arc::clone(&core), Arc::new(NoProviders)));
+        let mut schema = schema().data(core).data(reloader);
+        if let Some(token) = admin_token {
+            schema = schema.data(AdminToken(token.to_owned()));
+        }
+        let mut req = Request::new("mutation { reloadProviders { key } }");
+        if let Some(token) = bearer_token {
+            req = req.data(BearerToken(token.to_owned()));
+        }
+        let res = schema.finish().execute(req).await;
+        let res = serde_json::to_value(res).unwrap();
+        res["errors"][0]["extensions"]["code"]
+            .as_str()
+            .map(ToOwned::to_owned)
+    }
+
+    #[tokio::test]
+    async fn reloads_providers_only_for_admins() {
+        let forbidden = Some("FORBIDDEN".to_owned());
+        assert_eq!(reload(None, None).await, forbidden);
+        assert_eq!(reload(None, Some("")).await, forbidden);
+        assert_eq!(reload(Some("s3cret"), None).await, forbidden);
+        assert_eq!(reload(Some("s3cret"), Some("s3cre")).await, forbidden);
+        assert_eq!(reload(Some("s3cret"), Some("s3cret")).await, None);
+    }
+}
@@ -1,6 +1,11 @@
-use async_graphql::{ComplexObject, Context, Enum, Object, Result, SimpleObject, ID};
+use async_graphql::{
+    ComplexObject, Context, Enum, ErrorExtensions, Object, Result, SimpleObject, ID,
+};
 
-use super::{error, util::load_core};
+use super::{
+    error,
+    util::{load_core, load_instance_loader, load_reloader, require_admin},
+};
 
 #[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
 #[graphql(remote = "crate::core::State")]
@@ -11,6 +16,14 @@ pub enum State {
     Other,
 }
 
+#[derive(Debug, Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "crate::core::health::CircuitState")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
 #[derive(SimpleObject)]
 #[graphql(complex)]
 pub struct Provider {
@@ -19,28 +32,130 @@ pub struct Provider {
 
 #[ComplexObject]
 impl Provider {
-    async fn instances(&self, ctx: &Context<'_>) -> Result<Vec<Instance>> {
+    async fn instances(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default, desc = "Bypass the inventory cache.")] fresh: bool,
+    ) -> Result<Vec<Instance>> {
         let core = load_core(ctx);
-        let provider = core.provider(&self.key).ok_or(error::UnknownProvider)?;
-        let instances = provider.list().await?;
+        let provider = core
+            .provider(&self.key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
+        if fresh {
+            provider.invalidate(None);
+        }
+        let instances = provider.list().await.map_err(|err| error::provider(&err))?;
         let instances = instances.into_iter().map(Into::into).collect();
         Ok(instances)
     }
 
-    async fn instance(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Instance>> {
+    /// The health of the backend, unless it is not tracked.
+    async fn health(&self, ctx: &Context<'_>) -> Result<Option<ProviderHealth>> {
+        let core = load_core(ctx);
+        let provider = core
+            .provider(&self.key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
+        Ok(provider.health().map(Into::into))
+    }
+
+    /// How calls to the backend have been queued, unless they are not
+    /// limited.
+    async fn queue(&self, ctx: &Context<'_>) -> Result<Option<ProviderQueue>> {
         let core = load_core(ctx);
-        let provider = core.provider(&self.key).ok_or(error::UnknownProvider)?;
-        let instance = provider.get(&id).await?;
-        let instance = instance.map(Into::into);
+        let provider = core
+            .provider(&self.key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
+        Ok(provider.queue_stats().map(Into::into))
+    }
+
+    async fn instance(
+        &self,
+        ctx: &Context<'_>,
+        id: ID,
+        #[graphql(default, desc = "Bypass the inventory cache.")] fresh: bool,
+    ) -> Result<Option<Instance>> {
+        let core = load_core(ctx);
+        let provider = core
+            .provider(&self.key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
+        if fresh {
+            provider.invalidate(Some(&id));
+        }
+        let instance = load_instance_loader(ctx)
+            .load_one((self.key.clone(), id.0))
+            .await
+            .map_err(|err| error::provider(&err))?;
         Ok(instance)
     }
 }
 
+#[derive(SimpleObject, Clone)]
+pub struct ProviderHealth {
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    /// Seconds since the last successful call or probe.
+    pub last_success_age: Option<f64>,
+    /// Seconds since the last failed call or probe.
+    pub last_failure_age: Option<f64>,
+    /// The error code of the last failure.
+    pub last_error: Option<String>,
+    /// Seconds the last call or probe took.
+    pub latency: Option<f64>,
+}
+
+impl From<crate::core::health::Health> for ProviderHealth {
+    fn from(val: crate::core::health::Health) -> Self {
+        Self {
+            circuit: val.circuit.into(),
+            consecutive_failures: val.consecutive_failures,
+            last_success_age: val.last_success.map(|at| at.elapsed().as_secs_f64()),
+            last_failure_age: val.last_failure.map(|(at, _)| at.elapsed().as_secs_f64()),
+            last_error: val
+                .last_failure
+                .map(|(_, kind)| error::Code::from(kind).as_str().to_owned()),
+            latency: val.latency.map(|latency| latency.as_secs_f64()),
+        }
+    }
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct ProviderQueue {
+    /// Calls waiting for their turn.
+    pub queued: usize,
+    pub in_flight: usize,
+    /// Calls that went through the queue.
+    pub calls: u64,
+    /// Average seconds the calls waited.
+    pub average_wait: f64,
+    /// Seconds the call that waited the longest did.
+    pub max_wait: f64,
+}
+
+impl From<crate::core::limit::QueueStats> for ProviderQueue {
+    fn from(val: crate::core::limit::QueueStats) -> Self {
+        let average_wait = match val.calls {
+            0 => 0.0,
+            calls => val.total_wait.as_secs_f64() / calls as f64,
+        };
+        Self {
+            queued: val.queued,
+            in_flight: val.in_flight,
+            calls: val.calls,
+            average_wait,
+            max_wait: val.max_wait.as_secs_f64(),
+        }
+    }
+}
+
 #[derive(SimpleObject, Clone)]
 pub struct Instance {
     pub id: ID,
     pub name: String,
+    /// The kind of resource, as named by the provider.
+    pub resource_type: String,
     pub state: State,
+    /// Seconds since the state was read from the provider.
+    pub cache_age: f64,
 }
 
 impl From<crate::core::Instance> for Instance {
@@ -48,7 +163,9 @@ impl From<crate::core::Instance> for Instance {
         Self {
             id: val.id.into(),
             name: val.display_name,
+            resource_type: val.resource_type,
             state: val.state.into(),
+            cache_age: val.observed_at.elapsed().as_secs_f64(),
         }
     }
 }
@@ -60,9 +177,8 @@ impl QueryRoot {
     async fn providers(&self, ctx: &Context<'_>) -> Result<Vec<Provider>> {
         let core = load_core(ctx);
         let providers = core
-            .providers
-            .keys()
-            .cloned()
+            .provider_keys()
+            .into_iter()
             .map(|key| Provider { key })
             .collect();
         Ok(providers)
@@ -88,15 +204,24 @@ impl MutationRoot {
         instance: ID,
     ) -> Result<Instance> {
         let core = load_core(ctx);
-        let provider = core.provider(&provider.0).ok_or(error::UnknownProvider)?;
+        let provider_key = provider.0;
+        let provider = core
+            .provider(&provider_key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
 
-        let id = instance.as_str();
+        let id = instance.0;
 
-        provider.start(id).await?;
+        provider
+            .start(&id)
+            .await
+            .map_err(|err| error::provider(&err))?;
 
-        let instance = provider.get(id).await?;
-        let instance = instance.ok_or(error::InstanceGone)?;
-        Ok(instance.into())
+        let instance = load_instance_loader(ctx)
+            .load_one((provider_key, id))
+            .await
+            .map_err(|err| error::provider(&err))?;
+        let instance = instance.ok_or_else(|| error::InstanceGone.extend())?;
+        Ok(instance)
     }
 
     async fn stop_instance(
@@ -106,14 +231,35 @@ impl MutationRoot {
         instance: ID,
     ) -> Result<Instance> {
         let core = load_core(ctx);
-        let provider = core.provider(&provider.0).ok_or(error::UnknownProvider)?;
+        let provider_key = provider.0;
+        let provider = core
+            .provider(&provider_key)
+            .ok_or_else(|| error::UnknownProvider.extend())?;
+
+        let id = instance.0;
 
-        let id = instance.as_str();
+        provider
+            .stop(&id)
+            .await
+            .map_err(|err| error::provider(&err))?;
 
-        provider.stop(id).await?;
+        let instance = load_instance_loader(ctx)
+            .load_one((provider_key, id))
+            .await
+            .map_err(|err| error::provider(&err))?;
+        let instance = instance.ok_or_else(|| error::InstanceGone.extend())?;
+        Ok(instance)
+    }
 
-        let instance = provider.get(id).await?;
-        let instance = instance.ok_or(error::InstanceGone)?;
-        Ok(instance.into())
+    /// Rebuild the providers from the current configuration, as on `SIGHUP`.
+    /// In-flight requests complete against the previous providers, which are
+    /// kept if the configuration is invalid. Requires the admin token.
+    async fn reload_providers(&self, ctx: &Context<'_>) -> Result<Vec<Provider>> {
+        require_admin(ctx)?;
+        let keys = load_reloader(ctx)
+            .reload()
+            .await
+            .map_err(|err| error::reload(&err))?;
+        Ok(keys.into_iter().map(|key| Provider { key }).collect())
     }
 }
@@ -1,9 +1,38 @@
 use std::sync::Arc;
 
-use async_graphql::Context;
+use async_graphql::{dataloader::DataLoader, Context, ErrorExtensions};
 
-use crate::core::Core;
+use crate::core::{reload::Reloader, Core};
+
+use super::{error, loader::InstanceLoader, AdminToken, BearerToken};
 
 pub fn load_core<'a>(ctx: &'a Context<'_>) -> &'a Arc<Core> {
     ctx.data_unchecked::<Arc<Core>>()
 }
+
+pub fn load_instance_loader<'a>(ctx: &'a Context<'_>) -> &'a DataLoader<InstanceLoader> {
+    ctx.data_unchecked::<DataLoader<InstanceLoader>>()
+}
+
+pub fn load_reloader<'a>(ctx: &'a Context<'_>) -> &'a Arc<Reloader> {
+    ctx.data_unchecked::<Arc<Reloader>>()
+}
+
+/// Fail unless the request bears the admin token.
+pub fn require_admin(ctx: &Context<'_>) -> async_graphql::Result<()> {
+    let admin = ctx.data_opt::<AdminToken>();
+    let bearer = ctx.data_opt::<BearerToken>();
+    match (admin, bearer) {
+        (Some(admin), Some(bearer)) if constant_time_eq(&admin.0, &bearer.0) => Ok(()),
+        _ => Err(error::AdminOnly.extend()),
+    }
+}
+
+/// Compare secrets without telling how much of them matched through timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,121 @@
+//! Credentials of the instance profile, from the instance metadata service
+//! (IMDSv2).
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use super::Credentials;
+
+const TOKEN_TTL_HEADER: &str = "x-aws-ec2-metadata-token-ttl-seconds";
+const TOKEN_HEADER: &str = "x-aws-ec2-metadata-token";
+const TOKEN_TTL_SECS: u64 = 21600;
+
+/// Credentials are renewed this long before they expire.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("no role attached to the instance")]
+    NoRole,
+    #[error("unable to parse the expiration {0:?}")]
+    Expiration(String),
+}
+
+/// `AWS_EC2_METADATA_SERVICE_ENDPOINT`, or the link-local address.
+pub fn default_endpoint(var: impl Fn(&str) -> Option<String>) -> String {
+    var("AWS_EC2_METADATA_SERVICE_ENDPOINT").unwrap_or_else(|| "http://169.254.169.254".to_owned())
+}
+
+pub struct InstanceMetadata {
+    client: reqwest::Client,
+    endpoint: String,
+    cached: Mutex<Option<Credentials>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SecurityCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: String,
+    expiration: String,
+}
+
+impl InstanceMetadata {
+    pub fn new(client: reqwest::Client, endpoint: String) -> Self {
+        Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            cached: Mutex::const_new(None),
+        }
+    }
+
+    async fn get_text(&self, token: &str, path: &str) -> Result<String, Error> {
+        let text = self
+            .client
+            .get(format!("{}{}", self.endpoint, path))
+            .header(TOKEN_HEADER, token)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(text)
+    }
+
+    async fn fetch(&self) -> Result<Credentials, Error> {
+        let token = self
+            .client
+            .put(format!("{}/latest/api/token", self.endpoint))
+            .header(TOKEN_TTL_HEADER, TOKEN_TTL_SECS)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let roles = self
+            .get_text(&token, "/latest/meta-data/iam/security-credentials/")
+            .await?;
+        let role = roles.lines().next().ok_or(Error::NoRole)?;
+
+        let path = format!("/latest/meta-data/iam/security-credentials/{}", role);
+        let credentials: SecurityCredentials =
+            serde_json::from_str(&self.get_text(&token, &path).await?)?;
+
+        let expiration = chrono::DateTime::parse_from_rfc3339(&credentials.expiration)
+            .map_err(|_| Error::Expiration(credentials.expiration.clone()))?;
+        let valid_for = (expiration.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default();
+
+        Ok(Credentials {
+            access_key_id: credentials.access_key_id,
+            secret_access_key: credentials.secret_access_key,
+            session_token: Some(credentials.token),
+            expires_at: Some(Instant::now() + valid_for),
+        })
+    }
+
+    pub async fn get_credentials(&self) -> Result<Credentials, Error> {
+        let mut cached = self.cached.lock().await;
+        if let Some(credentials) = &*cached {
+            let fresh = credentials
+                .expires_at
+                .is_none_or(|expires_at| expires_at > Instant::now() + EXPIRY_MARGIN);
+            if fresh {
+                return Ok(credentials.clone());
+            }
+        }
+
+        let credentials = self.fetch().await?;
+        cached.replace(credentials.clone());
+        Ok(credentials)
+    }
+}
@@ -0,0 +1,143 @@
+//! Credentials, resolved like the AWS CLI does: from the environment, then
+//! from the shared credentials file, then from the instance metadata.
+//!
+//! The environment is read through a `var` function rather than directly,
+//! for the caller to choose where the variables come from.
+
+use std::{convert::Infallible, time::Instant};
+
+pub mod imds;
+pub mod profile;
+
+#[async_trait::async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    type Error: Send + Sync;
+
+    async fn get_credentials(&self) -> Result<Credentials, Self::Error>;
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Set for temporary credentials.
+    pub session_token: Option<String>,
+    /// When temporary credentials expire.
+    pub expires_at: Option<Instant>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    /// Read `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        Some(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: var("AWS_SESSION_TOKEN"),
+            expires_at: None,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialsProvider for Credentials {
+    type Error = Infallible;
+
+    async fn get_credentials(&self) -> Result<Credentials, Self::Error> {
+        Ok(self.clone())
+    }
+}
+
+/// The first credentials found in the usual places.
+pub enum DefaultCredentials {
+    Static(Credentials),
+    InstanceMetadata(imds::InstanceMetadata),
+}
+
+impl DefaultCredentials {
+    /// Look for credentials in the environment and the shared credentials
+    /// file, falling back to the instance metadata service, which is only
+    /// queried on use.
+    pub fn resolve(
+        client: reqwest::Client,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, profile::Error> {
+        if let Some(credentials) = Credentials::from_vars(&var) {
+            return Ok(Self::Static(credentials));
+        }
+        if let Some(credentials) = profile::load_default(&var)? {
+            return Ok(Self::Static(credentials));
+        }
+        Ok(Self::InstanceMetadata(imds::InstanceMetadata::new(
+            client,
+            imds::default_endpoint(&var),
+        )))
+    }
+}
+
+#[async_trait::async_trait]
+impl CredentialsProvider for DefaultCredentials {
+    type Error = imds::Error;
+
+    async fn get_credentials(&self) -> Result<Credentials, Self::Error> {
+        match self {
+            Self::Static(credentials) => Ok(credentials.clone()),
+            Self::InstanceMetadata(imds) => imds.get_credentials().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn resolve(vars: &HashMap<&str, String>) -> Result<DefaultCredentials, profile::Error> {
+        DefaultCredentials::resolve(reqwest::Client::new(), |key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn resolves_from_the_given_variables_only() {
+        let path = std::env::temp_dir().join(format!("aws-credentials-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "[ci]\naws_access_key_id = AKIDPROFILE\naws_secret_access_key = secret\n",
+        )
+        .unwrap();
+
+        let mut vars = HashMap::from([
+            ("AWS_SHARED_CREDENTIALS_FILE", path.display().to_string()),
+            ("AWS_PROFILE", "ci".to_owned()),
+        ]);
+        match resolve(&vars).unwrap() {
+            DefaultCredentials::Static(credentials) => {
+                assert_eq!(credentials.access_key_id, "AKIDPROFILE")
+            }
+            DefaultCredentials::InstanceMetadata(_) => panic!("the profile was not read"),
+        }
+
+        vars.insert("AWS_ACCESS_KEY_ID", "AKIDENV".to_owned());
+        vars.insert("AWS_SECRET_ACCESS_KEY", "secret".to_owned());
+        match resolve(&vars).unwrap() {
+            DefaultCredentials::Static(credentials) => {
+                assert_eq!(credentials.access_key_id, "AKIDENV")
+            }
+            DefaultCredentials::InstanceMetadata(_) => panic!("the variables were not read"),
+        }
+
+        vars.insert("AWS_PROFILE", "missing".to_owned());
+        vars.remove("AWS_ACCESS_KEY_ID");
+        assert!(matches!(resolve(&vars), Err(profile::Error::Incomplete(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+//! Credentials from the shared credentials file, `~/.aws/credentials`.
+
+use std::path::{Path, PathBuf};
+
+use super::Credentials;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("profile {0:?} has no credentials")]
+    Incomplete(String),
+}
+
+/// The file named by `AWS_SHARED_CREDENTIALS_FILE` (or
+/// `~/.aws/credentials`).
+pub fn default_path(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    match var("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            let home = var("HOME")?;
+            Some(Path::new(&home).join(".aws").join("credentials"))
+        }
+    }
+}
+
+/// Load the profile named by `AWS_PROFILE` (or `default`) from the
+/// [`default_path`], if the file exists.
+pub fn load_default(var: impl Fn(&str) -> Option<String>) -> Result<Option<Credentials>, Error> {
+    let path = match default_path(&var) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let explicit_profile = var("AWS_PROFILE");
+    let profile = explicit_profile.as_deref().unwrap_or("default");
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => return Err(Error::Io { path, source }),
+    };
+
+    match parse(&contents, profile) {
+        Some(credentials) => Ok(Some(credentials)),
+        // Only insist when the profile was asked for.
+        None if explicit_profile.is_some() => Err(Error::Incomplete(profile.to_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Find the credentials of a profile in the INI-like file format.
+pub fn parse(contents: &str, profile: &str) -> Option<Credentials> {
+    let mut in_profile = false;
+    let mut access_key_id = None;
+    let mut secret_access_key = None;
+    let mut session_token = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_profile = section.trim() == profile;
+            continue;
+        }
+        if !in_profile {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(value.trim().to_owned());
+            match key.trim() {
+                "aws_access_key_id" => access_key_id = value,
+                "aws_secret_access_key" => secret_access_key = value,
+                "aws_session_token" => session_token = value,
+                _ => {}
+            }
+        }
+    }
+
+    Some(Credentials {
+        access_key_id: access_key_id?,
+        secret_access_key: secret_access_key?,
+        session_token,
+        expires_at: None,
+    })
+}
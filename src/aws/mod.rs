@@ -0,0 +1,473 @@
+//! AWS EC2 provider implementation.
+//!
+//! Our IDs are the EC2 instance IDs (`i-0123456789abcdef0`); a provider
+//! manages a single region.
+
+use std::collections::HashMap;
+
+use reqwest::Method;
+
+use self::{
+    credentials::CredentialsProvider,
+    utils::{check_status, ServerError},
+};
+
+pub mod credentials;
+mod model;
+pub mod sigv4;
+mod utils;
+
+const API_VERSION: &str = "2016-11-15";
+const SERVICE: &str = "ec2";
+const RESOURCE_TYPE: &str = "AWS::EC2::Instance";
+
+/// The largest page `DescribeInstances` returns.
+const MAX_RESULTS: usize = 1000;
+
+/// The most values a `DescribeInstances` filter accepts.
+const MAX_FILTER_VALUES: usize = 200;
+
+pub struct Provider<Credentials> {
+    pub client: reqwest::Client,
+    pub region: String,
+    /// The EC2 endpoint, that of the region or of a local stand-in such as
+    /// LocalStack.
+    pub endpoint: String,
+    /// Hibernate instances instead of stopping them; they must have been
+    /// launched with hibernation enabled.
+    pub hibernate: bool,
+    pub credentials_provider: Credentials,
+}
+
+/// The public endpoint of a region.
+pub fn default_endpoint(region: &str) -> String {
+    format!("https://ec2.{}.amazonaws.com", region)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error<CredentialsError> {
+    #[error("credentials: {0}")]
+    Credentials(#[source] CredentialsError),
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("xml: {0}")]
+    Xml(#[from] quick_xml::DeError),
+}
+
+impl<CredentialsError> Error<CredentialsError>
+where
+    CredentialsError: std::error::Error + Send + Sync + 'static,
+{
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Credentials(_) => crate::core::ErrorKind::Unauthorized,
+            Self::Reqwest(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => err.kind(),
+            Self::Xml(_) => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(err.into()),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+fn parse_id(id: &crate::core::IdRef) -> Result<&str, crate::core::IdParsingError> {
+    match id.strip_prefix("i-") {
+        Some(suffix) if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            Ok(id)
+        }
+        _ => Err(crate::core::IdParsingError),
+    }
+}
+
+impl<Credentials> Provider<Credentials>
+where
+    Credentials: CredentialsProvider,
+{
+    /// Call an EC2 action with the given parameters, returning the XML body.
+    async fn call(
+        &self,
+        action: &str,
+        params: &[(String, String)],
+    ) -> Result<String, Error<Credentials::Error>> {
+        let credentials = self
+            .credentials_provider
+            .get_credentials()
+            .await
+            .map_err(Error::Credentials)?;
+
+        let mut form = vec![
+            ("Action".to_owned(), action.to_owned()),
+            ("Version".to_owned(), API_VERSION.to_owned()),
+        ];
+        form.extend_from_slice(params);
+        let body = serde_urlencoded::to_string(&form).expect("form fields are plain strings");
+
+        let mut request = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/", self.endpoint.trim_end_matches('/')),
+            )
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .body(body)
+            .build()?;
+        sigv4::sign(
+            &mut request,
+            &credentials,
+            &self.region,
+            SERVICE,
+            chrono::Utc::now(),
+        );
+
+        let res = self.client.execute(request).await?;
+        let res = check_status(res).await?;
+        Ok(res.text().await?)
+    }
+
+    /// Describe the instances, all of them or only those with the given IDs,
+    /// following the pages.
+    async fn describe_instances(
+        &self,
+        ids: Option<&[&str]>,
+    ) -> Result<Vec<model::Instance>, Error<Credentials::Error>> {
+        let mut params = vec![("MaxResults".to_owned(), MAX_RESULTS.to_string())];
+        // Unlike `InstanceId.N`, a filter does not fail on unknown IDs.
+        if let Some(ids) = ids {
+            params.push(("Filter.1.Name".to_owned(), "instance-id".to_owned()));
+            for (idx, id) in ids.iter().enumerate() {
+                params.push((format!("Filter.1.Value.{}", idx + 1), (*id).to_owned()));
+            }
+        }
+
+        let mut instances = Vec::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let mut page_params = params.clone();
+            if let Some(token) = next_token {
+                page_params.push(("NextToken".to_owned(), token));
+            }
+            let body = self.call("DescribeInstances", &page_params).await?;
+            let page: model::DescribeInstancesResponse = quick_xml::de::from_str(&body)?;
+            instances.extend(
+                page.reservation_set
+                    .item
+                    .into_iter()
+                    .flat_map(|reservation| reservation.instances_set.item),
+            );
+            next_token = page.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                return Ok(instances);
+            }
+        }
+    }
+
+    async fn instance_action(
+        &self,
+        action: &str,
+        id: &str,
+        extra_params: &[(String, String)],
+    ) -> Result<(), Error<Credentials::Error>> {
+        let mut params = vec![("InstanceId.1".to_owned(), id.to_owned())];
+        params.extend_from_slice(extra_params);
+        self.call(action, &params).await?;
+        Ok(())
+    }
+
+    fn model_to_instance(instance: model::Instance) -> crate::core::Instance {
+        let display_name = instance
+            .tag_set
+            .item
+            .into_iter()
+            .find(|tag| tag.key == model::TAG_NAME && !tag.value.is_empty())
+            .map(|tag| tag.value)
+            .unwrap_or_else(|| instance.instance_id.clone());
+        crate::core::Instance {
+            id: instance.instance_id,
+            display_name,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state: Self::detect_state(&instance.instance_state.name),
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    fn detect_state(name: &str) -> crate::core::State {
+        match name {
+            model::STATE_RUNNING => crate::core::State::On,
+            model::STATE_STOPPED => crate::core::State::Off,
+            model::STATE_PENDING | model::STATE_STOPPING | model::STATE_SHUTTING_DOWN => {
+                crate::core::State::InProgress
+            }
+            // Terminated instances linger for a while but cannot be started.
+            _ => crate::core::State::Other,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Credentials> crate::core::Provider for Provider<Credentials>
+where
+    Credentials: CredentialsProvider,
+    <Credentials as CredentialsProvider>::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: true,
+            max_concurrent_gets: 8,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let instances = self
+            .describe_instances(None)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(instances.into_iter().map(Self::model_to_instance).collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        // The smallest page EC2 accepts, without following the others.
+        let params = [("MaxResults".to_owned(), "5".to_owned())];
+        self.call("DescribeInstances", &params)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let id = parse_id(id)?;
+        let instances = self
+            .describe_instances(Some(&[id]))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(instances.into_iter().next().map(Self::model_to_instance))
+    }
+
+    async fn get_many(
+        &self,
+        ids: &[crate::core::Id],
+    ) -> Result<HashMap<crate::core::Id, crate::core::Instance>, anyhow::Error> {
+        let ids = ids
+            .iter()
+            .map(|id| parse_id(id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut instances = HashMap::with_capacity(ids.len());
+        for chunk in ids.chunks(MAX_FILTER_VALUES) {
+            let found = self
+                .describe_instances(Some(chunk))
+                .await
+                .map_err(Error::into_core)?;
+            instances.extend(
+                found
+                    .into_iter()
+                    .map(Self::model_to_instance)
+                    .map(|instance| (instance.id.clone(), instance)),
+            );
+        }
+        Ok(instances)
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = parse_id(id)?;
+        self.instance_action("StartInstances", id, &[])
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = parse_id(id)?;
+        let params = if self.hibernate {
+            vec![("Hibernate".to_owned(), "true".to_owned())]
+        } else {
+            Vec::new()
+        };
+        self.instance_action("StopInstances", id, &params)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::Form, http::StatusCode, routing::post, Router};
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    type Calls = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+    fn error(status: StatusCode, code: &str) -> (StatusCode, String) {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Response><Errors><Error><Code>{}</Code><Message>Something about {}</Message></Error></Errors><RequestID>ea966190-f9aa-478e-9ede-example</RequestID></Response>"#,
+            code, code
+        );
+        (status, body)
+    }
+
+    /// An EC2 endpoint knowing of `i-0123456789abcdef0` only, and throttling
+    /// listings.
+    fn fake_provider(calls: Calls) -> Provider<credentials::Credentials> {
+        let app = Router::new().route(
+            "/",
+            post(move |Form(params): Form<HashMap<String, String>>| async move {
+                calls.lock().unwrap().push(params.clone());
+                let action = params["Action"].as_str();
+                match params.get("InstanceId.1").map(String::as_str) {
+                    _ if action == "DescribeInstances" => {
+                        error(StatusCode::SERVICE_UNAVAILABLE, "RequestLimitExceeded")
+                    }
+                    Some("i-0123456789abcdef0") => (
+                        StatusCode::OK,
+                        format!(
+                            "<{0}Response><requestId>59dbff89-35bd-4eac-99ed-example</requestId></{0}Response>",
+                            action
+                        ),
+                    ),
+                    Some("i-0fedcba9876543210") => {
+                        error(StatusCode::FORBIDDEN, "UnauthorizedOperation")
+                    }
+                    _ => error(StatusCode::BAD_REQUEST, "InvalidInstanceID.NotFound"),
+                }
+            }),
+        );
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            client: reqwest::Client::new(),
+            region: "eu-west-1".to_owned(),
+            endpoint: format!("http://{}", addr),
+            hibernate: true,
+            credentials_provider: credentials::Credentials {
+                access_key_id: "AKIDEXAMPLE".to_owned(),
+                secret_access_key: "secret".to_owned(),
+                session_token: None,
+                expires_at: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn starts_and_hibernates_instances_on_fake_api() {
+        let calls = Calls::default();
+        let provider = fake_provider(Arc::clone(&calls));
+
+        provider.start("i-0123456789abcdef0").await.unwrap();
+        provider.stop("i-0123456789abcdef0").await.unwrap();
+        let calls = calls.lock().unwrap();
+        let actions: Vec<_> = calls
+            .iter()
+            .map(|params| params["Action"].as_str())
+            .collect();
+        assert_eq!(actions, ["StartInstances", "StopInstances"]);
+        assert_eq!(calls[0].get("Hibernate"), None);
+        assert_eq!(calls[1]["Hibernate"], "true");
+        assert_eq!(calls[1]["Version"], API_VERSION);
+    }
+
+    #[tokio::test]
+    async fn maps_ec2_error_codes() {
+        let provider = fake_provider(Calls::default());
+
+        let err = provider
+            .stop("i-0aaaaaaaaaaaaaaaa")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+        let details = err.details.unwrap();
+        assert_eq!(details.code.as_deref(), Some("InvalidInstanceID.NotFound"));
+        assert_eq!(
+            details.request_id.as_deref(),
+            Some("ea966190-f9aa-478e-9ede-example")
+        );
+        let err = provider
+            .start("i-0fedcba9876543210")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Forbidden);
+        let err = provider
+            .list()
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::RateLimited);
+        assert!(provider.start("web").await.is_err());
+    }
+
+    #[test]
+    fn parses_describe_instances_response() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <requestId>8f7724cf-496f-496e-8fe3-example</requestId>
+    <reservationSet>
+        <item>
+            <reservationId>r-1234567890abcdef0</reservationId>
+            <instancesSet>
+                <item>
+                    <instanceId>i-1234567890abcdef0</instanceId>
+                    <instanceState><code>80</code><name>stopped</name></instanceState>
+                    <tagSet>
+                        <item><key>Name</key><value>build-01</value></item>
+                    </tagSet>
+                </item>
+                <item>
+                    <instanceId>i-0598c7d356eba48d7</instanceId>
+                    <instanceState><code>16</code><name>running</name></instanceState>
+                    <tagSet/>
+                </item>
+            </instancesSet>
+        </item>
+    </reservationSet>
+    <nextToken>page2</nextToken>
+</DescribeInstancesResponse>"#;
+        let page: model::DescribeInstancesResponse = quick_xml::de::from_str(body).unwrap();
+        assert_eq!(page.next_token.as_deref(), Some("page2"));
+
+        let instances: Vec<_> = page
+            .reservation_set
+            .item
+            .into_iter()
+            .flat_map(|reservation| reservation.instances_set.item)
+            .map(Provider::<credentials::Credentials>::model_to_instance)
+            .collect();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].display_name, "build-01");
+        assert_eq!(instances[0].state, crate::core::State::Off);
+        assert_eq!(instances[1].display_name, "i-0598c7d356eba48d7");
+        assert_eq!(instances[1].state, crate::core::State::On);
+    }
+}
@@ -0,0 +1,90 @@
+//! EC2 query API models, deserialized from XML.
+
+use serde::Deserialize;
+
+// Like the Azure models, these only declare the fields we use.
+
+/// A list of items, as EC2 wraps them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ItemSet<T> {
+    #[serde(default = "Vec::new")]
+    pub item: Vec<T>,
+}
+
+impl<T> Default for ItemSet<T> {
+    fn default() -> Self {
+        Self { item: Vec::new() }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DescribeInstancesResponse {
+    #[serde(default)]
+    pub reservation_set: ItemSet<Reservation>,
+    /// The token to request the next page, absent on the last one.
+    pub next_token: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reservation {
+    #[serde(default)]
+    pub instances_set: ItemSet<Instance>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    pub instance_id: String,
+    #[serde(default)]
+    pub instance_state: InstanceState,
+    #[serde(default)]
+    pub tag_set: ItemSet<Tag>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceState {
+    /// The state name, e.g. `running`.
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub key: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+pub const TAG_NAME: &str = "Name";
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_RUNNING: &str = "running";
+pub const STATE_SHUTTING_DOWN: &str = "shutting-down";
+pub const STATE_STOPPING: &str = "stopping";
+pub const STATE_STOPPED: &str = "stopped";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorResponse {
+    #[serde(default)]
+    pub errors: Errors,
+    #[serde(rename = "RequestID")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Errors {
+    #[serde(default)]
+    pub error: Vec<ErrorDetail>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: Option<String>,
+}
@@ -0,0 +1,192 @@
+//! Signature Version 4 request signing.
+//!
+//! See <https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html>.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use sha2::{Digest, Sha256};
+
+use super::credentials::Credentials;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const DATE_HEADER: &str = "x-amz-date";
+const SECURITY_TOKEN_HEADER: &str = "x-amz-security-token";
+
+/// Sign a request in place, adding the date, session token and
+/// authorization headers.
+///
+/// All the headers already set on the request are signed, along with the
+/// host, so they must not be changed afterwards.
+pub fn sign(
+    request: &mut reqwest::Request,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let headers = request.headers_mut();
+    headers.insert(DATE_HEADER, header_value(&amz_date));
+    if let Some(session_token) = &credentials.session_token {
+        headers.insert(SECURITY_TOKEN_HEADER, header_value(session_token));
+    }
+
+    let mut canonical_headers: Vec<(String, String)> = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            (name.as_str().to_owned(), value.trim().to_owned())
+        })
+        .collect();
+    canonical_headers.push(("host".to_owned(), host(request.url())));
+    canonical_headers.sort();
+
+    let signed_headers = canonical_headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = canonical_headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+
+    let payload = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let canonical_request = format!(
+        "{method}\n{uri}\n{query}\n{headers}\n{signed_headers}\n{payload_hash}",
+        method = request.method(),
+        uri = canonical_uri(request.url()),
+        query = canonical_query(request.url()),
+        headers = canonical_headers,
+        signed_headers = signed_headers,
+        payload_hash = hex::encode(Sha256::digest(payload)),
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes())),
+    );
+
+    let key = signing_key(&credentials.secret_access_key, &date, region, service);
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+    );
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, header_value(&authorization));
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).expect("signing headers are valid header values")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    hmac(&key, b"aws4_request")
+}
+
+/// The host as sent in the `Host` header, with the port unless it is the
+/// default one.
+fn host(url: &reqwest::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    }
+}
+
+fn canonical_uri(url: &reqwest::Url) -> String {
+    // The path is already percent-encoded by the URL parser.
+    match url.path() {
+        "" => "/".to_owned(),
+        path => path.to_owned(),
+    }
+}
+
+fn canonical_query(url: &reqwest::Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| (uri_encode(&key), uri_encode(&value)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// The example from the AWS documentation.
+    #[test]
+    fn signs_documentation_example() {
+        let client = reqwest::Client::new();
+        let mut request = client
+            .get("https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08")
+            .header(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .build()
+            .unwrap();
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+            expires_at: None,
+        };
+
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        sign(&mut request, &credentials, "us-east-1", "iam", now);
+
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use crate::core::ErrorKind;
+
+use super::model;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The request ID reported in the response body, if it could be parsed.
+    pub request_id: Option<String>,
+    /// The first error reported in the response body, if it could be parsed.
+    pub error: Option<model::ErrorDetail>,
+}
+
+impl ServerError {
+    /// EC2 answers most failures with a 400, so the error code says more
+    /// than the status code.
+    pub fn kind(&self) -> ErrorKind {
+        let code = self.error.as_ref().map(|error| error.code.as_str());
+        match code {
+            Some("AuthFailure") => ErrorKind::Unauthorized,
+            Some("UnauthorizedOperation") => ErrorKind::Forbidden,
+            Some("InvalidInstanceID.NotFound") => ErrorKind::NotFound,
+            Some("InvalidInstanceID.Malformed") => ErrorKind::InvalidId,
+            Some("IncorrectInstanceState" | "UnsupportedHibernationConfiguration") => {
+                ErrorKind::Conflict
+            }
+            Some("RequestLimitExceeded") => ErrorKind::RateLimited,
+            _ => ErrorKind::from_http_status(self.status_code),
+        }
+    }
+}
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let response = match res.text().await {
+        Ok(body) => quick_xml::de::from_str::<model::ErrorResponse>(&body).ok(),
+        Err(_) => None,
+    };
+    let (request_id, error) = match response {
+        Some(response) => (
+            response.request_id,
+            response.errors.error.into_iter().next(),
+        ),
+        None => (None, None),
+    };
+
+    Err(ServerError {
+        status_code: status.as_u16(),
+        request_id,
+        error,
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.error.as_ref().map(|error| error.code.clone()),
+            message: err.error.as_ref().and_then(|error| error.message.clone()),
+            request_id: err.request_id.clone(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! API versions used for each resource type.
+
+use std::collections::HashMap;
+
+pub const VIRTUAL_MACHINES: &str = "Microsoft.Compute/virtualMachines";
+pub const VIRTUAL_MACHINE_SCALE_SETS: &str = "Microsoft.Compute/virtualMachineScaleSets";
+pub const MANAGED_CLUSTERS: &str = "Microsoft.ContainerService/managedClusters";
+pub const POSTGRESQL_FLEXIBLE_SERVERS: &str = "Microsoft.DBforPostgreSQL/flexibleServers";
+pub const MYSQL_FLEXIBLE_SERVERS: &str = "Microsoft.DBforMySQL/flexibleServers";
+pub const SITES: &str = "Microsoft.Web/sites";
+pub const RESOURCE_PROVIDERS: &str = "Microsoft.Resources/providers";
+pub const RESOURCE_GRAPH: &str = "Microsoft.ResourceGraph/resources";
+
+/// The versions we were built and tested against.
+const DEFAULTS: &[(&str, &str)] = &[
+    (VIRTUAL_MACHINES, "2021-07-01"),
+    (VIRTUAL_MACHINE_SCALE_SETS, "2021-07-01"),
+    (MANAGED_CLUSTERS, "2021-08-01"),
+    (POSTGRESQL_FLEXIBLE_SERVERS, "2021-06-01"),
+    (MYSQL_FLEXIBLE_SERVERS, "2021-05-01"),
+    (SITES, "2021-02-01"),
+    (RESOURCE_PROVIDERS, "2021-04-01"),
+    (RESOURCE_GRAPH, "2021-03-01"),
+];
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unable to parse the API version override {0:?}")]
+pub struct ParseError(String);
+
+/// API versions keyed by resource type, falling back to our defaults.
+///
+/// Versions pinned by the user take precedence over the ones picked by
+/// negotiation, which only ever replaces our defaults. Resource types are
+/// matched case-insensitively, like ARM does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiVersions {
+    overrides: HashMap<String, String>,
+    negotiated: HashMap<String, String>,
+}
+
+impl ApiVersions {
+    /// Parse a comma-separated list of `resourceType=apiVersion` pairs.
+    pub fn parse(spec: &str) -> Result<Self, ParseError> {
+        let mut versions = Self::default();
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (resource_type, api_version) = item
+                .split_once('=')
+                .ok_or_else(|| ParseError(item.to_owned()))?;
+            versions.set(resource_type.trim(), api_version.trim());
+        }
+        Ok(versions)
+    }
+
+    pub fn get(&self, resource_type: &str) -> &str {
+        let key = resource_type.to_ascii_lowercase();
+        if let Some(version) = self
+            .overrides
+            .get(&key)
+            .or_else(|| self.negotiated.get(&key))
+        {
+            return version;
+        }
+        DEFAULTS
+            .iter()
+            .find(|(ty, _)| ty.eq_ignore_ascii_case(resource_type))
+            .map(|(_, version)| *version)
+            .unwrap_or_else(|| panic!("no default API version for {}", resource_type))
+    }
+
+    /// Pin the version of a resource type.
+    pub fn set(&mut self, resource_type: &str, api_version: &str) {
+        self.overrides
+            .insert(resource_type.to_ascii_lowercase(), api_version.to_owned());
+    }
+
+    /// Whether the version of a resource type was pinned with [`Self::set`].
+    pub fn is_pinned(&self, resource_type: &str) -> bool {
+        self.overrides
+            .contains_key(&resource_type.to_ascii_lowercase())
+    }
+
+    /// Use the version negotiated for a resource type, unless it is pinned.
+    pub fn set_negotiated(&mut self, resource_type: &str, api_version: &str) {
+        if !self.is_pinned(resource_type) {
+            self.negotiated
+                .insert(resource_type.to_ascii_lowercase(), api_version.to_owned());
+        }
+    }
+
+    /// Resource types we have a version for.
+    pub fn resource_types(&self) -> impl Iterator<Item = &str> {
+        DEFAULTS.iter().map(|(ty, _)| *ty)
+    }
+}
+
+/// Pick the version to use given the one we want and the ones the cloud
+/// supports: the wanted one if supported, otherwise the newest stable version
+/// older than it.
+pub fn negotiate<'a>(wanted: &'a str, supported: &'a [String]) -> Option<&'a str> {
+    if supported.iter().any(|version| version == wanted) {
+        return Some(wanted);
+    }
+    supported
+        .iter()
+        .map(String::as_str)
+        .filter(|version| !version.contains("preview") && *version < wanted)
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_overrides() {
+        let versions = ApiVersions::parse("microsoft.compute/VirtualMachines=2020-06-01").unwrap();
+        assert_eq!(versions.get(VIRTUAL_MACHINES), "2020-06-01");
+        assert_eq!(versions.get(RESOURCE_PROVIDERS), "2021-04-01");
+        assert!(ApiVersions::parse("garbage").is_err());
+    }
+
+    #[test]
+    fn negotiation_keeps_pinned_versions() {
+        let mut versions = ApiVersions::parse("Microsoft.Web/sites=2022-03-01").unwrap();
+        versions.set_negotiated(SITES, "2020-12-01");
+        versions.set_negotiated(VIRTUAL_MACHINES, "2020-12-01");
+        assert_eq!(versions.get(SITES), "2022-03-01");
+        assert_eq!(versions.get(VIRTUAL_MACHINES), "2020-12-01");
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_older_stable() {
+        let supported = vec![
+            "2020-06-01".to_owned(),
+            "2020-12-01".to_owned(),
+            "2021-03-01-preview".to_owned(),
+        ];
+        assert_eq!(negotiate("2021-07-01", &supported), Some("2020-12-01"));
+        assert_eq!(negotiate("2020-06-01", &supported), Some("2020-06-01"));
+        assert_eq!(negotiate("2019-01-01", &supported), None);
+    }
+}
@@ -15,6 +15,8 @@ pub enum Error {
 
 pub struct ClientCredentials {
     pub client: reqwest::Client,
+    /// The authority host, e.g. `https://login.microsoftonline.com`.
+    pub authority_host: String,
     pub client_id: String,
     pub client_secret: String,
     pub scopes: Vec<String>,
@@ -34,8 +36,8 @@ impl ClientCredentials {
             serde_urlencoded::to_string(params).expect("what kind of failure is possible here?");
 
         let url = format!(
-            "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
-            self.tenant_id
+            "{}/{}/oauth2/v2.0/token",
+            self.authority_host, self.tenant_id
         );
 
         let req = self
@@ -46,7 +48,7 @@ impl ClientCredentials {
             .build()?;
 
         let res = self.client.execute(req).await?;
-        check_status(&res)?;
+        let res = check_status(res).await?;
         let login_response = res.json().await?;
         Ok(login_response)
     }
@@ -0,0 +1,86 @@
+//! Azure cloud environments.
+
+/// The set of endpoints that make up an Azure cloud.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cloud {
+    /// The Azure Resource Manager endpoint, without a trailing slash.
+    pub resource_manager_endpoint: String,
+    /// The Azure Active Directory authority host, without a trailing slash.
+    pub authority_host: String,
+}
+
+impl Cloud {
+    pub fn public() -> Self {
+        Self::custom(
+            "https://management.azure.com",
+            "https://login.microsoftonline.com",
+        )
+    }
+
+    pub fn china() -> Self {
+        Self::custom(
+            "https://management.chinacloudapi.cn",
+            "https://login.chinacloudapi.cn",
+        )
+    }
+
+    pub fn us_government() -> Self {
+        Self::custom(
+            "https://management.usgovcloudapi.net",
+            "https://login.microsoftonline.us",
+        )
+    }
+
+    /// Custom endpoints, for instance for Azure Stack or a local fake ARM
+    /// server.
+    pub fn custom(resource_manager_endpoint: &str, authority_host: &str) -> Self {
+        Self {
+            resource_manager_endpoint: resource_manager_endpoint.trim_end_matches('/').to_owned(),
+            authority_host: authority_host.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Look up a well-known cloud by name; accepts both our short names and
+    /// the names used by the Azure CLI.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "public" | "azurecloud" => Some(Self::public()),
+            "china" | "azurechinacloud" => Some(Self::china()),
+            "usgov" | "azureusgovernment" => Some(Self::us_government()),
+            _ => None,
+        }
+    }
+
+    /// The OAuth scope granting access to the Resource Manager.
+    pub fn resource_manager_scope(&self) -> String {
+        format!("{}/.default", self.resource_manager_endpoint)
+    }
+}
+
+impl Default for Cloud {
+    fn default() -> Self {
+        Self::public()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name() {
+        assert_eq!(Cloud::from_name("AzureChinaCloud"), Some(Cloud::china()));
+        assert_eq!(Cloud::from_name("usgov"), Some(Cloud::us_government()));
+        assert_eq!(Cloud::from_name("mars"), None);
+    }
+
+    #[test]
+    fn custom_endpoints_are_normalized() {
+        let cloud = Cloud::custom("http://localhost:8080/", "http://localhost:8081/");
+        assert_eq!(
+            cloud.resource_manager_scope(),
+            "http://localhost:8080/.default"
+        );
+        assert_eq!(cloud.authority_host, "http://localhost:8081");
+    }
+}
@@ -0,0 +1,59 @@
+//! PostgreSQL and MySQL flexible servers.
+//!
+//! Azure starts a stopped server back automatically after seven days.
+
+use super::{api_version, auth, model, Error, Id};
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    pub(super) async fn list_flexible_servers(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let mut servers: Vec<model::FlexibleServer> = self
+            .list_subscription_resources(api_version::POSTGRESQL_FLEXIBLE_SERVERS)
+            .await?;
+        let mysql_servers: Vec<model::FlexibleServer> = self
+            .list_subscription_resources(api_version::MYSQL_FLEXIBLE_SERVERS)
+            .await?;
+        servers.extend(mysql_servers);
+        servers
+            .into_iter()
+            .map(Self::flexible_server_to_instance)
+            .collect()
+    }
+
+    pub(super) async fn get_flexible_server(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        Self::flexible_server_to_instance(self.get_resource(id).await?)
+    }
+
+    fn flexible_server_to_instance(
+        server: model::FlexibleServer,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let id = Id::from_model(&server.id)?;
+        Ok(crate::core::Instance {
+            display_name: server.name,
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state: Self::detect_flexible_server_state(server.properties.state.as_deref()),
+            observed_at: std::time::Instant::now(),
+        })
+    }
+
+    fn detect_flexible_server_state(state: Option<&str>) -> crate::core::State {
+        match state {
+            Some(model::SERVER_STATE_READY) => crate::core::State::On,
+            Some(model::SERVER_STATE_STOPPED) => crate::core::State::Off,
+            Some(
+                model::SERVER_STATE_STARTING
+                | model::SERVER_STATE_STOPPING
+                | model::SERVER_STATE_UPDATING,
+            ) => crate::core::State::InProgress,
+            _ => crate::core::State::Other,
+        }
+    }
+}
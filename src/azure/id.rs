@@ -0,0 +1,463 @@
+//! Identifiers of the resources we manage.
+//!
+//! Our IDs are the resource group followed by the resource name for plain
+//! virtual machines (`myrg/vm0`), and by a kind and the resource names for
+//! everything else (`myrg/vmss/set0`, `myrg/vmss/set0/3`, `myrg/aks/aks0`,
+//! `myrg/aks/aks0/userpool`, `myrg/postgres/db0`, `myrg/mysql/db0`,
+//! `myrg/webapp/app0`).
+//!
+//! Virtual machines may also be listed in subscriptions other than the
+//! provider's own, in which case their IDs start with the subscription
+//! (`00000000-0000-0000-0000-000000000000:myrg/vm0`); colons are not allowed
+//! in resource group names.
+
+use super::api_version;
+
+const SCALE_SET_KIND: &str = "vmss";
+const MANAGED_CLUSTER_KIND: &str = "aks";
+const POSTGRESQL_SERVER_KIND: &str = "postgres";
+const MYSQL_SERVER_KIND: &str = "mysql";
+const WEB_APP_KIND: &str = "webapp";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Id {
+    VirtualMachine {
+        /// The subscription, unless it is the provider's own.
+        subscription_id: Option<String>,
+        resource_group_name: String,
+        vm_name: String,
+    },
+    ScaleSet {
+        resource_group_name: String,
+        scale_set_name: String,
+    },
+    ScaleSetInstance {
+        resource_group_name: String,
+        scale_set_name: String,
+        instance_id: String,
+    },
+    ManagedCluster {
+        resource_group_name: String,
+        cluster_name: String,
+    },
+    AgentPool {
+        resource_group_name: String,
+        cluster_name: String,
+        pool_name: String,
+    },
+    PostgreSqlServer {
+        resource_group_name: String,
+        server_name: String,
+    },
+    MySqlServer {
+        resource_group_name: String,
+        server_name: String,
+    },
+    WebApp {
+        resource_group_name: String,
+        site_name: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unable to parse the Id from Azure into our Id kind")]
+pub struct ModelIdParsingError;
+
+impl Id {
+    pub fn from_model(id: &str) -> Result<Self, ModelIdParsingError> {
+        // /subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachines/vm0
+        let mut split = id.split('/');
+
+        let _leading = split.next();
+        let _subscriptions_text = split.next();
+        let subscription_id = split.next();
+        let _resource_groups_text = split.next();
+        let resource_group = split.next();
+        let _providers_text = split.next();
+        let namespace = split.next();
+        let resource_type = split.next();
+        let name = split.next();
+        let child_type = split.next();
+        let child_name = split.next();
+
+        let (resource_group, namespace, resource_type, name) =
+            match (resource_group, namespace, resource_type, name) {
+                (Some(resource_group), Some(namespace), Some(resource_type), Some(name)) => (
+                    resource_group.to_owned(),
+                    namespace,
+                    resource_type,
+                    name.to_owned(),
+                ),
+                _ => return Err(ModelIdParsingError),
+            };
+
+        let is = |value: &str, expected: &str| value.eq_ignore_ascii_case(expected);
+        let is_compute = is(namespace, "Microsoft.Compute");
+        let is_container_service = is(namespace, "Microsoft.ContainerService");
+        let is_postgresql = is(namespace, "Microsoft.DBforPostgreSQL");
+        let is_mysql = is(namespace, "Microsoft.DBforMySQL");
+        let is_web = is(namespace, "Microsoft.Web");
+        match (resource_type, child_type, child_name) {
+            (ty, None, None) if is_compute && is(ty, "virtualMachines") => {
+                Ok(Self::VirtualMachine {
+                    subscription_id: subscription_id.map(ToOwned::to_owned),
+                    resource_group_name: resource_group,
+                    vm_name: name,
+                })
+            }
+            (ty, None, None) if is_compute && is(ty, "virtualMachineScaleSets") => {
+                Ok(Self::ScaleSet {
+                    resource_group_name: resource_group,
+                    scale_set_name: name,
+                })
+            }
+            (ty, Some(child_ty), Some(instance_id))
+                if is_compute
+                    && is(ty, "virtualMachineScaleSets")
+                    && is(child_ty, "virtualMachines") =>
+            {
+                Ok(Self::ScaleSetInstance {
+                    resource_group_name: resource_group,
+                    scale_set_name: name,
+                    instance_id: instance_id.to_owned(),
+                })
+            }
+            (ty, None, None) if is_container_service && is(ty, "managedClusters") => {
+                Ok(Self::ManagedCluster {
+                    resource_group_name: resource_group,
+                    cluster_name: name,
+                })
+            }
+            (ty, Some(child_ty), Some(pool_name))
+                if is_container_service
+                    && is(ty, "managedClusters")
+                    && is(child_ty, "agentPools") =>
+            {
+                Ok(Self::AgentPool {
+                    resource_group_name: resource_group,
+                    cluster_name: name,
+                    pool_name: pool_name.to_owned(),
+                })
+            }
+            (ty, None, None) if is_postgresql && is(ty, "flexibleServers") => {
+                Ok(Self::PostgreSqlServer {
+                    resource_group_name: resource_group,
+                    server_name: name,
+                })
+            }
+            (ty, None, None) if is_mysql && is(ty, "flexibleServers") => Ok(Self::MySqlServer {
+                resource_group_name: resource_group,
+                server_name: name,
+            }),
+            (ty, None, None) if is_web && is(ty, "sites") => Ok(Self::WebApp {
+                resource_group_name: resource_group,
+                site_name: name,
+            }),
+            _ => Err(ModelIdParsingError),
+        }
+    }
+
+    /// The path of the resource, relative to the subscription.
+    pub fn path(&self) -> String {
+        match self {
+            Self::VirtualMachine {
+                resource_group_name,
+                vm_name,
+                ..
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+                resource_group_name, vm_name
+            ),
+            Self::ScaleSet {
+                resource_group_name,
+                scale_set_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.Compute/virtualMachineScaleSets/{}",
+                resource_group_name, scale_set_name
+            ),
+            Self::ScaleSetInstance {
+                resource_group_name,
+                scale_set_name,
+                instance_id,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.Compute/virtualMachineScaleSets/{}/virtualMachines/{}",
+                resource_group_name, scale_set_name, instance_id
+            ),
+            Self::ManagedCluster {
+                resource_group_name,
+                cluster_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.ContainerService/managedClusters/{}",
+                resource_group_name, cluster_name
+            ),
+            Self::AgentPool {
+                resource_group_name,
+                cluster_name,
+                pool_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.ContainerService/managedClusters/{}/agentPools/{}",
+                resource_group_name, cluster_name, pool_name
+            ),
+            Self::PostgreSqlServer {
+                resource_group_name,
+                server_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.DBforPostgreSQL/flexibleServers/{}",
+                resource_group_name, server_name
+            ),
+            Self::MySqlServer {
+                resource_group_name,
+                server_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.DBforMySQL/flexibleServers/{}",
+                resource_group_name, server_name
+            ),
+            Self::WebApp {
+                resource_group_name,
+                site_name,
+            } => format!(
+                "resourceGroups/{}/providers/Microsoft.Web/sites/{}",
+                resource_group_name, site_name
+            ),
+        }
+    }
+
+    /// The resource type whose API version applies to the resource.
+    pub fn resource_type(&self) -> &'static str {
+        match self {
+            Self::VirtualMachine { .. } => api_version::VIRTUAL_MACHINES,
+            Self::ScaleSet { .. } | Self::ScaleSetInstance { .. } => {
+                api_version::VIRTUAL_MACHINE_SCALE_SETS
+            }
+            Self::ManagedCluster { .. } | Self::AgentPool { .. } => api_version::MANAGED_CLUSTERS,
+            Self::PostgreSqlServer { .. } => api_version::POSTGRESQL_FLEXIBLE_SERVERS,
+            Self::MySqlServer { .. } => api_version::MYSQL_FLEXIBLE_SERVERS,
+            Self::WebApp { .. } => api_version::SITES,
+        }
+    }
+
+    /// The type of the resource, as reported to clients.
+    pub fn arm_type(&self) -> &'static str {
+        match self {
+            Self::VirtualMachine { .. } => "Microsoft.Compute/virtualMachines",
+            Self::ScaleSet { .. } => "Microsoft.Compute/virtualMachineScaleSets",
+            Self::ScaleSetInstance { .. } => {
+                "Microsoft.Compute/virtualMachineScaleSets/virtualMachines"
+            }
+            Self::ManagedCluster { .. } => "Microsoft.ContainerService/managedClusters",
+            Self::AgentPool { .. } => "Microsoft.ContainerService/managedClusters/agentPools",
+            Self::PostgreSqlServer { .. } => "Microsoft.DBforPostgreSQL/flexibleServers",
+            Self::MySqlServer { .. } => "Microsoft.DBforMySQL/flexibleServers",
+            Self::WebApp { .. } => "Microsoft.Web/sites",
+        }
+    }
+
+    /// The subscription of the resource, if it is known not to be the
+    /// provider's own.
+    pub fn subscription_id(&self) -> Option<&str> {
+        match self {
+            Self::VirtualMachine {
+                subscription_id, ..
+            } => subscription_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Forget the subscription if it is `own_subscription_id`.
+    pub fn relative_to(mut self, own_subscription_id: &str) -> Self {
+        if let Self::VirtualMachine {
+            subscription_id, ..
+        } = &mut self
+        {
+            if matches!(subscription_id, Some(id) if id.eq_ignore_ascii_case(own_subscription_id)) {
+                *subscription_id = None;
+            }
+        }
+        self
+    }
+
+    /// The Resource Manager ID, given the provider's own subscription.
+    pub fn to_model(&self, own_subscription_id: &str) -> String {
+        format!(
+            "/subscriptions/{}/{}",
+            self.subscription_id().unwrap_or(own_subscription_id),
+            self.path()
+        )
+    }
+}
+
+impl From<Id> for crate::core::Id {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::VirtualMachine {
+                subscription_id: None,
+                resource_group_name,
+                vm_name,
+            } => format!("{}/{}", resource_group_name, vm_name),
+            Id::VirtualMachine {
+                subscription_id: Some(subscription_id),
+                resource_group_name,
+                vm_name,
+            } => format!("{}:{}/{}", subscription_id, resource_group_name, vm_name),
+            Id::ScaleSet {
+                resource_group_name,
+                scale_set_name,
+            } => format!(
+                "{}/{}/{}",
+                resource_group_name, SCALE_SET_KIND, scale_set_name
+            ),
+            Id::ScaleSetInstance {
+                resource_group_name,
+                scale_set_name,
+                instance_id,
+            } => format!(
+                "{}/{}/{}/{}",
+                resource_group_name, SCALE_SET_KIND, scale_set_name, instance_id
+            ),
+            Id::ManagedCluster {
+                resource_group_name,
+                cluster_name,
+            } => format!(
+                "{}/{}/{}",
+                resource_group_name, MANAGED_CLUSTER_KIND, cluster_name
+            ),
+            Id::AgentPool {
+                resource_group_name,
+                cluster_name,
+                pool_name,
+            } => format!(
+                "{}/{}/{}/{}",
+                resource_group_name, MANAGED_CLUSTER_KIND, cluster_name, pool_name
+            ),
+            Id::PostgreSqlServer {
+                resource_group_name,
+                server_name,
+            } => format!(
+                "{}/{}/{}",
+                resource_group_name, POSTGRESQL_SERVER_KIND, server_name
+            ),
+            Id::MySqlServer {
+                resource_group_name,
+                server_name,
+            } => format!(
+                "{}/{}/{}",
+                resource_group_name, MYSQL_SERVER_KIND, server_name
+            ),
+            Id::WebApp {
+                resource_group_name,
+                site_name,
+            } => format!("{}/{}/{}", resource_group_name, WEB_APP_KIND, site_name),
+        }
+    }
+}
+
+impl TryFrom<&crate::core::IdRef> for Id {
+    type Error = crate::core::IdParsingError;
+
+    fn try_from(value: &crate::core::IdRef) -> Result<Self, Self::Error> {
+        let (subscription_id, value) = match value.split_once(':') {
+            Some((subscription_id, value)) => (Some(subscription_id), value),
+            None => (None, value),
+        };
+        let parts: Vec<&str> = value.split('/').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(crate::core::IdParsingError);
+        }
+        // Only virtual machines are listed in other subscriptions.
+        if subscription_id.is_some() && (parts.len() != 2 || subscription_id == Some("")) {
+            return Err(crate::core::IdParsingError);
+        }
+        let owned = |idx: usize| parts[idx].to_owned();
+        match parts.as_slice() {
+            [_, _] => Ok(Self::VirtualMachine {
+                subscription_id: subscription_id.map(ToOwned::to_owned),
+                resource_group_name: owned(0),
+                vm_name: owned(1),
+            }),
+            [_, SCALE_SET_KIND, _] => Ok(Self::ScaleSet {
+                resource_group_name: owned(0),
+                scale_set_name: owned(2),
+            }),
+            [_, SCALE_SET_KIND, _, _] => Ok(Self::ScaleSetInstance {
+                resource_group_name: owned(0),
+                scale_set_name: owned(2),
+                instance_id: owned(3),
+            }),
+            [_, MANAGED_CLUSTER_KIND, _] => Ok(Self::ManagedCluster {
+                resource_group_name: owned(0),
+                cluster_name: owned(2),
+            }),
+            [_, MANAGED_CLUSTER_KIND, _, _] => Ok(Self::AgentPool {
+                resource_group_name: owned(0),
+                cluster_name: owned(2),
+                pool_name: owned(3),
+            }),
+            [_, POSTGRESQL_SERVER_KIND, _] => Ok(Self::PostgreSqlServer {
+                resource_group_name: owned(0),
+                server_name: owned(2),
+            }),
+            [_, MYSQL_SERVER_KIND, _] => Ok(Self::MySqlServer {
+                resource_group_name: owned(0),
+                server_name: owned(2),
+            }),
+            [_, WEB_APP_KIND, _] => Ok(Self::WebApp {
+                resource_group_name: owned(0),
+                site_name: owned(2),
+            }),
+            _ => Err(crate::core::IdParsingError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_from_model() {
+        let sample_model_id = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachines/vm0";
+        let id = Id::from_model(sample_model_id).unwrap();
+        assert_eq!(
+            id,
+            Id::VirtualMachine {
+                subscription_id: Some("00000000-0000-0000-0000-000000000000".into()),
+                resource_group_name: "myrg".into(),
+                vm_name: "vm0".into(),
+            }
+        )
+    }
+
+    #[test]
+    fn other_subscriptions_are_kept_in_ids() {
+        let sample_model_id = "/subscriptions/11111111-1111-1111-1111-111111111111/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachines/vm0";
+        let own_subscription_id = "00000000-0000-0000-0000-000000000000";
+        let id = Id::from_model(sample_model_id)
+            .unwrap()
+            .relative_to(own_subscription_id);
+        assert_eq!(id.to_model(own_subscription_id), sample_model_id);
+
+        let core_id = crate::core::Id::from(id.clone());
+        assert_eq!(core_id, "11111111-1111-1111-1111-111111111111:myrg/vm0");
+        assert_eq!(Id::try_from(core_id.as_str()).unwrap(), id);
+        assert!(Id::try_from("11111111-1111-1111-1111-111111111111:myrg/vmss/set0").is_err());
+
+        let own = Id::from_model(&id.to_model(own_subscription_id).replace("1111", "0000"))
+            .unwrap()
+            .relative_to(own_subscription_id);
+        assert_eq!(crate::core::Id::from(own), "myrg/vm0");
+    }
+
+    #[test]
+    fn scale_set_instance_id_round_trip() {
+        let sample_model_id = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachineScaleSets/set0/virtualMachines/3";
+        let id = Id::from_model(sample_model_id).unwrap();
+        assert_eq!(
+            id.to_model("00000000-0000-0000-0000-000000000000"),
+            sample_model_id
+        );
+
+        let core_id = crate::core::Id::from(id.clone());
+        assert_eq!(core_id, "myrg/vmss/set0/3");
+        assert_eq!(Id::try_from(core_id.as_str()).unwrap(), id);
+    }
+}
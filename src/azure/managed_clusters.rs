@@ -0,0 +1,294 @@
+//! AKS clusters, started and stopped as a whole, and their user node pools,
+//! scaled to zero and back.
+
+use reqwest::Method;
+
+use super::{api_version, auth, model, Error, Id};
+
+/// The tag remembering the node count of a pool scaled to zero.
+const COUNT_TAG: &str = "vm-onoff-count";
+
+/// The tag remembering the auto-scaling bounds, as `min:max`, of a pool
+/// scaled to zero.
+const AUTO_SCALING_TAG: &str = "vm-onoff-auto-scaling";
+
+/// The node count to restore when the tag is missing.
+const DEFAULT_COUNT: u64 = 1;
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    fn is_in_progress(provisioning_state: Option<&str>) -> bool {
+        matches!(
+            provisioning_state,
+            Some(
+                model::PROVISIONING_STATE_STARTING
+                    | model::PROVISIONING_STATE_STOPPING
+                    | model::PROVISIONING_STATE_SCALING
+            )
+        )
+    }
+
+    fn power_state_to_state(power_state: Option<&model::PowerState>) -> crate::core::State {
+        match power_state.map(|power_state| power_state.code.as_str()) {
+            Some(model::POWER_STATE_RUNNING) => crate::core::State::On,
+            Some(model::POWER_STATE_STOPPED) => crate::core::State::Off,
+            _ => crate::core::State::Other,
+        }
+    }
+
+    fn detect_cluster_state(properties: &model::ManagedClusterProperties) -> crate::core::State {
+        if Self::is_in_progress(properties.provisioning_state.as_deref()) {
+            return crate::core::State::InProgress;
+        }
+        Self::power_state_to_state(properties.power_state.as_ref())
+    }
+
+    fn detect_pool_state(properties: &model::AgentPoolProperties) -> crate::core::State {
+        if Self::is_in_progress(properties.provisioning_state.as_deref()) {
+            return crate::core::State::InProgress;
+        }
+        if properties.count == Some(0) {
+            return crate::core::State::Off;
+        }
+        Self::power_state_to_state(properties.power_state.as_ref())
+    }
+
+    /// The cluster itself followed by its node pools.
+    fn managed_cluster_to_instances(
+        cluster: model::ManagedCluster,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let (resource_group_name, cluster_name) = match Id::from_model(&cluster.id)? {
+            Id::ManagedCluster {
+                resource_group_name,
+                cluster_name,
+            } => (resource_group_name, cluster_name),
+            _ => return Err(Error::ModelIdParsing(super::ModelIdParsingError)),
+        };
+
+        let observed_at = std::time::Instant::now();
+        let id = Id::ManagedCluster {
+            resource_group_name: resource_group_name.clone(),
+            cluster_name: cluster_name.clone(),
+        };
+        let mut instances = vec![crate::core::Instance {
+            display_name: cluster.name.clone(),
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state: Self::detect_cluster_state(&cluster.properties),
+            observed_at,
+        }];
+
+        for pool in &cluster.properties.agent_pool_profiles {
+            let pool_name = match &pool.name {
+                Some(pool_name) => pool_name.clone(),
+                None => continue,
+            };
+            let display_name = format!("{}/{}", cluster.name, pool_name);
+            let id = Id::AgentPool {
+                resource_group_name: resource_group_name.clone(),
+                cluster_name: cluster_name.clone(),
+                pool_name,
+            };
+            instances.push(crate::core::Instance {
+                display_name,
+                resource_type: id.arm_type().to_owned(),
+                id: id.into(),
+                state: Self::detect_pool_state(pool),
+                observed_at,
+            });
+        }
+
+        Ok(instances)
+    }
+
+    pub(super) async fn list_managed_clusters(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let clusters: Vec<model::ManagedCluster> = self
+            .list_subscription_resources(api_version::MANAGED_CLUSTERS)
+            .await?;
+
+        let mut instances = Vec::new();
+        for cluster in clusters {
+            instances.extend(Self::managed_cluster_to_instances(cluster)?);
+        }
+        Ok(instances)
+    }
+
+    pub(super) async fn get_managed_cluster(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let cluster: model::ManagedCluster = self.get_resource(id).await?;
+        let mut instances = Self::managed_cluster_to_instances(cluster)?;
+        Ok(instances.swap_remove(0))
+    }
+
+    pub(super) async fn get_agent_pool(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let pool: model::AgentPool = self.get_resource(id).await?;
+        let cluster_name = match id {
+            Id::AgentPool { cluster_name, .. } => cluster_name,
+            _ => return Err(Error::ModelIdParsing(super::ModelIdParsingError)),
+        };
+        let pool_id = Id::from_model(&pool.id)?;
+        Ok(crate::core::Instance {
+            display_name: format!("{}/{}", cluster_name, pool.name),
+            resource_type: pool_id.arm_type().to_owned(),
+            id: pool_id.into(),
+            state: Self::detect_pool_state(&pool.properties),
+            observed_at: std::time::Instant::now(),
+        })
+    }
+
+    async fn get_user_agent_pool(
+        &self,
+        id: &Id,
+    ) -> Result<model::AgentPool, Error<AuthTokenProvider::Error>> {
+        let pool: model::AgentPool = self.get_resource(id).await?;
+        if pool.properties.mode.as_deref() != Some(model::AGENT_POOL_MODE_USER) {
+            return Err(Error::Unsupported(
+                "only user node pools can be scaled to zero",
+            ));
+        }
+        Ok(pool)
+    }
+
+    async fn put_agent_pool(
+        &self,
+        id: &Id,
+        pool: &model::AgentPool,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_resource_url(id, "", "");
+        self.exec(self.build_json_request(&auth_token, Method::PUT, &url, pool)?)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn start_managed_cluster(
+        &self,
+        id: &Id,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        if let Id::ManagedCluster { .. } = id {
+            return self.post_action(id, "/start").await;
+        }
+
+        let mut pool = self.get_user_agent_pool(id).await?;
+        let properties = &mut pool.properties;
+        if properties.count != Some(0) {
+            return Ok(());
+        }
+
+        properties.count = Some(
+            properties
+                .tags
+                .remove(COUNT_TAG)
+                .and_then(|count| count.parse().ok())
+                .unwrap_or(DEFAULT_COUNT),
+        );
+        let auto_scaling = properties.tags.remove(AUTO_SCALING_TAG);
+        let bounds = auto_scaling.as_deref().and_then(|bounds| {
+            let (min, max) = bounds.split_once(':')?;
+            Some((min.parse().ok()?, max.parse().ok()?))
+        });
+        if let Some((min_count, max_count)) = bounds {
+            properties.enable_auto_scaling = Some(true);
+            properties.min_count = Some(min_count);
+            properties.max_count = Some(max_count);
+        }
+
+        self.put_agent_pool(id, &pool).await
+    }
+
+    pub(super) async fn stop_managed_cluster(
+        &self,
+        id: &Id,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        if let Id::ManagedCluster { .. } = id {
+            return self.post_action(id, "/stop").await;
+        }
+
+        let mut pool = self.get_user_agent_pool(id).await?;
+        let properties = &mut pool.properties;
+        let count = properties.count.unwrap_or(0);
+        if count == 0 {
+            return Ok(());
+        }
+
+        properties
+            .tags
+            .insert(COUNT_TAG.to_owned(), count.to_string());
+        // The auto-scaler would scale the pool back up.
+        if properties.enable_auto_scaling == Some(true) {
+            if let (Some(min_count), Some(max_count)) = (properties.min_count, properties.max_count)
+            {
+                properties.tags.insert(
+                    AUTO_SCALING_TAG.to_owned(),
+                    format!("{}:{}", min_count, max_count),
+                );
+            }
+            properties.enable_auto_scaling = Some(false);
+            properties.min_count = None;
+            properties.max_count = None;
+        }
+        properties.count = Some(0);
+
+        self.put_agent_pool(id, &pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, routing::get, Json, Router};
+
+    use super::*;
+    use crate::azure::{testing, ListBackend};
+
+    #[tokio::test]
+    async fn lists_no_clusters_without_the_resource_provider() {
+        let app = Router::new().route(
+            "/subscriptions/:subscription/providers/Microsoft.ContainerService/managedClusters",
+            get(|| async {
+                let error = serde_json::json!({"error": {
+                    "code": "MissingSubscriptionRegistration",
+                    "message": "The subscription is not registered to use namespace 'Microsoft.ContainerService'.",
+                }});
+                (StatusCode::CONFLICT, Json(error))
+            }),
+        );
+        let provider = testing::fake_provider(app, ListBackend::Arm);
+
+        assert!(provider.list_managed_clusters().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn lists_no_clusters_when_not_allowed_to() {
+        let app = Router::new().route(
+            "/subscriptions/:subscription/providers/Microsoft.ContainerService/managedClusters",
+            get(|| async {
+                let error = serde_json::json!({"error": {
+                    "code": "AuthorizationFailed",
+                    "message": "The client does not have authorization to perform action 'Microsoft.ContainerService/managedClusters/read'.",
+                }});
+                (StatusCode::FORBIDDEN, Json(error))
+            }),
+        );
+        let provider = testing::fake_provider(app, ListBackend::Arm);
+
+        assert!(provider.list_managed_clusters().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn agent_pool_round_trips_unknown_fields() {
+        let json = r#"{"name":"userpool","id":"/x","properties":{"count":3,"mode":"User","vmSize":"Standard_D2s_v3","tags":{}}}"#;
+        let pool: model::AgentPool = serde_json::from_str(json).unwrap();
+        assert_eq!(pool.properties.count, Some(3));
+        let value = serde_json::to_value(&pool).unwrap();
+        assert_eq!(value["properties"]["vmSize"], "Standard_D2s_v3");
+    }
+}
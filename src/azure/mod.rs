@@ -1,78 +1,143 @@
 //! Azure provider implementation.
 
+use std::collections::HashMap;
+
 use reqwest::Method;
+use tracing::{info, warn};
 
 use self::{
     auth::Token,
-    utils::{check_status, ServerError},
+    utils::{check_status, ErrorDetail, ServerError},
 };
 
+pub mod api_version;
 pub mod auth;
+pub mod cloud;
+mod flexible_servers;
+mod id;
+mod managed_clusters;
+mod model;
+mod resource_graph;
+mod scale_sets;
+#[cfg(test)]
+pub(crate) mod testing;
 mod utils;
+mod virtual_machines;
+mod web_apps;
+
+use self::id::Id;
+pub use self::{api_version::ApiVersions, cloud::Cloud, id::ModelIdParsingError};
+
+/// Below this many virtual machines, `get_many` reads them from Resource
+/// Manager one by one: Resource Graph answers in a single request, but may lag
+/// behind by a few seconds and miss recent changes.
+const MIN_RESOURCE_GRAPH_BATCH: usize = 16;
 
 pub struct Provider<AuthTokenProvider> {
     pub client: reqwest::Client,
+    pub cloud: Cloud,
+    pub api_versions: ApiVersions,
+    pub list_backend: ListBackend,
+    /// The kinds of resources listed.
+    pub resource_kinds: Vec<ResourceKind>,
+    pub scale_set_stop_mode: ScaleSetStopMode,
     pub subscription_id: String,
+    /// Further subscriptions whose virtual machines are listed and managed
+    /// too; the other kinds of resources are only looked for in
+    /// `subscription_id`.
+    pub extra_subscription_ids: Vec<String>,
     pub auth_token_provider: AuthTokenProvider,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Id {
-    pub resource_group_name: String,
-    pub vm_name: String,
+/// How to list the virtual machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListBackend {
+    /// Page through the Resource Manager listing; always available.
+    Arm,
+    /// Query Azure Resource Graph, which is much faster for large estates;
+    /// falls back to [`ListBackend::Arm`] when Resource Graph is unavailable.
+    ResourceGraph,
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("Unable to parse the Id from Azure into our Id kind")]
-pub struct ModelIdParsingError;
-
-impl Id {
-    pub fn from_model(id: &str) -> Result<Self, ModelIdParsingError> {
-        // /subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachines/vm0
-        let mut split = id.split('/');
-
-        let _leading = split.next();
-        let _subscriptions_text = split.next();
-        let _subscription_id = split.next();
-        let _resource_groups_text = split.next();
-        let resource_group = split.next();
-        let _providers_text = split.next();
-        let _provider_id_0 = split.next();
-        let _provider_id_1 = split.next();
-        let vm_name = split.next();
-
-        let (resource_group, vm_name) = match (resource_group, vm_name) {
-            (Some(resource_group), Some(vm_name)) => (resource_group, vm_name),
-            _ => return Err(ModelIdParsingError),
-        };
+#[error("Unknown list backend {0:?}")]
+pub struct UnknownListBackend(String);
 
-        Ok(Self {
-            resource_group_name: resource_group.to_owned(),
-            vm_name: vm_name.to_owned(),
-        })
+impl std::str::FromStr for ListBackend {
+    type Err = UnknownListBackend;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arm" => Ok(Self::Arm),
+            "resource-graph" => Ok(Self::ResourceGraph),
+            _ => Err(UnknownListBackend(s.to_owned())),
+        }
     }
 }
 
-impl From<Id> for crate::core::Id {
-    fn from(id: Id) -> Self {
-        format!("{}/{}", id.resource_group_name, id.vm_name)
+/// A kind of resources to list, for service principals that may only read
+/// some of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    VirtualMachines,
+    ScaleSets,
+    ManagedClusters,
+    /// PostgreSQL and MySQL flexible servers.
+    FlexibleServers,
+    WebApps,
+}
+
+impl ResourceKind {
+    pub const ALL: [Self; 5] = [
+        Self::VirtualMachines,
+        Self::ScaleSets,
+        Self::ManagedClusters,
+        Self::FlexibleServers,
+        Self::WebApps,
+    ];
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown resource kind {0:?}")]
+pub struct UnknownResourceKind(String);
+
+impl std::str::FromStr for ResourceKind {
+    type Err = UnknownResourceKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "virtual-machines" => Ok(Self::VirtualMachines),
+            "scale-sets" => Ok(Self::ScaleSets),
+            "managed-clusters" => Ok(Self::ManagedClusters),
+            "flexible-servers" => Ok(Self::FlexibleServers),
+            "web-apps" => Ok(Self::WebApps),
+            _ => Err(UnknownResourceKind(s.to_owned())),
+        }
     }
 }
 
-impl TryFrom<&crate::core::IdRef> for Id {
-    type Error = crate::core::IdParsingError;
+/// How to stop (and start back) a whole scale set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleSetStopMode {
+    /// Deallocate the instances, keeping the capacity.
+    Deallocate,
+    /// Scale the capacity to zero, remembering it in a tag to restore it.
+    ScaleToZero,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown scale set stop mode {0:?}")]
+pub struct UnknownScaleSetStopMode(String);
+
+impl std::str::FromStr for ScaleSetStopMode {
+    type Err = UnknownScaleSetStopMode;
 
-    fn try_from(value: &crate::core::IdRef) -> Result<Self, Self::Error> {
-        let mut iter = value.split('/');
-        let resource_group_name = iter.next().ok_or(crate::core::IdParsingError)?;
-        let vm_name = iter.next().ok_or(crate::core::IdParsingError)?;
-        if iter.next().is_some() {
-            return Err(crate::core::IdParsingError);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deallocate" => Ok(Self::Deallocate),
+            "scale-to-zero" => Ok(Self::ScaleToZero),
+            _ => Err(UnknownScaleSetStopMode(s.to_owned())),
         }
-        Ok(Self {
-            resource_group_name: resource_group_name.to_owned(),
-            vm_name: vm_name.to_owned(),
-        })
     }
 }
 
@@ -86,30 +151,98 @@ pub enum Error<AuthError> {
     Server(#[from] ServerError),
     #[error(transparent)]
     ModelIdParsing(#[from] ModelIdParsingError),
+    #[error("unsupported: {0}")]
+    Unsupported(&'static str),
+}
+
+impl<AuthError> Error<AuthError>
+where
+    AuthError: std::error::Error + Send + Sync + 'static,
+{
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Auth(_) => crate::core::ErrorKind::Unauthorized,
+            Self::Reqwest(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::ModelIdParsing(_) => crate::core::ErrorKind::Other,
+            Self::Unsupported(_) => crate::core::ErrorKind::Conflict,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(err.into()),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
 }
 
 impl<AuthTokenProvider> Provider<AuthTokenProvider>
 where
     AuthTokenProvider: auth::TokenProvider,
 {
-    fn build_vm_url(&self, id: Id, action: &str, query_extras: &str) -> String {
+    fn build_resource_url(&self, id: &Id, action: &str, query_extras: &str) -> String {
         format!(
-            "https://management.azure.com/subscriptions/{subscriptionId}/resourceGroups/{resourceGroupName}/providers/Microsoft.Compute/virtualMachines/{vmName}{action}?api-version=2021-07-01{query_extras}",
-            subscriptionId = self.subscription_id,
-            resourceGroupName = id.resource_group_name,
-            vmName = id.vm_name,
+            "{endpoint}/subscriptions/{subscriptionId}/{path}{action}?api-version={apiVersion}{query_extras}",
+            endpoint = self.cloud.resource_manager_endpoint,
+            subscriptionId = id.subscription_id().unwrap_or(&self.subscription_id),
+            path = id.path(),
             action = action,
+            apiVersion = self.api_versions.get(id.resource_type()),
             query_extras = query_extras,
         )
     }
 
-    fn build_all_vms_list_url(&self, subscription_id: &str) -> String {
+    /// Our own subscription followed by the extra ones.
+    fn subscription_ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.subscription_id.as_str())
+            .chain(self.extra_subscription_ids.iter().map(String::as_str))
+    }
+
+    fn build_subscription_list_url(&self, resource_type: &str) -> String {
+        format!(
+            "{endpoint}/subscriptions/{subscriptionId}/providers/{resourceType}?api-version={apiVersion}",
+            endpoint = self.cloud.resource_manager_endpoint,
+            subscriptionId = self.subscription_id,
+            resourceType = resource_type,
+            apiVersion = self.api_versions.get(resource_type),
+        )
+    }
+
+    fn build_resource_provider_url(&self, namespace: &str) -> String {
+        format!(
+            "{endpoint}/subscriptions/{subscriptionId}/providers/{namespace}?api-version={apiVersion}",
+            endpoint = self.cloud.resource_manager_endpoint,
+            subscriptionId = self.subscription_id,
+            namespace = namespace,
+            apiVersion = self.api_versions.get(api_version::RESOURCE_PROVIDERS),
+        )
+    }
+
+    /// The first resource of our subscription, which is the cheapest way to
+    /// check both the token and Resource Manager.
+    fn build_resource_probe_url(&self) -> String {
         format!(
-            "https://management.azure.com/subscriptions/{subscriptionId}/providers/Microsoft.Compute/virtualMachines?api-version=2021-07-01&statusOnly=true",
-            subscriptionId = subscription_id,
+            "{endpoint}/subscriptions/{subscriptionId}/resources?api-version={apiVersion}&$top=1",
+            endpoint = self.cloud.resource_manager_endpoint,
+            subscriptionId = self.subscription_id,
+            apiVersion = self.api_versions.get(api_version::RESOURCE_PROVIDERS),
         )
     }
 
+    async fn probe(&self) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_resource_probe_url();
+        self.exec(self.build_request(&auth_token, Method::GET, &url)?)
+            .await?;
+        Ok(())
+    }
+
     fn build_request(
         &self,
         auth_token: &str,
@@ -130,6 +263,24 @@ where
         builder.build().map_err(Error::Reqwest)
     }
 
+    fn build_json_request<T>(
+        &self,
+        auth_token: &str,
+        method: Method,
+        url: &str,
+        body: &T,
+    ) -> Result<reqwest::Request, Error<AuthTokenProvider::Error>>
+    where
+        T: serde::Serialize,
+    {
+        self.client
+            .request(method, url)
+            .bearer_auth(auth_token)
+            .json(body)
+            .build()
+            .map_err(Error::Reqwest)
+    }
+
     async fn get_auth_token(&self) -> Result<String, Error<AuthTokenProvider::Error>> {
         let token = self
             .auth_token_provider
@@ -139,53 +290,168 @@ where
         Ok(token.access_token().to_owned())
     }
 
-    async fn start(&self, id: Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+    /// Check the API versions we are about to use against the ones the cloud
+    /// advertises, and downgrade those it does not support yet. Versions
+    /// pinned by the user are left alone.
+    ///
+    /// Sovereign clouds tend to lag behind the public cloud.
+    pub async fn negotiate_api_versions(&mut self) -> Result<(), Error<AuthTokenProvider::Error>> {
         let auth_token = self.get_auth_token().await?;
-        let url = self.build_vm_url(id, "/start", "");
-        self.exec(self.build_request(&auth_token, Method::POST, &url)?)
-            .await?;
+
+        let resource_types: Vec<String> = self
+            .api_versions
+            .resource_types()
+            .filter(|resource_type| {
+                *resource_type != api_version::RESOURCE_PROVIDERS
+                    && !self.api_versions.is_pinned(resource_type)
+            })
+            .map(ToOwned::to_owned)
+            .collect();
+
+        for resource_type in resource_types {
+            let (namespace, type_name) = match resource_type.split_once('/') {
+                Some(parts) => parts,
+                None => continue,
+            };
+
+            let url = self.build_resource_provider_url(namespace);
+            let res = self
+                .exec(self.build_request(&auth_token, Method::GET, &url)?)
+                .await?;
+            let resource_provider: model::ResourceProvider = Self::parse_json(res).await?;
+
+            let supported = match resource_provider
+                .resource_types
+                .into_iter()
+                .find(|ty| ty.resource_type.eq_ignore_ascii_case(type_name))
+            {
+                Some(ty) => ty.api_versions,
+                None => continue,
+            };
+
+            let wanted = self.api_versions.get(&resource_type).to_owned();
+            match api_version::negotiate(&wanted, &supported) {
+                Some(version) if version == wanted => {}
+                Some(version) => {
+                    info!(%resource_type, %wanted, %version, "Downgrading API version");
+                    let version = version.to_owned();
+                    self.api_versions.set_negotiated(&resource_type, &version);
+                }
+                None => {
+                    warn!(%resource_type, %wanted, "No supported API version found");
+                }
+            }
+        }
+
         Ok(())
     }
 
-    async fn stop(&self, id: Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+    /// POST an action, such as `/start`, to a resource.
+    async fn post_action(
+        &self,
+        id: &Id,
+        action: &str,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
         let auth_token = self.get_auth_token().await?;
-        let url = self.build_vm_url(id, "/deallocate", "");
+        let url = self.build_resource_url(id, action, "");
         self.exec(self.build_request(&auth_token, Method::POST, &url)?)
             .await?;
         Ok(())
     }
 
-    async fn get(&self, id: Id) -> Result<model::VirtualMachine, Error<AuthTokenProvider::Error>> {
+    async fn get_resource<T>(&self, id: &Id) -> Result<T, Error<AuthTokenProvider::Error>>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
         let auth_token = self.get_auth_token().await?;
-        let url = self.build_vm_url(id, "", "&$expand=instanceView");
+        let url = self.build_resource_url(id, "", "");
         let res = self
             .exec(self.build_request(&auth_token, Method::GET, &url)?)
             .await?;
-
-        let vm = Self::parse_json(res).await?;
-        Ok(vm)
+        Self::parse_json(res).await
     }
 
-    async fn list_all_vms(
-        &self,
-    ) -> Result<model::List<model::VirtualMachine>, Error<AuthTokenProvider::Error>> {
-        let url = self.build_all_vms_list_url(&self.subscription_id);
-        self.list_by_url(&url).await
+    /// Get all the items of a list, following the next links.
+    async fn list_all<T>(&self, url: &str) -> Result<Vec<T>, Error<AuthTokenProvider::Error>>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let mut items = Vec::new();
+        let mut next_link = Some(url.to_owned());
+        while let Some(url) = next_link {
+            let auth_token = self.get_auth_token().await?;
+            let res = self
+                .exec(self.build_request(&auth_token, Method::GET, &url)?)
+                .await?;
+            let list: model::List<T> = Self::parse_json(res).await?;
+            next_link = list.next_link;
+            items.extend(list.value);
+        }
+        Ok(items)
     }
 
-    async fn list_by_url<T>(
+    /// List the resources of a type across the subscription; there are none
+    /// when its resource provider is not registered in the subscription, and
+    /// they are skipped when we are not allowed to read them.
+    async fn list_subscription_resources<T>(
         &self,
-        url: &str,
-    ) -> Result<model::List<T>, Error<AuthTokenProvider::Error>>
+        resource_type: &str,
+    ) -> Result<Vec<T>, Error<AuthTokenProvider::Error>>
     where
         T: for<'de> serde::Deserialize<'de>,
     {
-        let auth_token = self.get_auth_token().await?;
-        let res = self
-            .exec(self.build_request(&auth_token, Method::GET, url)?)
-            .await?;
-        let vms = Self::parse_json(res).await?;
-        Ok(vms)
+        let url = self.build_subscription_list_url(resource_type);
+        match self.list_all(&url).await {
+            Err(Error::Server(ServerError {
+                error: Some(ErrorDetail { code, .. }),
+                ..
+            })) if code == "MissingSubscriptionRegistration" => Ok(Vec::new()),
+            Err(Error::Server(err)) if err.status_code == 403 => {
+                warn!(
+                    resource_type,
+                    "Not allowed to list the resources, skipping them: {}", err
+                );
+                Ok(Vec::new())
+            }
+            result => result,
+        }
+    }
+
+    async fn list_vms(&self) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>>
+    where
+        AuthTokenProvider::Error: std::error::Error,
+    {
+        match self.list_backend {
+            ListBackend::Arm => self.list_vms_via_arm().await,
+            ListBackend::ResourceGraph => match self.list_vms_via_resource_graph().await {
+                Err(err) if resource_graph::should_fall_back(&err) => {
+                    warn!(
+                        "Resource Graph listing failed, falling back to ARM: {}",
+                        err
+                    );
+                    self.list_vms_via_arm().await
+                }
+                result => result,
+            },
+        }
+    }
+
+    async fn get_instance(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        match id {
+            Id::VirtualMachine { .. } | Id::ScaleSetInstance { .. } => {
+                self.model_to_instance(self.get_vm(id).await?)
+            }
+            Id::ScaleSet { .. } => self.get_scale_set(id).await,
+            Id::ManagedCluster { .. } => self.get_managed_cluster(id).await,
+            Id::AgentPool { .. } => self.get_agent_pool(id).await,
+            Id::PostgreSqlServer { .. } | Id::MySqlServer { .. } => {
+                self.get_flexible_server(id).await
+            }
+            Id::WebApp { .. } => self.get_web_app(id).await,
+        }
     }
 
     async fn exec(
@@ -193,7 +459,7 @@ where
         request: reqwest::Request,
     ) -> Result<reqwest::Response, Error<AuthTokenProvider::Error>> {
         let res = self.client.execute(request).await.map_err(Error::Reqwest)?;
-        check_status(&res)?;
+        let res = check_status(res).await?;
         Ok(res)
     }
 
@@ -203,163 +469,239 @@ where
     {
         res.json().await.map_err(Error::Reqwest)
     }
-
-    fn model_to_instance(
-        vm: model::VirtualMachine,
-    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
-        let name = vm.name;
-        let id = Id::from_model(&vm.id)?;
-        let state = Self::detect_state(&vm.properties.instance_view.statuses);
-
-        Ok(crate::core::Instance {
-            display_name: name,
-            id: id.into(),
-            state,
-        })
-    }
-
-    fn detect_state(statuses: &[model::InstanceViewStatus]) -> crate::core::State {
-        let is_found = |code: &str| statuses.iter().any(|status| status.code == code);
-
-        let is_stopped = is_found(model::STATUS_POWER_STATE_STOPPED);
-        let is_deallocated = is_found(model::STATUS_POWER_STATE_DEALLOCATED);
-        let is_off = is_stopped || is_deallocated;
-
-        let is_running = is_found(model::STATUS_POWER_STATE_RUNNING);
-        let is_on = is_running;
-
-        let is_stopping = is_found(model::STATUS_POWER_STATE_STOPPING);
-        let is_deallocing = is_found(model::STATUS_POWER_STATE_DEALLOCATING);
-        let is_starting = is_found(model::STATUS_POWER_STATE_STARTING);
-        let is_in_progress = is_stopping || is_deallocing || is_starting;
-
-        match (is_on, is_off, is_in_progress) {
-            (true, false, false) => crate::core::State::On,
-            (false, true, false) => crate::core::State::Off,
-            (false, false, true) => crate::core::State::InProgress,
-            (_, _, _) => crate::core::State::Other,
-        }
-    }
-}
-
-mod model {
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct List<T> {
-        pub value: Vec<T>,
-        pub next_link: Option<String>,
-    }
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct VirtualMachine {
-        /// Resource name.
-        pub name: String,
-        /// Resource Id.
-        pub id: String,
-        /// Properties.
-        pub properties: VirtualMachineProperties,
-    }
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct VirtualMachineProperties {
-        /// The virtual machine instance view.
-        pub instance_view: VirtualMachineInstanceView,
-    }
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct VirtualMachineInstanceView {
-        /// The resource status information.
-        pub statuses: Vec<InstanceViewStatus>,
-    }
-
-    #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    pub struct InstanceViewStatus {
-        /// The status code.
-        pub code: String,
-    }
-
-    pub const STATUS_POWER_STATE_STOPPING: &str = "PowerState/stopping";
-    pub const STATUS_POWER_STATE_STOPPED: &str = "PowerState/stopped";
-    pub const STATUS_POWER_STATE_DEALLOCATING: &str = "PowerState/deallocating";
-    pub const STATUS_POWER_STATE_DEALLOCATED: &str = "PowerState/deallocated";
-    pub const STATUS_POWER_STATE_STARTING: &str = "PowerState/starting";
-    pub const STATUS_POWER_STATE_RUNNING: &str = "PowerState/running";
 }
 
 #[async_trait::async_trait]
 impl<AuthTokenProvider> crate::core::Provider for Provider<AuthTokenProvider>
 where
     AuthTokenProvider: auth::TokenProvider,
-    <AuthTokenProvider as auth::TokenProvider>::Error: std::error::Error + 'static,
+    <AuthTokenProvider as auth::TokenProvider>::Error: std::error::Error + Send + Sync + 'static,
 {
-    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
-        let list = self.list_all_vms().await?;
-
-        let collect_instances = |vms: Vec<_>| {
-            vms.into_iter()
-                .map(Self::model_to_instance)
-                .collect::<Result<Vec<_>, _>>()
-        };
-
-        let mut next_link = list.next_link;
-        let mut instances = collect_instances(list.value)?;
-        while let Some(url) = next_link {
-            let list = self.list_by_url(&url).await?;
-            next_link = list.next_link;
-
-            let new_instances = collect_instances(list.value)?;
-            instances.extend(new_instances);
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: true,
+            max_concurrent_gets: 8,
         }
+    }
 
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let mut instances = Vec::new();
+        for kind in &self.resource_kinds {
+            let listed = match kind {
+                ResourceKind::VirtualMachines => self.list_vms().await,
+                ResourceKind::ScaleSets => self.list_scale_sets().await,
+                ResourceKind::ManagedClusters => self.list_managed_clusters().await,
+                ResourceKind::FlexibleServers => self.list_flexible_servers().await,
+                ResourceKind::WebApps => self.list_web_apps().await,
+            };
+            instances.extend(listed.map_err(Error::into_core)?);
+        }
         Ok(instances)
     }
 
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.probe().await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
     async fn get(
         &self,
         id: &crate::core::IdRef,
     ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
-        let vm = match self.get(Id::try_from(id)?).await {
-            Ok(vm) => vm,
-            Err(Error::Server(ServerError { status_code })) if status_code == 404 => {
-                return Ok(None)
+        match self.get_instance(&Id::try_from(id)?).await {
+            Ok(instance) => Ok(Some(instance)),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err.into_core().into()),
+        }
+    }
+
+    async fn get_many(
+        &self,
+        ids: &[crate::core::Id],
+    ) -> Result<HashMap<crate::core::Id, crate::core::Instance>, anyhow::Error> {
+        // Resource Graph only knows the power state of plain virtual machines.
+        let mut vm_ids = Vec::with_capacity(ids.len());
+        let mut other_ids = Vec::new();
+        for id in ids {
+            match Id::try_from(id.as_str()) {
+                Ok(vm_id @ Id::VirtualMachine { .. }) => vm_ids.push((id.clone(), vm_id)),
+                Ok(_) => other_ids.push(id.clone()),
+                // Not ours, so not found, rather than failing the whole batch.
+                Err(_) => {}
+            }
+        }
+
+        if vm_ids.len() < MIN_RESOURCE_GRAPH_BATCH {
+            other_ids.extend(vm_ids.drain(..).map(|(id, _)| id));
+        }
+
+        let mut instances = match self.get_many_vms(&vm_ids).await {
+            Ok(rows) => rows
+                .into_iter()
+                .map(|(id, row)| {
+                    let instance = self.row_to_instance(row).map_err(Error::into_core)?;
+                    Ok((id, instance))
+                })
+                .collect::<Result<HashMap<_, _>, anyhow::Error>>()?,
+            Err(err) if resource_graph::should_fall_back(&err) => {
+                warn!(
+                    "Resource Graph lookup failed, falling back to gets: {}",
+                    err
+                );
+                other_ids.extend(vm_ids.into_iter().map(|(id, _)| id));
+                HashMap::new()
             }
-            Err(err) => return Err(err.into()),
+            Err(err) => return Err(err.into_core().into()),
         };
-        let instance = Self::model_to_instance(vm)?;
-        Ok(Some(instance))
+
+        if !other_ids.is_empty() {
+            let others = crate::core::get_many_by_get(self, &other_ids).await?;
+            instances.extend(others);
+        }
+        Ok(instances)
     }
 
     async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
-        self.start(Id::try_from(id)?).await?;
+        let id = Id::try_from(id)?;
+        let result = match id {
+            Id::VirtualMachine { .. } => self.start_vm(&id).await,
+            Id::ScaleSet { .. } | Id::ScaleSetInstance { .. } => self.start_scale_set(&id).await,
+            Id::ManagedCluster { .. } | Id::AgentPool { .. } => {
+                self.start_managed_cluster(&id).await
+            }
+            Id::PostgreSqlServer { .. } | Id::MySqlServer { .. } | Id::WebApp { .. } => {
+                self.post_action(&id, "/start").await
+            }
+        };
+        result.map_err(Error::into_core)?;
         Ok(())
     }
 
     async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
-        self.stop(Id::try_from(id)?).await?;
+        let id = Id::try_from(id)?;
+        let result = match id {
+            Id::VirtualMachine { .. } => self.stop_vm(&id).await,
+            Id::ScaleSet { .. } | Id::ScaleSetInstance { .. } => self.stop_scale_set(&id).await,
+            Id::ManagedCluster { .. } | Id::AgentPool { .. } => {
+                self.stop_managed_cluster(&id).await
+            }
+            Id::PostgreSqlServer { .. } | Id::MySqlServer { .. } | Id::WebApp { .. } => {
+                self.post_action(&id, "/stop").await
+            }
+        };
+        result.map_err(Error::into_core)?;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::{Path, RawQuery},
+        http::StatusCode,
+        routing::get,
+        Json, Router,
+    };
+
     use super::*;
 
-    #[test]
-    fn id_from_model() {
-        let sample_model_id = "/subscriptions/00000000-0000-0000-0000-000000000000/resourceGroups/myrg/providers/Microsoft.Compute/virtualMachines/vm0";
-        let id = Id::from_model(sample_model_id).unwrap();
-        assert_eq!(
-            id,
-            Id {
-                resource_group_name: "myrg".into(),
-                vm_name: "vm0".into(),
-            }
-        )
+    #[tokio::test]
+    async fn checks_health_with_a_single_request() {
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().route(
+            "/subscriptions/:subscription/resources",
+            get({
+                let queries = Arc::clone(&queries);
+                move |RawQuery(query): RawQuery| async move {
+                    queries.lock().unwrap().push(query.unwrap_or_default());
+                    Json(serde_json::json!({"value": []}))
+                }
+            }),
+        );
+        let provider = testing::fake_provider(app, ListBackend::Arm);
+
+        crate::core::Provider::check_health(&provider)
+            .await
+            .unwrap();
+        assert_eq!(*queries.lock().unwrap(), ["api-version=2021-04-01&$top=1"]);
+    }
+
+    #[tokio::test]
+    async fn lists_the_kinds_we_may_read() {
+        fn resource(ty: &str, name: &str) -> serde_json::Value {
+            serde_json::json!({
+                "id": format!(
+                    "/subscriptions/{}/resourceGroups/rg/providers/{}/{}",
+                    testing::SUBSCRIPTION_ID,
+                    ty,
+                    name
+                ),
+                "name": name,
+                "properties": {"state": "Ready"},
+            })
+        }
+        let app = Router::new().route(
+            "/subscriptions/:subscription/providers/:namespace/:ty",
+            get(|Path((_, namespace, ty)): Path<(String, String, String)>| async move {
+                let ty = format!("{}/{}", namespace, ty);
+                if ty == api_version::POSTGRESQL_FLEXIBLE_SERVERS {
+                    let error = serde_json::json!({"error": {
+                        "code": "AuthorizationFailed",
+                        "message": "The client does not have authorization to perform action 'Microsoft.DBforPostgreSQL/flexibleServers/read'.",
+                    }});
+                    return (StatusCode::FORBIDDEN, Json(error));
+                }
+                let name = ty.rsplit('/').next().unwrap().to_ascii_lowercase();
+                (StatusCode::OK, Json(serde_json::json!({"value": [resource(&ty, &name)]})))
+            }),
+        );
+        let mut provider = testing::fake_provider(app, ListBackend::Arm);
+        provider.resource_kinds = vec![
+            ResourceKind::VirtualMachines,
+            ResourceKind::FlexibleServers,
+            ResourceKind::WebApps,
+        ];
+
+        let instances = crate::core::Provider::list(&provider).await.unwrap();
+        let names: Vec<_> = instances
+            .iter()
+            .map(|instance| instance.display_name.as_str())
+            .collect();
+        assert_eq!(names, ["virtualmachines", "flexibleservers", "sites"]);
+    }
+
+    #[tokio::test]
+    async fn leaves_invalid_ids_out_of_batches() {
+        let app = Router::new().route(
+            "/subscriptions/:subscription/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/:name",
+            get(|Path((subscription, name)): Path<(String, String)>| async move {
+                Json(serde_json::json!({
+                    "id": format!(
+                        "/subscriptions/{}/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/{}",
+                        subscription, name
+                    ),
+                    "name": name,
+                    "properties": {"instanceView": {"statuses": [{"code": "PowerState/running"}]}},
+                }))
+            }),
+        );
+        let provider = testing::fake_provider(app, ListBackend::Arm);
+
+        let ids = [
+            "rg/vm0".to_owned(),
+            "rg//vm1".to_owned(),
+            "rg/vm2".to_owned(),
+        ];
+        let instances = crate::core::Provider::get_many(&provider, &ids)
+            .await
+            .unwrap();
+        let mut found: Vec<_> = instances.keys().map(String::as_str).collect();
+        found.sort_unstable();
+        assert_eq!(found, ["rg/vm0", "rg/vm2"]);
     }
 }
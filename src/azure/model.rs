@@ -0,0 +1,257 @@
+//! Resource Manager models.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct List<T> {
+    pub value: Vec<T>,
+    pub next_link: Option<String>,
+}
+
+// The models only declare the fields we use, and default everything that
+// may be missing, so that they keep deserializing across API versions.
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachine {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// Properties.
+    #[serde(default)]
+    pub properties: VirtualMachineProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineProperties {
+    /// The virtual machine instance view.
+    #[serde(default)]
+    pub instance_view: VirtualMachineInstanceView,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineInstanceView {
+    /// The resource status information.
+    #[serde(default)]
+    pub statuses: Vec<InstanceViewStatus>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineScaleSet {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// The virtual machine scale set sku.
+    #[serde(default)]
+    pub sku: Sku,
+    /// Resource tags.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sku {
+    /// The sku name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Specifies the number of virtual machines in the scale set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u64>,
+}
+
+/// The body of a scale set update, carrying only what we change.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineScaleSetUpdate {
+    /// The virtual machine scale set sku.
+    pub sku: Sku,
+    /// Resource tags.
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineScaleSetVmInstanceIds {
+    /// The virtual machine scale set instance ids.
+    pub instance_ids: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedCluster {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// Properties of a managed cluster.
+    #[serde(default)]
+    pub properties: ManagedClusterProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedClusterProperties {
+    /// The current provisioning state.
+    pub provisioning_state: Option<String>,
+    /// The Power State of the cluster.
+    pub power_state: Option<PowerState>,
+    /// The agent pool properties.
+    #[serde(default)]
+    pub agent_pool_profiles: Vec<AgentPoolProperties>,
+}
+
+/// An agent pool, keeping the fields we do not know about so that it can be
+/// sent back as is.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentPool {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// Properties of an agent pool.
+    #[serde(default)]
+    pub properties: AgentPoolProperties,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentPoolProperties {
+    /// The name of the agent pool, only set in the cluster's profiles.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Number of agents (VMs) to host docker containers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    /// The mode of an agent pool, `System` or `User`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Whether to enable auto-scaler.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_auto_scaling: Option<bool>,
+    /// The minimum number of nodes for auto-scaling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_count: Option<u64>,
+    /// The maximum number of nodes for auto-scaling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_count: Option<u64>,
+    /// The current deployment or provisioning state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provisioning_state: Option<String>,
+    /// Whether the Agent Pool is Running or Stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_state: Option<PowerState>,
+    /// The tags to be persisted on the agent pool virtual machine scale set.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerState {
+    /// Tells whether the cluster is Running or Stopped.
+    pub code: String,
+}
+
+pub const POWER_STATE_RUNNING: &str = "Running";
+pub const POWER_STATE_STOPPED: &str = "Stopped";
+
+pub const PROVISIONING_STATE_STARTING: &str = "Starting";
+pub const PROVISIONING_STATE_STOPPING: &str = "Stopping";
+pub const PROVISIONING_STATE_SCALING: &str = "Scaling";
+
+pub const AGENT_POOL_MODE_USER: &str = "User";
+
+/// A PostgreSQL or MySQL flexible server.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlexibleServer {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// Properties of the server.
+    #[serde(default)]
+    pub properties: FlexibleServerProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlexibleServerProperties {
+    /// The state of the server.
+    pub state: Option<String>,
+}
+
+pub const SERVER_STATE_READY: &str = "Ready";
+pub const SERVER_STATE_STOPPED: &str = "Stopped";
+pub const SERVER_STATE_STARTING: &str = "Starting";
+pub const SERVER_STATE_STOPPING: &str = "Stopping";
+pub const SERVER_STATE_UPDATING: &str = "Updating";
+
+/// A web app, or any other kind of App Service site.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Site {
+    /// Resource name.
+    pub name: String,
+    /// Resource Id.
+    pub id: String,
+    /// Site resource specific properties.
+    #[serde(default)]
+    pub properties: SiteProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteProperties {
+    /// Current state of the app.
+    pub state: Option<String>,
+}
+
+pub const SITE_STATE_RUNNING: &str = "Running";
+pub const SITE_STATE_STOPPED: &str = "Stopped";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceProvider {
+    /// The collection of provider resource types.
+    #[serde(default)]
+    pub resource_types: Vec<ProviderResourceType>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderResourceType {
+    /// The resource type.
+    pub resource_type: String,
+    /// The API versions.
+    #[serde(default)]
+    pub api_versions: Vec<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceViewStatus {
+    /// The status code.
+    pub code: String,
+}
+
+pub const STATUS_POWER_STATE_STOPPING: &str = "PowerState/stopping";
+pub const STATUS_POWER_STATE_STOPPED: &str = "PowerState/stopped";
+pub const STATUS_POWER_STATE_DEALLOCATING: &str = "PowerState/deallocating";
+pub const STATUS_POWER_STATE_DEALLOCATED: &str = "PowerState/deallocated";
+pub const STATUS_POWER_STATE_STARTING: &str = "PowerState/starting";
+pub const STATUS_POWER_STATE_RUNNING: &str = "PowerState/running";
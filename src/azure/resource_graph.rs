@@ -0,0 +1,274 @@
+//! Azure Resource Graph queries, to look up many resources in few requests.
+
+use std::collections::HashMap;
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use super::{api_version, auth, utils::ServerError, Error, Id};
+
+/// How many IDs to look up per query, to keep the queries reasonably short.
+const IDS_PER_QUERY: usize = 100;
+
+/// The maximum page size allowed by Resource Graph.
+const PAGE_SIZE: u32 = 1000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequest<'a> {
+    subscriptions: &'a [String],
+    query: &'a str,
+    options: QueryRequestOptions<'a>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryRequestOptions<'a> {
+    #[serde(rename = "$top")]
+    top: u32,
+    #[serde(rename = "$skipToken", skip_serializing_if = "Option::is_none")]
+    skip_token: Option<&'a str>,
+    result_format: &'static str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryResponse<T> {
+    /// Query output in the requested format.
+    data: Vec<T>,
+    /// When present, the value can be passed to a subsequent query call to
+    /// retrieve the next page.
+    #[serde(rename = "$skipToken")]
+    skip_token: Option<String>,
+}
+
+/// A virtual machine as projected by our queries.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachineRow {
+    pub id: String,
+    pub name: String,
+    /// The power state code, e.g. `PowerState/running`.
+    #[serde(default)]
+    pub power_state: Option<String>,
+}
+
+/// The query projecting virtual machines into [`VirtualMachineRow`]s,
+/// to be completed with extra clauses.
+pub const VIRTUAL_MACHINES_QUERY: &str = "Resources \
+    | where type =~ 'microsoft.compute/virtualmachines' \
+    | project id, name, powerState = tostring(properties.extended.instanceView.powerState.code)";
+
+/// The query projecting the instances of all scale sets into
+/// [`VirtualMachineRow`]s.
+pub const SCALE_SET_VIRTUAL_MACHINES_QUERY: &str = "ComputeResources \
+    | where type =~ 'microsoft.compute/virtualmachinescalesets/virtualmachines' \
+    | project id, name, powerState = tostring(properties.extended.instanceView.powerState.code)";
+
+/// Whether a Resource Graph query failed in a way Resource Manager may not:
+/// the service is down or throttling us, or this cloud does not offer it.
+/// Other failures, such as rejected credentials, would fail there as well.
+pub fn should_fall_back<AuthError>(err: &Error<AuthError>) -> bool {
+    match err {
+        Error::Reqwest(_) => true,
+        Error::Server(ServerError {
+            status_code, error, ..
+        }) => match status_code {
+            404 | 429 | 500..=599 => true,
+            400 => error.as_ref().is_some_and(|error| {
+                error.code == "NoRegisteredProviderFound" || error.code == "InvalidResourceType"
+            }),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Quote a string for use as a KQL string literal.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    fn build_resource_graph_url(&self) -> String {
+        format!(
+            "{endpoint}/providers/Microsoft.ResourceGraph/resources?api-version={apiVersion}",
+            endpoint = self.cloud.resource_manager_endpoint,
+            apiVersion = self.api_versions.get(api_version::RESOURCE_GRAPH),
+        )
+    }
+
+    /// Run a query over all our subscriptions, following all the pages.
+    pub(super) async fn query_resource_graph<T>(
+        &self,
+        query: &str,
+    ) -> Result<Vec<T>, Error<AuthTokenProvider::Error>>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let url = self.build_resource_graph_url();
+        let subscriptions: Vec<String> = self.subscription_ids().map(ToOwned::to_owned).collect();
+
+        let mut rows = Vec::new();
+        let mut skip_token = None;
+        loop {
+            let body = QueryRequest {
+                subscriptions: &subscriptions,
+                query,
+                options: QueryRequestOptions {
+                    top: PAGE_SIZE,
+                    skip_token: skip_token.as_deref(),
+                    result_format: "objectArray",
+                },
+            };
+            let auth_token = self.get_auth_token().await?;
+            let request = self.build_json_request(&auth_token, Method::POST, &url, &body)?;
+            let res = self.exec(request).await?;
+            let page: QueryResponse<T> = Self::parse_json(res).await?;
+
+            rows.extend(page.data);
+            skip_token = match page.skip_token {
+                Some(token) => Some(token),
+                None => break,
+            };
+        }
+
+        Ok(rows)
+    }
+
+    /// Look up virtual machines by ID, keyed by the requested IDs.
+    pub(super) async fn get_many_vms(
+        &self,
+        ids: &[(crate::core::Id, Id)],
+    ) -> Result<HashMap<crate::core::Id, VirtualMachineRow>, Error<AuthTokenProvider::Error>> {
+        let requested: HashMap<String, &crate::core::Id> = ids
+            .iter()
+            .map(|(core_id, id)| {
+                let model_id = id.to_model(&self.subscription_id);
+                (model_id.to_ascii_lowercase(), core_id)
+            })
+            .collect();
+
+        let model_ids: Vec<_> = requested.keys().collect();
+        let mut found = HashMap::with_capacity(ids.len());
+        for chunk in model_ids.chunks(IDS_PER_QUERY) {
+            let list = chunk
+                .iter()
+                .map(|id| quote(id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let query = format!("{} | where id in~ ({})", VIRTUAL_MACHINES_QUERY, list);
+
+            let rows: Vec<VirtualMachineRow> = self.query_resource_graph(&query).await?;
+            for row in rows {
+                if let Some(&id) = requested.get(&row.id.to_ascii_lowercase()) {
+                    found.insert(id.clone(), row);
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use crate::azure::{testing, ListBackend};
+
+    const OTHER_SUBSCRIPTION_ID: &str = "11111111-1111-1111-1111-111111111111";
+
+    /// Resource Graph failing with `status`, in front of a Resource Manager
+    /// listing one virtual machine per subscription.
+    fn failing_resource_graph(
+        status: StatusCode,
+        arm_lists: Arc<AtomicUsize>,
+        subscriptions: Arc<Mutex<Vec<serde_json::Value>>>,
+    ) -> Router {
+        Router::new()
+            .route(
+                "/providers/Microsoft.ResourceGraph/resources",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    subscriptions
+                        .lock()
+                        .unwrap()
+                        .push(body["subscriptions"].clone());
+                    let error = serde_json::json!({"error": {"code": "Failed"}});
+                    (status, Json(error))
+                }),
+            )
+            .route(
+                "/subscriptions/:subscription/providers/Microsoft.Compute/virtualMachines",
+                get(move |Path(subscription): Path<String>| async move {
+                    arm_lists.fetch_add(1, Ordering::SeqCst);
+                    Json(serde_json::json!({"value": [{
+                        "id": format!(
+                            "/subscriptions/{}/resourceGroups/rg/providers/Microsoft.Compute/virtualMachines/vm0",
+                            subscription
+                        ),
+                        "name": "vm0",
+                    }]}))
+                }),
+            )
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_arm_only_when_unavailable() {
+        let arm_lists = Arc::new(AtomicUsize::new(0));
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+        let app = failing_resource_graph(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Arc::clone(&arm_lists),
+            Arc::clone(&subscriptions),
+        );
+        let mut provider = testing::fake_provider(app, ListBackend::ResourceGraph);
+        provider.extra_subscription_ids = vec![OTHER_SUBSCRIPTION_ID.to_owned()];
+
+        let instances = provider.list_vms().await.unwrap();
+        let ids: Vec<_> = instances.iter().map(|instance| &instance.id).collect();
+        assert_eq!(
+            ids,
+            ["rg/vm0", &format!("{}:rg/vm0", OTHER_SUBSCRIPTION_ID)]
+        );
+        assert_eq!(arm_lists.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            *subscriptions.lock().unwrap(),
+            [serde_json::json!([
+                testing::SUBSCRIPTION_ID,
+                OTHER_SUBSCRIPTION_ID
+            ])]
+        );
+
+        let arm_lists = Arc::new(AtomicUsize::new(0));
+        let app = failing_resource_graph(
+            StatusCode::FORBIDDEN,
+            Arc::clone(&arm_lists),
+            Arc::clone(&subscriptions),
+        );
+        let provider = testing::fake_provider(app, ListBackend::ResourceGraph);
+        assert!(provider.list_vms().await.is_err());
+        assert_eq!(arm_lists.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn quote_escapes() {
+        assert_eq!(quote("vm0"), "'vm0'");
+        assert_eq!(quote(r"it's\"), r"'it\'s\\'");
+    }
+}
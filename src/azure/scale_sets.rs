@@ -0,0 +1,410 @@
+//! Virtual machine scale sets, managed both as a whole and per instance.
+
+use std::collections::HashMap;
+
+use futures::{stream, StreamExt};
+use reqwest::Method;
+use tracing::warn;
+
+use super::{api_version, auth, model, resource_graph, Error, Id, ListBackend, ScaleSetStopMode};
+
+/// The tag remembering the capacity of a scale set scaled to zero.
+const CAPACITY_TAG: &str = "vm-onoff-capacity";
+
+/// The capacity to restore when the tag is missing.
+const DEFAULT_CAPACITY: u64 = 1;
+
+/// How many scale sets have their instances listed at once through Resource
+/// Manager.
+const CONCURRENT_INSTANCE_LISTINGS: usize = 8;
+
+/// Instances keyed by the lowercased Resource Manager ID of their scale set.
+type InstancesBySet = HashMap<String, Vec<crate::core::Instance>>;
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    fn build_all_scale_sets_list_url(&self) -> String {
+        format!(
+            "{endpoint}/subscriptions/{subscriptionId}/providers/Microsoft.Compute/virtualMachineScaleSets?api-version={apiVersion}",
+            endpoint = self.cloud.resource_manager_endpoint,
+            subscriptionId = self.subscription_id,
+            apiVersion = self.api_versions.get(api_version::VIRTUAL_MACHINE_SCALE_SETS),
+        )
+    }
+
+    /// The scale set an ID is, or belongs to, along with the instance it
+    /// designates, if any.
+    fn split_scale_set_id(id: &Id) -> (Id, Option<String>) {
+        match id {
+            Id::ScaleSetInstance {
+                resource_group_name,
+                scale_set_name,
+                instance_id,
+            } => (
+                Id::ScaleSet {
+                    resource_group_name: resource_group_name.clone(),
+                    scale_set_name: scale_set_name.clone(),
+                },
+                Some(instance_id.clone()),
+            ),
+            id => (id.clone(), None),
+        }
+    }
+
+    async fn list_scale_set_vms(
+        &self,
+        id: &Id,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let url = self.build_resource_url(id, "/virtualMachines", "&$expand=instanceView");
+        let vms: Vec<model::VirtualMachine> = self.list_all(&url).await?;
+        vms.into_iter()
+            .map(|vm| self.model_to_instance(vm))
+            .collect()
+    }
+
+    /// List the instances of each scale set, a few sets at a time; the sets
+    /// whose instances cannot be listed are left out of the map.
+    async fn list_scale_sets_vms_via_arm(
+        &self,
+        sets: &[model::VirtualMachineScaleSet],
+    ) -> InstancesBySet
+    where
+        AuthTokenProvider::Error: std::error::Error,
+    {
+        let listings: Vec<_> = sets
+            .iter()
+            .map(|set| async move {
+                let vms = match Id::from_model(&set.id) {
+                    Ok(id) => self.list_scale_set_vms(&id).await,
+                    Err(err) => Err(err.into()),
+                };
+                (set, vms)
+            })
+            .collect();
+        let listings: Vec<_> = stream::iter(listings)
+            .buffer_unordered(CONCURRENT_INSTANCE_LISTINGS)
+            .collect()
+            .await;
+
+        listings
+            .into_iter()
+            .filter_map(|(set, vms)| match vms {
+                Ok(vms) => Some((set.id.to_ascii_lowercase(), vms)),
+                Err(err) => {
+                    warn!(scale_set = %set.id, "Unable to list the scale set instances, skipping them: {}", err);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// List the instances of all the scale sets in a single query.
+    async fn list_scale_sets_vms_via_resource_graph(
+        &self,
+        sets: &[model::VirtualMachineScaleSet],
+    ) -> Result<InstancesBySet, Error<AuthTokenProvider::Error>> {
+        let rows: Vec<resource_graph::VirtualMachineRow> = self
+            .query_resource_graph(resource_graph::SCALE_SET_VIRTUAL_MACHINES_QUERY)
+            .await?;
+
+        let mut vms_by_set: InstancesBySet = sets
+            .iter()
+            .map(|set| (set.id.to_ascii_lowercase(), Vec::new()))
+            .collect();
+        for row in rows {
+            let set_id = row
+                .id
+                .to_ascii_lowercase()
+                .rsplit_once("/virtualmachines/")
+                .map(|(set_id, _)| set_id.to_owned());
+            if let Some(vms) = set_id.and_then(|set_id| vms_by_set.get_mut(&set_id)) {
+                vms.push(self.row_to_instance(row)?);
+            }
+        }
+        Ok(vms_by_set)
+    }
+
+    /// The scale set itself followed by its instances, if they could be
+    /// listed; without them the state of the set is unknown.
+    fn scale_set_to_instances(
+        &self,
+        set: model::VirtualMachineScaleSet,
+        vms: Option<Vec<crate::core::Instance>>,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let (state, vms) = match (set.sku.capacity, vms) {
+            (Some(0), vms) => (crate::core::State::Off, vms.unwrap_or_default()),
+            (_, Some(vms)) => (
+                crate::core::State::aggregate(vms.iter().map(|vm| vm.state)),
+                vms,
+            ),
+            (_, None) => (crate::core::State::Other, Vec::new()),
+        };
+        let id = Id::from_model(&set.id)?;
+        let set = crate::core::Instance {
+            display_name: set.name,
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state,
+            observed_at: std::time::Instant::now(),
+        };
+
+        Ok(std::iter::once(set).chain(vms).collect())
+    }
+
+    pub(super) async fn list_scale_sets(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>>
+    where
+        AuthTokenProvider::Error: std::error::Error,
+    {
+        let url = self.build_all_scale_sets_list_url();
+        let sets: Vec<model::VirtualMachineScaleSet> = self.list_all(&url).await?;
+
+        let vms_by_set = match self.list_backend {
+            ListBackend::Arm => None,
+            ListBackend::ResourceGraph => {
+                match self.list_scale_sets_vms_via_resource_graph(&sets).await {
+                    Ok(vms_by_set) => Some(vms_by_set),
+                    Err(err) if resource_graph::should_fall_back(&err) => {
+                        warn!(
+                            "Resource Graph listing failed, falling back to ARM: {}",
+                            err
+                        );
+                        None
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        };
+        let mut vms_by_set = match vms_by_set {
+            Some(vms_by_set) => vms_by_set,
+            None => self.list_scale_sets_vms_via_arm(&sets).await,
+        };
+
+        let mut instances = Vec::new();
+        for set in sets {
+            let vms = vms_by_set.remove(&set.id.to_ascii_lowercase());
+            instances.extend(self.scale_set_to_instances(set, vms)?);
+        }
+        Ok(instances)
+    }
+
+    pub(super) async fn get_scale_set(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let set = self
+            .get_resource::<model::VirtualMachineScaleSet>(id)
+            .await?;
+        let vms = self.list_scale_set_vms(id).await?;
+        let mut instances = self.scale_set_to_instances(set, Some(vms))?;
+        Ok(instances.swap_remove(0))
+    }
+
+    async fn scale_set_action(
+        &self,
+        id: &Id,
+        action: &str,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let (set_id, instance_id) = Self::split_scale_set_id(id);
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_resource_url(&set_id, action, "");
+        let request = match instance_id {
+            Some(instance_id) => {
+                let body = model::VirtualMachineScaleSetVmInstanceIds {
+                    instance_ids: vec![instance_id],
+                };
+                self.build_json_request(&auth_token, Method::POST, &url, &body)?
+            }
+            None => self.build_request(&auth_token, Method::POST, &url)?,
+        };
+        self.exec(request).await?;
+        Ok(())
+    }
+
+    async fn update_scale_set(
+        &self,
+        id: &Id,
+        update: &model::VirtualMachineScaleSetUpdate,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_resource_url(id, "", "");
+        self.exec(self.build_json_request(&auth_token, Method::PATCH, &url, update)?)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn start_scale_set(
+        &self,
+        id: &Id,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        if matches!(id, Id::ScaleSet { .. })
+            && self.scale_set_stop_mode == ScaleSetStopMode::ScaleToZero
+        {
+            let set = self
+                .get_resource::<model::VirtualMachineScaleSet>(id)
+                .await?;
+            if set.sku.capacity == Some(0) {
+                let mut tags = set.tags;
+                let capacity = tags
+                    .remove(CAPACITY_TAG)
+                    .and_then(|capacity| capacity.parse().ok())
+                    .unwrap_or(DEFAULT_CAPACITY);
+                let update = model::VirtualMachineScaleSetUpdate {
+                    sku: model::Sku {
+                        name: set.sku.name,
+                        capacity: Some(capacity),
+                    },
+                    tags,
+                };
+                return self.update_scale_set(id, &update).await;
+            }
+        }
+
+        self.scale_set_action(id, "/start").await
+    }
+
+    pub(super) async fn stop_scale_set(
+        &self,
+        id: &Id,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        if matches!(id, Id::ScaleSet { .. })
+            && self.scale_set_stop_mode == ScaleSetStopMode::ScaleToZero
+        {
+            let set = self
+                .get_resource::<model::VirtualMachineScaleSet>(id)
+                .await?;
+            let capacity = set.sku.capacity.unwrap_or(0);
+            if capacity == 0 {
+                return Ok(());
+            }
+            let mut tags = set.tags;
+            tags.insert(CAPACITY_TAG.to_owned(), capacity.to_string());
+            let update = model::VirtualMachineScaleSetUpdate {
+                sku: model::Sku {
+                    name: set.sku.name,
+                    capacity: Some(0),
+                },
+                tags,
+            };
+            return self.update_scale_set(id, &update).await;
+        }
+
+        self.scale_set_action(id, "/deallocate").await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::azure::testing;
+
+    fn set_id(name: &str) -> String {
+        format!(
+            "/subscriptions/{}/resourceGroups/rg/providers/Microsoft.Compute/virtualMachineScaleSets/{}",
+            testing::SUBSCRIPTION_ID,
+            name
+        )
+    }
+
+    /// Two scale sets, the instances of the second of which cannot be listed
+    /// through Resource Manager.
+    fn scale_sets(arm_listings: Arc<AtomicUsize>) -> Router {
+        Router::new()
+            .route(
+                "/subscriptions/:subscription/providers/Microsoft.Compute/virtualMachineScaleSets",
+                get(|| async {
+                    Json(serde_json::json!({"value": [
+                        {"id": set_id("set0"), "name": "set0", "sku": {"capacity": 1}},
+                        {"id": set_id("set1"), "name": "set1", "sku": {"capacity": 1}},
+                    ]}))
+                }),
+            )
+            .route(
+                "/subscriptions/:subscription/resourceGroups/rg/providers/Microsoft.Compute/virtualMachineScaleSets/:name/virtualMachines",
+                get(move |Path((_, name)): Path<(String, String)>| async move {
+                    arm_listings.fetch_add(1, Ordering::SeqCst);
+                    if name == "set1" {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(serde_json::json!({"error": {"code": "InternalServerError"}})),
+                        );
+                    }
+                    let vms = Json(serde_json::json!({"value": [{
+                        "id": format!("{}/virtualMachines/0", set_id(&name)),
+                        "name": format!("{}_0", name),
+                        "properties": {"instanceView": {"statuses": [{"code": "PowerState/running"}]}},
+                    }]}));
+                    (StatusCode::OK, vms)
+                }),
+            )
+            .route(
+                "/providers/Microsoft.ResourceGraph/resources",
+                post(|| async {
+                    let rows: Vec<_> = ["set0", "set1"]
+                        .iter()
+                        .map(|name| {
+                            serde_json::json!({
+                                "id": format!("{}/virtualMachines/0", set_id(name)),
+                                "name": format!("{}_0", name),
+                                "powerState": "PowerState/deallocated",
+                            })
+                        })
+                        .collect();
+                    Json(serde_json::json!({ "data": rows }))
+                }),
+            )
+    }
+
+    #[tokio::test]
+    async fn keeps_scale_sets_whose_instances_cannot_be_listed() {
+        let arm_listings = Arc::new(AtomicUsize::new(0));
+        let provider =
+            testing::fake_provider(scale_sets(Arc::clone(&arm_listings)), ListBackend::Arm);
+
+        let instances = provider.list_scale_sets().await.unwrap();
+        let ids: Vec<_> = instances.iter().map(|instance| &instance.id).collect();
+        assert_eq!(ids, ["rg/vmss/set0", "rg/vmss/set0/0", "rg/vmss/set1"]);
+        assert_eq!(instances[2].state, crate::core::State::Other);
+        assert_eq!(arm_listings.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lists_scale_set_instances_in_one_query() {
+        let arm_listings = Arc::new(AtomicUsize::new(0));
+        let provider = testing::fake_provider(
+            scale_sets(Arc::clone(&arm_listings)),
+            ListBackend::ResourceGraph,
+        );
+
+        let instances = provider.list_scale_sets().await.unwrap();
+        let ids: Vec<_> = instances.iter().map(|instance| &instance.id).collect();
+        assert_eq!(
+            ids,
+            [
+                "rg/vmss/set0",
+                "rg/vmss/set0/0",
+                "rg/vmss/set1",
+                "rg/vmss/set1/0"
+            ]
+        );
+        assert!(instances
+            .iter()
+            .all(|instance| instance.state == crate::core::State::Off));
+        assert_eq!(arm_listings.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,45 @@
+//! A provider talking to a fake Resource Manager, for tests.
+
+use std::net::SocketAddr;
+
+use axum::Router;
+
+use super::{auth, ApiVersions, Cloud, ListBackend, Provider, ResourceKind, ScaleSetStopMode};
+
+pub const SUBSCRIPTION_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+pub struct StaticToken;
+
+#[async_trait::async_trait]
+impl auth::TokenProvider for StaticToken {
+    type Token = auth::client_credentials::Token;
+    type Error = std::convert::Infallible;
+
+    async fn get_auth_token(&self) -> Result<Self::Token, Self::Error> {
+        Ok(auth::client_credentials::Token {
+            access_token: "token".to_owned(),
+            expires_at: std::time::Instant::now(),
+        })
+    }
+}
+
+/// Serve `app` on a local port, and return a provider using it as the
+/// Resource Manager endpoint.
+pub fn fake_provider(app: Router, list_backend: ListBackend) -> Provider<StaticToken> {
+    let server =
+        axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    Provider {
+        client: reqwest::Client::new(),
+        cloud: Cloud::custom(&format!("http://{}", addr), "http://login.invalid"),
+        api_versions: ApiVersions::default(),
+        list_backend,
+        resource_kinds: ResourceKind::ALL.to_vec(),
+        scale_set_stop_mode: ScaleSetStopMode::Deallocate,
+        subscription_id: SUBSCRIPTION_ID.to_owned(),
+        extra_subscription_ids: Vec::new(),
+        auth_token_provider: StaticToken,
+    }
+}
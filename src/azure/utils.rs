@@ -1,15 +1,64 @@
+use serde::Deserialize;
+
 #[derive(Debug, thiserror::Error)]
 #[error("{status_code} status code")]
 pub struct ServerError {
     pub status_code: u16,
+    /// The `x-ms-request-id` of the failed request, if present.
+    pub request_id: Option<String>,
+    /// The ARM `error` object, whose `code` (such as `ResourceNotFound`) is what
+    /// callers match on; gateway timeouts come without one.
+    pub error: Option<ErrorDetail>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ErrorDetail {
+    /// The error code.
+    pub code: String,
+    /// The error message.
+    pub message: Option<String>,
 }
 
-pub fn check_status(res: &reqwest::Response) -> Result<(), ServerError> {
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+const REQUEST_ID_HEADER: &str = "x-ms-request-id";
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
     let status = res.status();
-    if !status.is_success() {
-        return Err(ServerError {
-            status_code: status.as_u16(),
-        });
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let request_id = res
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|val| val.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let error = match res.bytes().await {
+        Ok(body) => serde_json::from_slice::<ErrorResponse>(&body)
+            .ok()
+            .map(|res| res.error),
+        Err(_) => None,
+    };
+
+    Err(ServerError {
+        status_code: status.as_u16(),
+        request_id,
+        error,
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.error.as_ref().map(|error| error.code.clone()),
+            message: err.error.as_ref().and_then(|error| error.message.clone()),
+            request_id: err.request_id.clone(),
+        }
     }
-    Ok(())
 }
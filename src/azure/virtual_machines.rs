@@ -0,0 +1,142 @@
+//! Plain virtual machines.
+
+use reqwest::Method;
+
+use super::{api_version, auth, model, resource_graph, Error, Id};
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    fn build_all_vms_list_url(&self, subscription_id: &str) -> String {
+        format!(
+            "{endpoint}/subscriptions/{subscriptionId}/providers/Microsoft.Compute/virtualMachines?api-version={apiVersion}&statusOnly=true",
+            endpoint = self.cloud.resource_manager_endpoint,
+            apiVersion = self.api_versions.get(api_version::VIRTUAL_MACHINES),
+            subscriptionId = subscription_id,
+        )
+    }
+
+    pub(super) async fn start_vm(&self, id: &Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+        self.post_action(id, "/start").await
+    }
+
+    pub(super) async fn stop_vm(&self, id: &Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+        self.post_action(id, "/deallocate").await
+    }
+
+    /// Get a virtual machine, or a scale set instance, with its instance view.
+    pub(super) async fn get_vm(
+        &self,
+        id: &Id,
+    ) -> Result<model::VirtualMachine, Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_resource_url(id, "", "&$expand=instanceView");
+        let res = self
+            .exec(self.build_request(&auth_token, Method::GET, &url)?)
+            .await?;
+
+        let vm = Self::parse_json(res).await?;
+        Ok(vm)
+    }
+
+    pub(super) async fn list_vms_via_arm(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let mut instances = Vec::new();
+        for subscription_id in self.subscription_ids() {
+            let url = self.build_all_vms_list_url(subscription_id);
+            let vms = self.list_all(&url).await?;
+            for vm in vms {
+                instances.push(self.model_to_instance(vm)?);
+            }
+        }
+        Ok(instances)
+    }
+
+    pub(super) async fn list_vms_via_resource_graph(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let rows = self
+            .query_resource_graph(resource_graph::VIRTUAL_MACHINES_QUERY)
+            .await?;
+        rows.into_iter()
+            .map(|row| self.row_to_instance(row))
+            .collect()
+    }
+
+    pub(super) fn model_to_instance(
+        &self,
+        vm: model::VirtualMachine,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let name = vm.name;
+        let id = Id::from_model(&vm.id)?.relative_to(&self.subscription_id);
+        let state = Self::detect_state(&vm.properties.instance_view.statuses);
+
+        Ok(crate::core::Instance {
+            display_name: name,
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state,
+            observed_at: std::time::Instant::now(),
+        })
+    }
+
+    pub(super) fn row_to_instance(
+        &self,
+        row: resource_graph::VirtualMachineRow,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let id = Id::from_model(&row.id)?.relative_to(&self.subscription_id);
+        let statuses: Vec<_> = row
+            .power_state
+            .into_iter()
+            .map(|code| model::InstanceViewStatus { code })
+            .collect();
+        let state = Self::detect_state(&statuses);
+
+        Ok(crate::core::Instance {
+            display_name: row.name,
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state,
+            observed_at: std::time::Instant::now(),
+        })
+    }
+
+    fn detect_state(statuses: &[model::InstanceViewStatus]) -> crate::core::State {
+        let is_found = |code: &str| statuses.iter().any(|status| status.code == code);
+
+        let is_stopped = is_found(model::STATUS_POWER_STATE_STOPPED);
+        let is_deallocated = is_found(model::STATUS_POWER_STATE_DEALLOCATED);
+        let is_off = is_stopped || is_deallocated;
+
+        let is_running = is_found(model::STATUS_POWER_STATE_RUNNING);
+        let is_on = is_running;
+
+        let is_stopping = is_found(model::STATUS_POWER_STATE_STOPPING);
+        let is_deallocing = is_found(model::STATUS_POWER_STATE_DEALLOCATING);
+        let is_starting = is_found(model::STATUS_POWER_STATE_STARTING);
+        let is_in_progress = is_stopping || is_deallocing || is_starting;
+
+        match (is_on, is_off, is_in_progress) {
+            (true, false, false) => crate::core::State::On,
+            (false, true, false) => crate::core::State::Off,
+            (false, false, true) => crate::core::State::InProgress,
+            (_, _, _) => crate::core::State::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_tolerates_unknown_and_missing_fields() {
+        let vm: model::VirtualMachine = serde_json::from_str(
+            r#"{"name":"vm0","id":"/x","location":"westeurope","properties":{"hibernationEnabled":true}}"#,
+        )
+        .unwrap();
+        assert!(vm.properties.instance_view.statuses.is_empty());
+    }
+}
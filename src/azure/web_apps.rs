@@ -0,0 +1,43 @@
+//! Web apps, and the other kinds of App Service sites.
+//!
+//! Stopping an app stops it from serving requests, but its App Service plan
+//! keeps being billed.
+
+use super::{api_version, auth, model, Error, Id};
+
+impl<AuthTokenProvider> super::Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    pub(super) async fn list_web_apps(
+        &self,
+    ) -> Result<Vec<crate::core::Instance>, Error<AuthTokenProvider::Error>> {
+        let sites: Vec<model::Site> = self.list_subscription_resources(api_version::SITES).await?;
+        sites.into_iter().map(Self::site_to_instance).collect()
+    }
+
+    pub(super) async fn get_web_app(
+        &self,
+        id: &Id,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        Self::site_to_instance(self.get_resource(id).await?)
+    }
+
+    fn site_to_instance(
+        site: model::Site,
+    ) -> Result<crate::core::Instance, Error<AuthTokenProvider::Error>> {
+        let id = Id::from_model(&site.id)?;
+        let state = match site.properties.state.as_deref() {
+            Some(model::SITE_STATE_RUNNING) => crate::core::State::On,
+            Some(model::SITE_STATE_STOPPED) => crate::core::State::Off,
+            _ => crate::core::State::Other,
+        };
+        Ok(crate::core::Instance {
+            display_name: site.name,
+            resource_type: id.arm_type().to_owned(),
+            id: id.into(),
+            state,
+            observed_at: std::time::Instant::now(),
+        })
+    }
+}
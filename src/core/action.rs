@@ -0,0 +1,80 @@
+//! Waiting for the long-running actions that some backends start instead of
+//! acting right away, such as Proxmox tasks or cloud actions.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+/// The status of an action, as reported when polled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionStatus {
+    Running,
+    Succeeded,
+    /// The action failed, for the given reason.
+    Failed(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum WaitError<E> {
+    /// Polling the action failed.
+    Poll(E),
+    /// The action failed, for the given reason.
+    Failed(String),
+    /// The action was still running when the timeout elapsed.
+    Timeout,
+}
+
+/// Poll an action until it completes, or until `timeout` elapses; it is
+/// polled right away, then every `interval`.
+pub async fn wait_for<F, Fut, E>(
+    interval: Duration,
+    timeout: Duration,
+    mut poll: F,
+) -> Result<(), WaitError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<ActionStatus, E>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        match poll().await.map_err(WaitError::Poll)? {
+            ActionStatus::Running => {}
+            ActionStatus::Succeeded => return Ok(()),
+            ActionStatus::Failed(reason) => return Err(WaitError::Failed(reason)),
+        }
+        if Instant::now() >= deadline {
+            return Err(WaitError::Timeout);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_until_action_completes() {
+        let mut polls = 0;
+        let result: Result<(), WaitError<()>> =
+            wait_for(Duration::from_millis(1), Duration::from_secs(1), || {
+                polls += 1;
+                let status = match polls {
+                    1 | 2 => ActionStatus::Running,
+                    _ => ActionStatus::Failed("disk full".to_owned()),
+                };
+                async move { Ok(status) }
+            })
+            .await;
+        assert_eq!(result, Err(WaitError::Failed("disk full".to_owned())));
+        assert_eq!(polls, 3);
+
+        let result: Result<(), WaitError<()>> =
+            wait_for(Duration::from_millis(1), Duration::ZERO, || async {
+                Ok(ActionStatus::Running)
+            })
+            .await;
+        assert_eq!(result, Err(WaitError::Timeout));
+    }
+}
@@ -0,0 +1,429 @@
+//! Inventory cache layered around a provider.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+
+use super::{health::Health, limit::QueueStats, Capabilities, Id, IdRef, Instance, Provider};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// How long a listing is served without being refreshed.
+    pub ttl: Duration,
+    /// How long an expired listing may still be served while it is being
+    /// refreshed in the background; past this, reads wait for the backend.
+    pub max_stale: Duration,
+}
+
+/// A provider that keeps the last `list` result around.
+///
+/// Instances are invalidated individually after a `start` or a `stop`, and
+/// re-read from the backend on the next access.
+pub struct Cached<P> {
+    inner: Arc<P>,
+    config: CacheConfig,
+    state: Arc<Mutex<CacheState>>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    snapshot: Option<Snapshot>,
+    refreshing: bool,
+    /// When the whole cache was last invalidated, so that listings started
+    /// earlier are not stored.
+    cleared_at: Option<Instant>,
+}
+
+struct Snapshot {
+    fetched_at: Instant,
+    instances: Vec<Instance>,
+    index: HashMap<Id, usize>,
+    /// Instances invalidated since the listing, with the time they were.
+    stale: HashMap<Id, Instant>,
+}
+
+impl Snapshot {
+    fn new(fetched_at: Instant, instances: Vec<Instance>) -> Self {
+        let mut snapshot = Self {
+            fetched_at,
+            instances,
+            index: HashMap::new(),
+            stale: HashMap::new(),
+        };
+        snapshot.reindex();
+        snapshot
+    }
+
+    fn reindex(&mut self) {
+        self.index = self
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(idx, instance)| (instance.id.clone(), idx))
+            .collect();
+    }
+
+    fn lookup(&self, id: &IdRef) -> Option<&Instance> {
+        if self.stale.contains_key(id) {
+            return None;
+        }
+        self.index.get(id).map(|&idx| &self.instances[idx])
+    }
+
+    /// Replace what we know about the given instances with data fetched
+    /// from `started_at`, keeping their place in the listing, unless they
+    /// were invalidated since.
+    fn patch(&mut self, ids: &[Id], mut fresh: HashMap<Id, Instance>, started_at: Instant) {
+        let mut gone = HashSet::new();
+        for id in ids {
+            if matches!(self.stale.get(id), Some(invalidated_at) if *invalidated_at >= started_at) {
+                continue;
+            }
+            self.stale.remove(id);
+            match (self.index.get(id), fresh.remove(id)) {
+                (Some(&idx), Some(instance)) => self.instances[idx] = instance,
+                (None, Some(instance)) => {
+                    self.index.insert(id.clone(), self.instances.len());
+                    self.instances.push(instance);
+                }
+                (Some(_), None) => {
+                    gone.insert(id);
+                }
+                (None, None) => {}
+            }
+        }
+        if !gone.is_empty() {
+            self.instances
+                .retain(|instance| !gone.contains(&instance.id));
+            self.reindex();
+        }
+    }
+}
+
+impl<P> Cached<P>
+where
+    P: Provider + 'static,
+{
+    pub fn new(inner: P, config: CacheConfig) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    fn lock(state: &Mutex<CacheState>) -> MutexGuard<'_, CacheState> {
+        state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// The snapshot if it can be served, scheduling a background refresh when
+    /// it has expired.
+    fn usable_snapshot<'a>(&self, state: &'a mut CacheState) -> Option<&'a Snapshot> {
+        let age = state.snapshot.as_ref()?.fetched_at.elapsed();
+        if age > self.config.max_stale {
+            return None;
+        }
+        if age > self.config.ttl && !state.refreshing {
+            state.refreshing = true;
+            self.spawn_refresh();
+        }
+        state.snapshot.as_ref()
+    }
+
+    fn spawn_refresh(&self) {
+        let inner = Arc::clone(&self.inner);
+        let state = Arc::clone(&self.state);
+        tokio::spawn(async move {
+            if let Err(err) = Self::refresh(&inner, &state).await {
+                warn!("Background inventory refresh failed: {:#}", err);
+            }
+            Self::lock(&state).refreshing = false;
+        });
+    }
+
+    async fn refresh(inner: &P, state: &Mutex<CacheState>) -> Result<Vec<Instance>, anyhow::Error> {
+        let started_at = Instant::now();
+        let instances = inner.list().await?;
+
+        let mut state = Self::lock(state);
+        let cleared = matches!(state.cleared_at, Some(cleared_at) if cleared_at >= started_at);
+        if !cleared {
+            // Keep the invalidations that happened while we were listing, as
+            // the listing may predate them.
+            let stale = state
+                .snapshot
+                .take()
+                .map(|snapshot| snapshot.stale)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(_, invalidated_at)| *invalidated_at >= started_at)
+                .collect();
+            let mut snapshot = Snapshot::new(started_at, instances.clone());
+            snapshot.stale = stale;
+            state.snapshot = Some(snapshot);
+        }
+
+        Ok(instances)
+    }
+
+    /// Store instances fetched from `started_at`, unless the cache was
+    /// invalidated since.
+    fn store(&self, ids: &[Id], fresh: &HashMap<Id, Instance>, started_at: Instant) {
+        let mut state = Self::lock(&self.state);
+        if matches!(state.cleared_at, Some(cleared_at) if cleared_at >= started_at) {
+            return;
+        }
+        if let Some(snapshot) = &mut state.snapshot {
+            snapshot.patch(ids, fresh.clone(), started_at);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> Provider for Cached<P>
+where
+    P: Provider + 'static,
+{
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+        let stale_ids: Vec<Id> = {
+            let mut state = Self::lock(&self.state);
+            match self.usable_snapshot(&mut state) {
+                Some(snapshot) if snapshot.stale.is_empty() => {
+                    return Ok(snapshot.instances.clone())
+                }
+                Some(snapshot) => snapshot.stale.keys().cloned().collect(),
+                None => Vec::new(),
+            }
+        };
+
+        if stale_ids.is_empty() {
+            return Self::refresh(&self.inner, &self.state).await;
+        }
+
+        let started_at = Instant::now();
+        let fresh = self.inner.get_many(&stale_ids).await?;
+        {
+            let mut state = Self::lock(&self.state);
+            if let Some(snapshot) = &mut state.snapshot {
+                snapshot.patch(&stale_ids, fresh, started_at);
+                return Ok(snapshot.instances.clone());
+            }
+        }
+        // Invalidated entirely in the meantime.
+        Self::refresh(&self.inner, &self.state).await
+    }
+
+    async fn get(&self, id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+        {
+            let mut state = Self::lock(&self.state);
+            let cached = self
+                .usable_snapshot(&mut state)
+                .and_then(|snapshot| snapshot.lookup(id));
+            if let Some(instance) = cached {
+                return Ok(Some(instance.clone()));
+            }
+        }
+
+        let started_at = Instant::now();
+        let instance = self.inner.get(id).await?;
+        let id = id.to_owned();
+        let fresh = instance
+            .iter()
+            .map(|instance| (id.clone(), instance.clone()))
+            .collect();
+        self.store(&[id], &fresh, started_at);
+        Ok(instance)
+    }
+
+    async fn get_many(&self, ids: &[Id]) -> Result<HashMap<Id, Instance>, anyhow::Error> {
+        let mut found = HashMap::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        {
+            let mut state = Self::lock(&self.state);
+            let snapshot = self.usable_snapshot(&mut state);
+            for id in ids {
+                match snapshot.and_then(|snapshot| snapshot.lookup(id)) {
+                    Some(instance) => {
+                        found.insert(id.clone(), instance.clone());
+                    }
+                    None => missing.push(id.clone()),
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let started_at = Instant::now();
+            let fresh = self.inner.get_many(&missing).await?;
+            self.store(&missing, &fresh, started_at);
+            found.extend(fresh);
+        }
+
+        Ok(found)
+    }
+
+    async fn start(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        let result = self.inner.start(id).await;
+        self.invalidate(Some(id));
+        result
+    }
+
+    async fn stop(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        let result = self.inner.stop(id).await;
+        self.invalidate(Some(id));
+        result
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.inner.check_health().await
+    }
+
+    fn health(&self) -> Option<Health> {
+        self.inner.health()
+    }
+
+    fn queue_stats(&self) -> Option<QueueStats> {
+        self.inner.queue_stats()
+    }
+
+    fn invalidate(&self, id: Option<&IdRef>) {
+        self.inner.invalidate(id);
+
+        let mut state = Self::lock(&self.state);
+        match id {
+            Some(id) => {
+                if let Some(snapshot) = &mut state.snapshot {
+                    snapshot.stale.insert(id.to_owned(), Instant::now());
+                }
+            }
+            None => {
+                state.snapshot = None;
+                state.cleared_at = Some(Instant::now());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::core::State;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct CountingProvider {
+        lists: AtomicUsize,
+        gets: AtomicUsize,
+        stopped: Mutex<HashSet<Id>>,
+    }
+
+    impl CountingProvider {
+        fn instance(&self, id: &IdRef) -> Instance {
+            let stopped = self.stopped.lock().unwrap().contains(id);
+            Instance {
+                id: id.to_owned(),
+                display_name: id.to_owned(),
+                resource_type: "test".to_owned(),
+                state: if stopped { State::Off } else { State::On },
+                observed_at: Instant::now(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for CountingProvider {
+        async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+            self.lists.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![self.instance("a"), self.instance("b")])
+        }
+
+        async fn get(&self, id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            Ok(Some(self.instance(id)))
+        }
+
+        async fn start(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+            self.stopped.lock().unwrap().remove(id);
+            Ok(())
+        }
+
+        async fn stop(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+            self.stopped.lock().unwrap().insert(id.to_owned());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_listing_and_refetches_invalidated_instances() {
+        let cached = Cached::new(
+            CountingProvider::default(),
+            CacheConfig {
+                ttl: Duration::from_secs(60),
+                max_stale: Duration::from_secs(120),
+            },
+        );
+
+        cached.list().await.unwrap();
+        cached.list().await.unwrap();
+        cached.get("a").await.unwrap();
+        assert_eq!(cached.inner.lists.load(Ordering::SeqCst), 1);
+        assert_eq!(cached.inner.gets.load(Ordering::SeqCst), 0);
+
+        cached.stop("a").await.unwrap();
+        let instance = cached.get("a").await.unwrap().unwrap();
+        assert_eq!(instance.state, State::Off);
+        assert_eq!(cached.inner.gets.load(Ordering::SeqCst), 1);
+
+        cached.invalidate(None);
+        cached.list().await.unwrap();
+        assert_eq!(cached.inner.lists.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn patches_in_place_unless_invalidated_since() {
+        let provider = CountingProvider::default();
+        let listed_at = Instant::now();
+        let mut snapshot = Snapshot::new(
+            listed_at,
+            vec![
+                provider.instance("a"),
+                provider.instance("b"),
+                provider.instance("c"),
+            ],
+        );
+
+        let started_at = Instant::now();
+        snapshot.stale.insert("b".to_owned(), Instant::now());
+        provider
+            .stopped
+            .lock()
+            .unwrap()
+            .extend(["a".to_owned(), "b".to_owned()]);
+        let ids = ["a".to_owned(), "b".to_owned()];
+        let fresh = ids
+            .iter()
+            .map(|id| (id.clone(), provider.instance(id)))
+            .collect();
+        snapshot.patch(&ids, fresh, started_at);
+
+        let listed: Vec<_> = snapshot
+            .instances
+            .iter()
+            .map(|instance| (instance.id.as_str(), instance.state))
+            .collect();
+        assert_eq!(
+            listed,
+            [("a", State::Off), ("b", State::On), ("c", State::On)]
+        );
+        assert!(snapshot.lookup("b").is_none());
+    }
+}
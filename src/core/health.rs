@@ -0,0 +1,408 @@
+//! Health tracking and circuit breaking layered around a provider.
+//!
+//! Every call and periodic probe is recorded. After too many consecutive
+//! failures that point at the backend itself (it is unreachable or rejects
+//! our credentials), the circuit opens and calls fail right away instead of
+//! each waiting for the backend to time out. Once `open_duration` has
+//! elapsed, a single trial call, or probe, is let through: the circuit closes
+//! if it succeeds and opens again if it fails the same way, while other
+//! failures let another trial through.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use super::{
+    limit::QueueStats, Capabilities, ErrorKind, Id, IdRef, Instance, Provider, ProviderError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthConfig {
+    /// How often the backend is probed, if at all.
+    pub probe_interval: Option<Duration>,
+    /// How long a probe may take before it counts as a failure.
+    pub probe_timeout: Duration,
+    /// How many consecutive failures open the circuit.
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a trial call is let through.
+    pub open_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls fail right away.
+    Open,
+    /// A trial call is deciding whether to close the circuit again.
+    HalfOpen,
+}
+
+/// What is known of the health of a backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<Instant>,
+    /// When the last failure happened, and what kind it was.
+    pub last_failure: Option<(Instant, ErrorKind)>,
+    /// How long the last call or probe took.
+    pub latency: Option<Duration>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("the circuit is open after repeated failures")]
+pub struct CircuitOpen;
+
+#[derive(Debug, thiserror::Error)]
+#[error("the health probe timed out")]
+struct ProbeTimeout;
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { trial_started_at: Instant },
+}
+
+struct Breaker {
+    config: HealthConfig,
+    circuit: Circuit,
+    consecutive_failures: u32,
+    last_success: Option<Instant>,
+    last_failure: Option<(Instant, ErrorKind)>,
+    latency: Option<Duration>,
+}
+
+impl Breaker {
+    fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            circuit: Circuit::Closed,
+            consecutive_failures: 0,
+            last_success: None,
+            last_failure: None,
+            latency: None,
+        }
+    }
+
+    /// Whether a call may go through now.
+    fn admit(&mut self, now: Instant) -> bool {
+        match self.circuit {
+            Circuit::Closed => true,
+            Circuit::Open { until } if now < until => false,
+            // A trial that never reported back, e.g. because the caller went
+            // away, does not keep the circuit half-open forever.
+            Circuit::HalfOpen { trial_started_at }
+                if now < trial_started_at + self.config.open_duration =>
+            {
+                false
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                self.circuit = Circuit::HalfOpen {
+                    trial_started_at: now,
+                };
+                true
+            }
+        }
+    }
+
+    /// Record the outcome of a call, returning whether the circuit opened or
+    /// closed as a result.
+    fn record(&mut self, now: Instant, latency: Duration, failure: Option<ErrorKind>) -> bool {
+        self.latency = Some(latency);
+        let was_closed = matches!(self.circuit, Circuit::Closed);
+        match failure {
+            Some(kind) if trips(kind) => {
+                self.consecutive_failures += 1;
+                self.last_failure = Some((now, kind));
+                if !was_closed || self.consecutive_failures >= self.config.failure_threshold {
+                    self.circuit = Circuit::Open {
+                        until: now + self.config.open_duration,
+                    };
+                }
+            }
+            // The backend answered, but that is no success: the failures
+            // still add up, and a trial has to be tried again.
+            Some(_) => {
+                if let Circuit::HalfOpen { .. } = self.circuit {
+                    self.circuit = Circuit::Open { until: now };
+                }
+            }
+            None => {
+                self.consecutive_failures = 0;
+                self.last_success = Some(now);
+                self.circuit = Circuit::Closed;
+            }
+        }
+        was_closed != matches!(self.circuit, Circuit::Closed)
+    }
+
+    fn health(&self) -> Health {
+        Health {
+            circuit: match self.circuit {
+                Circuit::Closed => CircuitState::Closed,
+                Circuit::Open { .. } => CircuitState::Open,
+                Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+            },
+            consecutive_failures: self.consecutive_failures,
+            last_success: self.last_success,
+            last_failure: self.last_failure,
+            latency: self.latency,
+        }
+    }
+}
+
+/// Whether a failure says something about the backend as a whole, rather than
+/// about the request.
+fn trips(kind: ErrorKind) -> bool {
+    matches!(kind, ErrorKind::Unavailable | ErrorKind::Unauthorized)
+}
+
+fn error_kind(err: &anyhow::Error) -> ErrorKind {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<ProviderError>())
+        .map_or(ErrorKind::Other, |err| err.kind)
+}
+
+fn unavailable(err: impl std::error::Error + Send + Sync + 'static) -> anyhow::Error {
+    ProviderError {
+        kind: ErrorKind::Unavailable,
+        details: None,
+        source: Box::new(err),
+    }
+    .into()
+}
+
+struct Shared<P> {
+    /// The provider key, for the logs.
+    name: String,
+    inner: P,
+    breaker: Mutex<Breaker>,
+}
+
+impl<P> Shared<P>
+where
+    P: Provider,
+{
+    fn lock(&self) -> MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    async fn call<T, F>(&self, call: F) -> Result<T, anyhow::Error>
+    where
+        F: Future<Output = Result<T, anyhow::Error>>,
+    {
+        if !self.lock().admit(Instant::now()) {
+            return Err(unavailable(CircuitOpen));
+        }
+
+        let started_at = Instant::now();
+        let result = call.await;
+        let failure = result.as_ref().err().map(error_kind);
+
+        let mut breaker = self.lock();
+        if breaker.record(Instant::now(), started_at.elapsed(), failure) {
+            match (breaker.circuit, &result) {
+                (Circuit::Closed, _) => info!(provider = %self.name, "Circuit closed"),
+                (_, Err(err)) => warn!(provider = %self.name, "Circuit opened: {:#}", err),
+                (_, Ok(_)) => {}
+            }
+        }
+        result
+    }
+
+    async fn probe(&self) -> Result<(), anyhow::Error> {
+        let timeout = self.lock().config.probe_timeout;
+        self.call(async {
+            tokio::time::timeout(timeout, self.inner.check_health())
+                .await
+                .map_err(|_| unavailable(ProbeTimeout))?
+        })
+        .await
+    }
+}
+
+/// A provider whose calls go through a circuit breaker, and whose backend is
+/// probed in the background.
+pub struct Monitored<P> {
+    shared: Arc<Shared<P>>,
+}
+
+impl<P> Monitored<P>
+where
+    P: Provider + 'static,
+{
+    pub fn new(name: String, inner: P, config: HealthConfig) -> Self {
+        let shared = Arc::new(Shared {
+            name,
+            inner,
+            breaker: Mutex::new(Breaker::new(config)),
+        });
+        if let Some(interval) = config.probe_interval {
+            tokio::spawn(Self::probe_periodically(Arc::downgrade(&shared), interval));
+        }
+        Self { shared }
+    }
+
+    /// Probe the backend until the provider is dropped.
+    async fn probe_periodically(shared: Weak<Shared<P>>, interval: Duration) {
+        while let Some(shared) = shared.upgrade() {
+            if let Err(err) = shared.probe().await {
+                warn!(provider = %shared.name, "Health probe failed: {:#}", err);
+            }
+            drop(shared);
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> Provider for Monitored<P>
+where
+    P: Provider + 'static,
+{
+    fn capabilities(&self) -> Capabilities {
+        self.shared.inner.capabilities()
+    }
+
+    async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+        self.shared.call(self.shared.inner.list()).await
+    }
+
+    async fn get(&self, id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+        self.shared.call(self.shared.inner.get(id)).await
+    }
+
+    async fn get_many(&self, ids: &[Id]) -> Result<HashMap<Id, Instance>, anyhow::Error> {
+        self.shared.call(self.shared.inner.get_many(ids)).await
+    }
+
+    async fn start(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        self.shared.call(self.shared.inner.start(id)).await
+    }
+
+    async fn stop(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        self.shared.call(self.shared.inner.stop(id)).await
+    }
+
+    fn invalidate(&self, id: Option<&IdRef>) {
+        self.shared.inner.invalidate(id);
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.shared.probe().await
+    }
+
+    fn health(&self) -> Option<Health> {
+        Some(self.shared.lock().health())
+    }
+
+    fn queue_stats(&self) -> Option<QueueStats> {
+        self.shared.inner.queue_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Flaky {
+        down: AtomicBool,
+        lists: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Provider for Arc<Flaky> {
+        async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+            self.lists.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(unavailable(ProbeTimeout));
+            }
+            Ok(Vec::new())
+        }
+
+        async fn get(&self, _id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+            Ok(None)
+        }
+
+        async fn start(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn stop(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_after_failures_and_recovers() {
+        let backend = Arc::new(Flaky::default());
+        backend.down.store(true, Ordering::SeqCst);
+        let provider = Monitored::new(
+            "flaky".to_owned(),
+            Arc::clone(&backend),
+            HealthConfig {
+                probe_interval: None,
+                probe_timeout: Duration::from_secs(1),
+                failure_threshold: 2,
+                open_duration: Duration::from_millis(50),
+            },
+        );
+
+        assert!(provider.list().await.is_err());
+        assert!(provider.list().await.is_err());
+        let err = provider.list().await.unwrap_err();
+        assert!(err.chain().any(|cause| cause.is::<CircuitOpen>()));
+        assert_eq!(backend.lists.load(Ordering::SeqCst), 2);
+        assert_eq!(provider.health().unwrap().circuit, CircuitState::Open);
+
+        backend.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        provider.check_health().await.unwrap();
+        let health = provider.health().unwrap();
+        assert_eq!(health.circuit, CircuitState::Closed);
+        assert_eq!(health.consecutive_failures, 0);
+        assert!(provider.list().await.is_ok());
+    }
+
+    #[test]
+    fn request_errors_neither_succeed_nor_fail() {
+        let config = HealthConfig {
+            probe_interval: None,
+            probe_timeout: Duration::from_secs(1),
+            failure_threshold: 2,
+            open_duration: Duration::from_secs(30),
+        };
+        let mut breaker = Breaker::new(config);
+        let latency = Duration::from_millis(10);
+        let now = Instant::now();
+
+        breaker.record(now, latency, Some(ErrorKind::Unavailable));
+        breaker.record(now, latency, Some(ErrorKind::NotFound));
+        assert_eq!(breaker.health().consecutive_failures, 1);
+        assert_eq!(breaker.health().last_success, None);
+        assert!(breaker.record(now, latency, Some(ErrorKind::Unavailable)));
+        assert_eq!(breaker.health().circuit, CircuitState::Open);
+
+        // A trial hitting a missing instance proves nothing either way.
+        let later = now + config.open_duration;
+        assert!(breaker.admit(later));
+        assert!(!breaker.admit(later));
+        assert!(!breaker.record(later, latency, Some(ErrorKind::NotFound)));
+        assert_eq!(breaker.health().circuit, CircuitState::Open);
+        assert_eq!(breaker.health().consecutive_failures, 2);
+        assert!(breaker.admit(later));
+        assert!(breaker.record(later, latency, None));
+        assert_eq!(breaker.health().circuit, CircuitState::Closed);
+        assert_eq!(breaker.health().consecutive_failures, 0);
+    }
+}
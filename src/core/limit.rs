@@ -0,0 +1,499 @@
+//! Rate and concurrency limits layered around a provider.
+//!
+//! Calls wait in a queue per user until a slot is free and, for the call's
+//! class, a token is available; the queues are served in turn, so that a
+//! user starting many calls at once does not hold up the others. Reads and
+//! writes have their own rates, as backends often limit writes more tightly.
+//!
+//! Users are set per request with [`as_user`]; background calls, such as
+//! cache refreshes and health probes, share an anonymous user. Calls are
+//! counted for the task making them, so work shared between requests, like
+//! a data loader batch, counts for whichever of them runs it.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use super::{health::Health, Capabilities, Id, IdRef, Instance, Provider};
+
+pub type UserId = String;
+
+tokio::task_local! {
+    static USER: UserId;
+}
+
+/// Run `future` on behalf of `user`, whose calls are queued separately.
+pub async fn as_user<F>(user: UserId, future: F) -> F::Output
+where
+    F: Future,
+{
+    USER.scope(user, future).await
+}
+
+fn current_user() -> UserId {
+    USER.try_with(Clone::clone).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimitConfig {
+    /// How many calls may be in flight at once.
+    pub max_concurrent: usize,
+    /// How many reads (`list`, `get`) may start per second, if limited.
+    pub reads_per_second: Option<f64>,
+    /// How many writes (`start`, `stop`) may start per second, if limited.
+    pub writes_per_second: Option<f64>,
+}
+
+/// What the queue has gone through, to tell whether the limits are too tight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// How many calls are waiting.
+    pub queued: usize,
+    pub in_flight: usize,
+    /// How many calls went through.
+    pub calls: u64,
+    /// How long they waited in total.
+    pub total_wait: Duration,
+    /// How long the call that waited the longest did.
+    pub max_wait: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Read,
+    Write,
+}
+
+/// A token bucket, holding up to a second worth of tokens.
+struct Bucket {
+    rate: Option<f64>,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Option<f64>) -> Self {
+        Self {
+            rate,
+            tokens: rate.map_or(0.0, Self::capacity),
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn capacity(rate: f64) -> f64 {
+        rate.max(1.0)
+    }
+
+    /// Take a token, or tell when one will be available.
+    fn take(&mut self, now: Instant) -> Result<(), Instant> {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(Self::capacity(rate));
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(now + Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    fn refund(&mut self) {
+        if let Some(rate) = self.rate {
+            self.tokens = (self.tokens + 1.0).min(Self::capacity(rate));
+        }
+    }
+}
+
+struct Waiter {
+    id: u64,
+    class: Class,
+    queued_at: Instant,
+    grant: oneshot::Sender<()>,
+}
+
+struct State {
+    max_concurrent: usize,
+    in_flight: usize,
+    reads: Bucket,
+    writes: Bucket,
+    queues: HashMap<UserId, VecDeque<Waiter>>,
+    /// The users with queued calls, in the order they are served.
+    turns: VecDeque<UserId>,
+    /// When a dispatch is scheduled for tokens to become available.
+    wakeup_at: Option<Instant>,
+    next_waiter_id: u64,
+    stats: QueueStats,
+}
+
+impl State {
+    fn bucket(&mut self, class: Class) -> &mut Bucket {
+        match class {
+            Class::Read => &mut self.reads,
+            Class::Write => &mut self.writes,
+        }
+    }
+
+    /// Grant as many queued calls as the limits allow, taking turns between
+    /// users; returns when to try again if calls wait for tokens.
+    fn dispatch(&mut self, now: Instant) -> Option<Instant> {
+        let mut retry_at: Option<Instant> = None;
+        // How many users in a row had to wait for a token.
+        let mut waiting = 0;
+        while self.in_flight < self.max_concurrent && waiting < self.turns.len() {
+            let user = match self.turns.pop_front() {
+                Some(user) => user,
+                None => break,
+            };
+            let class = match self.queues.get(&user).and_then(VecDeque::front) {
+                Some(waiter) => waiter.class,
+                None => {
+                    self.queues.remove(&user);
+                    continue;
+                }
+            };
+
+            if let Err(available_at) = self.bucket(class).take(now) {
+                retry_at = Some(retry_at.map_or(available_at, |at| at.min(available_at)));
+                self.turns.push_back(user);
+                waiting += 1;
+                continue;
+            }
+            waiting = 0;
+
+            let queue = self.queues.get_mut(&user).expect("the queue was just seen");
+            let waiter = queue.pop_front().expect("the queue was just seen");
+            let empty = queue.is_empty();
+            self.stats.queued -= 1;
+            if empty {
+                self.queues.remove(&user);
+            } else {
+                self.turns.push_back(user);
+            }
+
+            if waiter.grant.send(()).is_ok() {
+                let wait = now.saturating_duration_since(waiter.queued_at);
+                self.in_flight += 1;
+                self.stats.calls += 1;
+                self.stats.total_wait += wait;
+                self.stats.max_wait = self.stats.max_wait.max(wait);
+            } else {
+                self.bucket(class).refund();
+            }
+        }
+        retry_at
+    }
+
+    /// Forget a call abandoned before it was granted.
+    fn cancel(&mut self, user: &UserId, id: u64) {
+        let queue = match self.queues.get_mut(user) {
+            Some(queue) => queue,
+            None => return,
+        };
+        let idx = match queue.iter().position(|waiter| waiter.id == id) {
+            Some(idx) => idx,
+            // Already granted or dropped by a dispatch.
+            None => return,
+        };
+        queue.remove(idx);
+        self.stats.queued -= 1;
+        if queue.is_empty() {
+            self.queues.remove(user);
+            self.turns.retain(|turn| turn != user);
+        }
+    }
+}
+
+struct Scheduler {
+    state: Mutex<State>,
+}
+
+impl Scheduler {
+    fn new(config: LimitConfig) -> Self {
+        Self {
+            state: Mutex::new(State {
+                max_concurrent: config.max_concurrent.max(1),
+                in_flight: 0,
+                reads: Bucket::new(config.reads_per_second),
+                writes: Bucket::new(config.writes_per_second),
+                queues: HashMap::new(),
+                turns: VecDeque::new(),
+                wakeup_at: None,
+                next_waiter_id: 0,
+                stats: QueueStats {
+                    queued: 0,
+                    in_flight: 0,
+                    calls: 0,
+                    total_wait: Duration::ZERO,
+                    max_wait: Duration::ZERO,
+                },
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Dispatch, and schedule the next dispatch if calls wait for tokens.
+    fn dispatch(self: &Arc<Self>, state: &mut State) {
+        let retry_at = match state.dispatch(Instant::now()) {
+            Some(retry_at) => retry_at,
+            None => return,
+        };
+        if matches!(state.wakeup_at, Some(wakeup_at) if wakeup_at <= retry_at) {
+            return;
+        }
+        state.wakeup_at = Some(retry_at);
+        let scheduler = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(retry_at.into()).await;
+            let mut state = scheduler.lock();
+            if state.wakeup_at == Some(retry_at) {
+                state.wakeup_at = None;
+            }
+            scheduler.dispatch(&mut state);
+        });
+    }
+
+    async fn acquire(self: &Arc<Self>, user: UserId, class: Class) -> Permit {
+        let (grant, granted) = oneshot::channel();
+        let id = {
+            let mut state = self.lock();
+            let id = state.next_waiter_id;
+            state.next_waiter_id += 1;
+            let queue = state.queues.entry(user.clone()).or_default();
+            queue.push_back(Waiter {
+                id,
+                class,
+                queued_at: Instant::now(),
+                grant,
+            });
+            if queue.len() == 1 {
+                state.turns.push_back(user.clone());
+            }
+            state.stats.queued += 1;
+            self.dispatch(&mut state);
+            id
+        };
+
+        let mut pending = Pending {
+            scheduler: Arc::clone(self),
+            user,
+            id,
+            granted,
+            done: false,
+        };
+        // The sender is only dropped along with the scheduler, which we hold.
+        let _ = (&mut pending.granted).await;
+        pending.done = true;
+        Permit {
+            scheduler: Arc::clone(self),
+        }
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.lock();
+        state.in_flight -= 1;
+        self.dispatch(&mut state);
+    }
+
+    fn stats(&self) -> QueueStats {
+        let state = self.lock();
+        QueueStats {
+            in_flight: state.in_flight,
+            ..state.stats
+        }
+    }
+}
+
+/// A call waiting for its turn; if it is abandoned, it leaves the queue, or
+/// gives the slot back when it was granted already.
+struct Pending {
+    scheduler: Arc<Scheduler>,
+    user: UserId,
+    id: u64,
+    granted: oneshot::Receiver<()>,
+    done: bool,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        // Once closed, the call can no longer be granted.
+        self.granted.close();
+        if self.granted.try_recv().is_ok() {
+            self.scheduler.release();
+        } else {
+            self.scheduler.lock().cancel(&self.user, self.id);
+        }
+    }
+}
+
+/// A slot held for the duration of a call.
+struct Permit {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+/// A provider whose calls are queued to stay within limits.
+pub struct Limited<P> {
+    inner: P,
+    scheduler: Arc<Scheduler>,
+}
+
+impl<P> Limited<P>
+where
+    P: Provider,
+{
+    pub fn new(inner: P, config: LimitConfig) -> Self {
+        Self {
+            inner,
+            scheduler: Arc::new(Scheduler::new(config)),
+        }
+    }
+
+    async fn call<T, F>(&self, class: Class, call: F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let _permit = self.scheduler.acquire(current_user(), class).await;
+        call.await
+    }
+}
+
+#[async_trait::async_trait]
+impl<P> Provider for Limited<P>
+where
+    P: Provider,
+{
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+        self.call(Class::Read, self.inner.list()).await
+    }
+
+    async fn get(&self, id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+        self.call(Class::Read, self.inner.get(id)).await
+    }
+
+    async fn get_many(&self, ids: &[Id]) -> Result<HashMap<Id, Instance>, anyhow::Error> {
+        self.call(Class::Read, self.inner.get_many(ids)).await
+    }
+
+    async fn start(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        self.call(Class::Write, self.inner.start(id)).await
+    }
+
+    async fn stop(&self, id: &IdRef) -> Result<(), anyhow::Error> {
+        self.call(Class::Write, self.inner.stop(id)).await
+    }
+
+    fn invalidate(&self, id: Option<&IdRef>) {
+        self.inner.invalidate(id);
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.call(Class::Read, self.inner.check_health()).await
+    }
+
+    fn health(&self) -> Option<Health> {
+        self.inner.health()
+    }
+
+    fn queue_stats(&self) -> Option<QueueStats> {
+        Some(self.scheduler.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn takes_turns_between_users() {
+        let scheduler = Arc::new(Scheduler::new(LimitConfig {
+            max_concurrent: 1,
+            reads_per_second: None,
+            writes_per_second: None,
+        }));
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        let first = scheduler.acquire("a".to_owned(), Class::Read).await;
+        let mut calls = Vec::new();
+        for (user, call) in [("a", "a1"), ("a", "a2"), ("b", "b1")] {
+            let scheduler = Arc::clone(&scheduler);
+            let order = Arc::clone(&order);
+            calls.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(user.to_owned(), Class::Write).await;
+                order.lock().unwrap().push(call);
+            }));
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(scheduler.stats().queued, 3);
+
+        drop(first);
+        for call in calls {
+            call.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["a1", "b1", "a2"]);
+        let stats = scheduler.stats();
+        assert_eq!((stats.queued, stats.in_flight, stats.calls), (0, 0, 4));
+    }
+
+    #[tokio::test]
+    async fn forgets_cancelled_calls() {
+        let scheduler = Arc::new(Scheduler::new(LimitConfig {
+            max_concurrent: 1,
+            reads_per_second: None,
+            writes_per_second: None,
+        }));
+
+        let first = scheduler.acquire("a".to_owned(), Class::Read).await;
+        let cancelled = tokio::spawn({
+            let scheduler = Arc::clone(&scheduler);
+            async move {
+                let _permit = scheduler.acquire("b".to_owned(), Class::Read).await;
+            }
+        });
+        let queued = tokio::spawn({
+            let scheduler = Arc::clone(&scheduler);
+            async move {
+                let _permit = scheduler.acquire("c".to_owned(), Class::Read).await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(scheduler.stats().queued, 2);
+
+        cancelled.abort();
+        assert!(cancelled.await.unwrap_err().is_cancelled());
+        assert_eq!(scheduler.stats().queued, 1);
+        {
+            let state = scheduler.lock();
+            assert!(!state.queues.contains_key("b"));
+            assert_eq!(state.turns, ["c"]);
+        }
+
+        drop(first);
+        queued.await.unwrap();
+        let stats = scheduler.stats();
+        assert_eq!((stats.queued, stats.in_flight, stats.calls), (0, 0, 2));
+    }
+}
@@ -1,21 +1,64 @@
-use std::collections::HashMap;
+pub mod action;
+pub mod cache;
+pub mod health;
+pub mod limit;
+pub mod reload;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
 
 pub type ProviderKey = String;
 pub type ProviderKeyRef = str;
 
+pub type Providers = HashMap<ProviderKey, Arc<dyn Provider>>;
+
+/// The registry of providers, which may be swapped at runtime (see
+/// [`reload`]).
 pub struct Core {
-    pub providers: HashMap<ProviderKey, Box<dyn Provider>>,
+    providers: RwLock<Providers>,
 }
 
 impl Core {
-    pub fn provider(&self, key: &ProviderKeyRef) -> Option<&dyn Provider> {
-        self.providers
-            .get(key)
-            .map(|val| val.as_ref() as &dyn Provider)
+    pub fn new(providers: Providers) -> Self {
+        Self {
+            providers: RwLock::new(providers),
+        }
+    }
+
+    /// The provider registered under `key`. Callers keep the returned handle
+    /// for the rest of their request, which therefore completes against the
+    /// same provider even if it is replaced in the meantime.
+    pub fn provider(&self, key: &ProviderKeyRef) -> Option<Arc<dyn Provider>> {
+        self.read().get(key).cloned()
     }
 
     pub fn has_provider(&self, key: &ProviderKeyRef) -> bool {
-        self.providers.contains_key(key)
+        self.read().contains_key(key)
+    }
+
+    /// The keys of the registered providers, sorted.
+    pub fn provider_keys(&self) -> Vec<ProviderKey> {
+        let mut keys: Vec<_> = self.read().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Replace all the providers at once, returning the previous ones.
+    pub fn replace_providers(&self, providers: Providers) -> Providers {
+        let mut current = self
+            .providers
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        std::mem::replace(&mut current, providers)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Providers> {
+        self.providers.read().unwrap_or_else(|err| err.into_inner())
     }
 }
 
@@ -26,13 +69,139 @@ pub type IdRef = str;
 #[error("Unable to parse the ID")]
 pub struct IdParsingError;
 
+/// Backend-agnostic classification of a provider failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    Unavailable,
+    Conflict,
+    RateLimited,
+    InvalidId,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn from_http_status(status_code: u16) -> Self {
+        match status_code {
+            401 => Self::Unauthorized,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            409 => Self::Conflict,
+            429 => Self::RateLimited,
+            500..=599 => Self::Unavailable,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Details reported by the backend itself, as opposed to our own description
+/// of the failure.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderErrorDetails {
+    pub status_code: Option<u16>,
+    pub code: Option<String>,
+    pub message: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// An error that providers return (wrapped in `anyhow::Error`) to let the
+/// API layer report the failure with a stable code.
+#[derive(Debug, thiserror::Error)]
+#[error("provider error ({kind:?})")]
+pub struct ProviderError {
+    pub kind: ErrorKind,
+    pub details: Option<ProviderErrorDetails>,
+    #[source]
+    pub source: Box<dyn std::error::Error + Send + Sync>,
+}
+
+/// What a provider is good at, for callers to pick the cheapest way to get
+/// what they need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether `get` is a single cheap request rather than, say, a filtered
+    /// `list`.
+    pub efficient_get: bool,
+    /// Whether `get_many` fetches many instances in a few requests, instead
+    /// of falling back to `get`.
+    pub efficient_get_many: bool,
+    /// How many `get` calls may be in flight at once.
+    pub max_concurrent_gets: usize,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            efficient_get: false,
+            efficient_get_many: false,
+            max_concurrent_gets: 1,
+        }
+    }
+}
+
 #[async_trait::async_trait]
 pub trait Provider: Send + Sync {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     async fn list(&self) -> Result<Vec<Instance>, anyhow::Error>;
     async fn get(&self, id: &IdRef) -> Result<Option<Instance>, anyhow::Error>;
 
+    /// Get many instances at once, keyed by the requested IDs; the instances
+    /// that are not found are omitted.
+    async fn get_many(&self, ids: &[Id]) -> Result<HashMap<Id, Instance>, anyhow::Error> {
+        get_many_by_get(self, ids).await
+    }
+
     async fn start(&self, id: &IdRef) -> Result<(), anyhow::Error>;
     async fn stop(&self, id: &IdRef) -> Result<(), anyhow::Error>;
+
+    /// Forget what is cached about an instance, or about all of them with
+    /// `None`, so that the next reads go to the backend.
+    fn invalidate(&self, _id: Option<&IdRef>) {}
+
+    /// Check that the backend is reachable and accepts our credentials; a
+    /// `list` does both by default, which providers that page or fan out
+    /// replace with a single cheap request.
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.list().await.map(|_| ())
+    }
+
+    /// The health of the backend, for the providers that keep track of it.
+    fn health(&self) -> Option<health::Health> {
+        None
+    }
+
+    /// How calls have been queued, for the providers that limit them.
+    fn queue_stats(&self) -> Option<limit::QueueStats> {
+        None
+    }
+}
+
+/// The fallback implementation of [`Provider::get_many`], running `get`s
+/// concurrently within the provider's limit.
+pub async fn get_many_by_get<P>(
+    provider: &P,
+    ids: &[Id],
+) -> Result<HashMap<Id, Instance>, anyhow::Error>
+where
+    P: Provider + ?Sized,
+{
+    let gets: Vec<_> = ids
+        .iter()
+        .map(|id| async move {
+            let instance = provider.get(id).await?;
+            Ok::<_, anyhow::Error>(instance.map(|instance| (id.clone(), instance)))
+        })
+        .collect();
+    let instances: Vec<_> = stream::iter(gets)
+        .buffer_unordered(provider.capabilities().max_concurrent_gets.max(1))
+        .try_collect()
+        .await?;
+    Ok(instances.into_iter().flatten().collect())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -43,8 +212,31 @@ pub enum State {
     Other,
 }
 
+impl State {
+    /// The state of a group of instances: on or off if they all are, in
+    /// progress if any is, and other otherwise (including when empty).
+    pub fn aggregate(states: impl IntoIterator<Item = State>) -> Self {
+        let mut aggregated = None;
+        for state in states {
+            aggregated = match (aggregated, state) {
+                (_, Self::InProgress) => return Self::InProgress,
+                (None, state) => Some(state),
+                (Some(prev), state) if prev == state => Some(state),
+                (Some(_), _) => Some(Self::Other),
+            };
+        }
+        aggregated.unwrap_or(Self::Other)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Instance {
     pub id: Id,
     pub display_name: String,
+    /// The kind of resource, as named by the backend, so that clients can
+    /// tell apart the instances of a provider managing several.
+    pub resource_type: String,
     pub state: State,
+    /// When the state was read from the backend.
+    pub observed_at: Instant,
 }
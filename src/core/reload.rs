@@ -0,0 +1,191 @@
+//! Rebuilding the providers without a restart.
+//!
+//! A [`Reloader`] builds a whole new set of providers from the current
+//! configuration and swaps it into the [`Core`] at once; nothing changes if
+//! the build fails. Requests that already got hold of a provider complete
+//! against it, and the old provider is dropped, closing its connections and
+//! child processes, once the last of them is done.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use super::{Core, ProviderKey, Providers};
+
+/// Builds the providers from the configuration.
+#[async_trait::async_trait]
+pub trait ProviderFactory: Send + Sync {
+    async fn build(&self) -> Result<Providers, anyhow::Error>;
+
+    /// The files the configuration is read from, which trigger a reload
+    /// when they change.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to build the providers: {0:#}")]
+    Build(anyhow::Error),
+}
+
+pub struct Reloader {
+    core: Arc<Core>,
+    factory: Arc<dyn ProviderFactory>,
+    /// Serializes the reloads, so that a slow one cannot overwrite the
+    /// result of a later one.
+    lock: Mutex<()>,
+}
+
+impl Reloader {
+    pub fn new(core: Arc<Core>, factory: Arc<dyn ProviderFactory>) -> Self {
+        Self {
+            core,
+            factory,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Rebuild the providers and swap them in, returning the new keys.
+    pub async fn reload(&self) -> Result<Vec<ProviderKey>, Error> {
+        let _guard = self.lock.lock().await;
+
+        let providers = self.factory.build().await.map_err(Error::Build)?;
+
+        let previous = self.core.replace_providers(providers);
+        let keys = self.core.provider_keys();
+        let added: Vec<_> = keys
+            .iter()
+            .filter(|key| !previous.contains_key(*key))
+            .collect();
+        let mut removed: Vec<_> = previous.keys().filter(|key| !keys.contains(key)).collect();
+        removed.sort();
+        info!(?added, ?removed, "Reloaded the providers");
+        Ok(keys)
+    }
+
+    /// Reload whenever the process receives `SIGHUP`.
+    pub async fn reload_on_sighup(&self) -> std::io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the providers");
+            if let Err(err) = self.reload().await {
+                error!("{}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reload whenever one of the watched files is modified, created or
+    /// removed, checking every `interval`.
+    pub async fn watch_files(&self, interval: Duration) {
+        let mut modified = self.modification_times();
+        loop {
+            tokio::time::sleep(interval).await;
+            let current = self.modification_times();
+            if current == modified {
+                continue;
+            }
+            modified = current;
+            info!("Configuration files changed, reloading the providers");
+            if let Err(err) = self.reload().await {
+                error!("{}", err);
+            }
+        }
+    }
+
+    fn modification_times(&self) -> HashMap<PathBuf, Option<SystemTime>> {
+        self.factory
+            .watched_files()
+            .into_iter()
+            .map(|path| {
+                let modified = std::fs::metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                (path, modified)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::core::{Id, IdRef, Instance, Provider, State};
+
+    struct Fixed(&'static str);
+
+    #[async_trait::async_trait]
+    impl Provider for Fixed {
+        async fn list(&self) -> Result<Vec<Instance>, anyhow::Error> {
+            Ok(vec![Instance {
+                id: Id::from(self.0),
+                display_name: self.0.to_owned(),
+                resource_type: "test".to_owned(),
+                state: State::On,
+                observed_at: std::time::Instant::now(),
+            }])
+        }
+
+        async fn get(&self, _id: &IdRef) -> Result<Option<Instance>, anyhow::Error> {
+            Ok(None)
+        }
+
+        async fn start(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+
+        async fn stop(&self, _id: &IdRef) -> Result<(), anyhow::Error> {
+            Ok(())
+        }
+    }
+
+    /// Builds `old`, then `new` with an extra provider, then fails.
+    #[derive(Default)]
+    struct Generations(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl ProviderFactory for Generations {
+        async fn build(&self) -> Result<Providers, anyhow::Error> {
+            let mut providers = Providers::new();
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => {
+                    providers.insert("a".to_owned(), Arc::new(Fixed("old")));
+                }
+                1 => {
+                    providers.insert("a".to_owned(), Arc::new(Fixed("new")));
+                    providers.insert("b".to_owned(), Arc::new(Fixed("new")));
+                }
+                _ => anyhow::bail!("invalid configuration"),
+            }
+            Ok(providers)
+        }
+    }
+
+    #[tokio::test]
+    async fn swaps_providers_and_keeps_them_on_failure() {
+        let factory = Arc::new(Generations::default());
+        let core = Arc::new(Core::new(factory.build().await.unwrap()));
+        let reloader = Reloader::new(Arc::clone(&core), factory);
+
+        let in_flight = core.provider("a").unwrap();
+        assert_eq!(reloader.reload().await.unwrap(), ["a", "b"]);
+        assert_eq!(in_flight.list().await.unwrap()[0].id, "old");
+        let provider = core.provider("a").unwrap();
+        assert_eq!(provider.list().await.unwrap()[0].id, "new");
+
+        assert!(matches!(reloader.reload().await, Err(Error::Build(_))));
+        assert_eq!(core.provider_keys(), ["a", "b"]);
+    }
+}
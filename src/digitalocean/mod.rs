@@ -0,0 +1,405 @@
+//! DigitalOcean provider implementation, for droplets.
+//!
+//! Our IDs are the droplet IDs.
+
+use std::time::Duration;
+
+use reqwest::Method;
+
+use self::utils::{check_status, ServerError};
+use crate::core::action::{self, ActionStatus, WaitError};
+
+mod model;
+mod utils;
+
+const RESOURCE_TYPE: &str = "digitalocean/droplet";
+
+/// The largest page the API returns.
+const PER_PAGE: usize = 200;
+
+/// How often to poll an action while waiting for it; the API allows 5000
+/// requests an hour.
+const ACTION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The public DigitalOcean API.
+pub const DEFAULT_ENDPOINT: &str = "https://api.digitalocean.com/v2";
+
+pub struct Provider {
+    pub client: reqwest::Client,
+    /// The DigitalOcean API, or a local stand-in.
+    pub endpoint: String,
+    /// A personal access token with write scope.
+    pub token: String,
+    pub stop_mode: StopMode,
+    /// How long to wait for a power action to complete.
+    pub action_timeout: Duration,
+}
+
+/// How to stop a droplet; either way, a powered off droplet is still billed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Ask the OS to shut down gracefully.
+    Shutdown,
+    /// Cut the power immediately.
+    PowerOff,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(Self::Shutdown),
+            "power-off" => Ok(Self::PowerOff),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("action {0} errored")]
+    Action(u64),
+    #[error("action {0} is still in progress")]
+    ActionTimeout(u64),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Reqwest(_) | Self::ActionTimeout(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::Action(_) => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(err.into()),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Provider {
+    /// Send a request to an API URL, relative to the endpoint unless
+    /// absolute.
+    async fn request<T>(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&model::ActionRequest>,
+    ) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let url = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_owned()
+        } else {
+            format!("{}{}", self.endpoint, url)
+        };
+        let mut request = self.client.request(method, url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let res = check_status(request.send().await?).await?;
+        Ok(res.json().await?)
+    }
+
+    async fn list_droplets(&self) -> Result<Vec<model::Droplet>, Error> {
+        let mut droplets = Vec::new();
+        let mut url = format!("/droplets?per_page={}", PER_PAGE);
+        loop {
+            let list: model::DropletList = self.request(Method::GET, &url, None).await?;
+            droplets.extend(list.droplets);
+            match list.links.pages.next {
+                Some(next) => url = next,
+                None => return Ok(droplets),
+            }
+        }
+    }
+
+    async fn get_droplet(&self, id: &crate::core::IdRef) -> Result<Option<model::Droplet>, Error> {
+        let id: u64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        match self
+            .request::<model::DropletResponse>(Method::GET, &format!("/droplets/{}", id), None)
+            .await
+        {
+            Ok(res) => Ok(Some(res.droplet)),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Run a power action, such as `power_on`, and wait for it.
+    async fn droplet_action(
+        &self,
+        id: &crate::core::IdRef,
+        action_type: &'static str,
+    ) -> Result<(), Error> {
+        let id: u64 = id.parse().map_err(|_| ServerError {
+            status_code: 404,
+            error: None,
+        })?;
+        let path = format!("/droplets/{}/actions", id);
+        let body = model::ActionRequest { action_type };
+        let res: model::ActionResponse = self.request(Method::POST, &path, Some(&body)).await?;
+        self.wait_for_action(res.action.id).await
+    }
+
+    async fn wait_for_action(&self, id: u64) -> Result<(), Error> {
+        let path = &format!("/actions/{}", id);
+        let result = action::wait_for(ACTION_POLL_INTERVAL, self.action_timeout, || async move {
+            let res: model::ActionResponse = self.request(Method::GET, path, None).await?;
+            Ok(match res.action.status.as_str() {
+                model::ACTION_STATUS_IN_PROGRESS => ActionStatus::Running,
+                model::ACTION_STATUS_COMPLETED => ActionStatus::Succeeded,
+                // Errored actions come with no reason.
+                status => ActionStatus::Failed(status.to_owned()),
+            })
+        })
+        .await;
+        result.map_err(|err| match err {
+            WaitError::Poll(err) => err,
+            WaitError::Failed(_) => Error::Action(id),
+            WaitError::Timeout => Error::ActionTimeout(id),
+        })
+    }
+
+    fn droplet_to_instance(droplet: model::Droplet) -> crate::core::Instance {
+        let state = match droplet.status.as_str() {
+            model::DROPLET_STATUS_ACTIVE => crate::core::State::On,
+            model::DROPLET_STATUS_OFF => crate::core::State::Off,
+            model::DROPLET_STATUS_NEW => crate::core::State::InProgress,
+            // Archived.
+            _ => crate::core::State::Other,
+        };
+        crate::core::Instance {
+            id: droplet.id.to_string(),
+            display_name: droplet.name,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: 4,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let droplets = self.list_droplets().await.map_err(Error::into_core)?;
+        Ok(droplets
+            .into_iter()
+            .map(Self::droplet_to_instance)
+            .collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.request::<model::DropletList>(Method::GET, "/droplets?per_page=1", None)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let droplet = self.get_droplet(id).await.map_err(Error::into_core)?;
+        Ok(droplet.map(Self::droplet_to_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.droplet_action(id, model::ACTION_TYPE_POWER_ON)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let action_type = match self.stop_mode {
+            StopMode::Shutdown => model::ACTION_TYPE_SHUTDOWN,
+            StopMode::PowerOff => model::ACTION_TYPE_POWER_OFF,
+        };
+        self.droplet_action(id, action_type)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    fn error(status: StatusCode, id: &str, message: &str) -> (StatusCode, Json<serde_json::Value>) {
+        (
+            status,
+            Json(serde_json::json!({"id": id, "message": message, "request_id": "4d9d8375-3c56"})),
+        )
+    }
+
+    async fn droplet(Path(id): Path<u64>) -> (StatusCode, Json<serde_json::Value>) {
+        match id {
+            3164444 => (
+                StatusCode::OK,
+                Json(
+                    serde_json::json!({"droplet": {"id": 3164444, "name": "example.com", "status": "new"}}),
+                ),
+            ),
+            _ => error(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                "The resource you were accessing could not be found.",
+            ),
+        }
+    }
+
+    async fn droplet_action(
+        Path(id): Path<u64>,
+        Json(body): Json<serde_json::Value>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        let action_id = match (id, body["type"].as_str()) {
+            (3164444, Some("power_on")) => 1,
+            (3164444, Some("shutdown")) => 2,
+            _ => {
+                return error(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "unprocessable_entity",
+                    "Droplet is already powered off.",
+                )
+            }
+        };
+        (
+            StatusCode::CREATED,
+            Json(serde_json::json!({"action": {"id": action_id, "status": "in-progress"}})),
+        )
+    }
+
+    async fn action(Path(id): Path<u64>) -> Json<serde_json::Value> {
+        let status = match id {
+            1 => "completed",
+            _ => "errored",
+        };
+        Json(serde_json::json!({"action": {"id": id, "status": status}}))
+    }
+
+    async fn unauthorized() -> (StatusCode, Json<serde_json::Value>) {
+        error(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Unable to authenticate you.",
+        )
+    }
+
+    #[tokio::test]
+    async fn runs_droplet_actions_and_maps_api_errors() {
+        let app = Router::new()
+            .route("/droplets", get(unauthorized))
+            .route("/droplets/:id", get(droplet))
+            .route("/droplets/:id/actions", post(droplet_action))
+            .route("/actions/:id", get(action));
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        let provider = Provider {
+            client: reqwest::Client::new(),
+            endpoint: format!("http://{}", addr),
+            token: "token".to_owned(),
+            stop_mode: StopMode::Shutdown,
+            action_timeout: Duration::from_secs(10),
+        };
+
+        provider.start("3164444").await.unwrap();
+        let instance = provider.get("3164444").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::InProgress);
+        assert!(provider.get("3164445").await.unwrap().is_none());
+        assert!(provider.get("example.com").await.unwrap().is_none());
+
+        // Errored actions give no reason.
+        let err = provider
+            .stop("3164444")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Other);
+        assert!(err.details.is_none());
+        let err = provider
+            .stop("3164445")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Other);
+        let details = err.details.unwrap();
+        assert_eq!(details.code.as_deref(), Some("unprocessable_entity"));
+        assert_eq!(details.request_id.as_deref(), Some("4d9d8375-3c56"));
+        let err = provider
+            .list()
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Unauthorized);
+    }
+
+    #[test]
+    fn maps_droplet_list() {
+        let json = r#"{
+            "droplets": [
+                {"id": 3164444, "name": "example.com", "status": "active", "memory": 1024},
+                {"id": 3164445, "name": "ci", "status": "off"}
+            ],
+            "links": {"pages": {"next": "https://api.digitalocean.com/v2/droplets?page=2&per_page=2"}},
+            "meta": {"total": 3}
+        }"#;
+        let list: model::DropletList = serde_json::from_str(json).unwrap();
+        assert!(list.links.pages.next.is_some());
+        let instances: Vec<_> = list
+            .droplets
+            .into_iter()
+            .map(Provider::droplet_to_instance)
+            .collect();
+        assert_eq!(instances[0].id, "3164444");
+        assert_eq!(instances[0].state, crate::core::State::On);
+        assert_eq!(instances[1].state, crate::core::State::Off);
+    }
+}
@@ -0,0 +1,72 @@
+//! DigitalOcean API models.
+
+use serde::{Deserialize, Serialize};
+
+// Like the other providers' models, these only declare the fields we use.
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct DropletList {
+    pub droplets: Vec<Droplet>,
+    #[serde(default)]
+    pub links: Links,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Links {
+    #[serde(default)]
+    pub pages: Pages,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Pages {
+    /// The URL of the next page, missing on the last one.
+    pub next: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct DropletResponse {
+    pub droplet: Droplet,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Droplet {
+    pub id: u64,
+    pub name: String,
+    pub status: String,
+}
+
+pub const DROPLET_STATUS_NEW: &str = "new";
+pub const DROPLET_STATUS_ACTIVE: &str = "active";
+pub const DROPLET_STATUS_OFF: &str = "off";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct ActionRequest {
+    #[serde(rename = "type")]
+    pub action_type: &'static str,
+}
+
+pub const ACTION_TYPE_POWER_ON: &str = "power_on";
+pub const ACTION_TYPE_SHUTDOWN: &str = "shutdown";
+pub const ACTION_TYPE_POWER_OFF: &str = "power_off";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ActionResponse {
+    pub action: Action,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Action {
+    pub id: u64,
+    pub status: String,
+}
+
+pub const ACTION_STATUS_IN_PROGRESS: &str = "in-progress";
+pub const ACTION_STATUS_COMPLETED: &str = "completed";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorResponse {
+    /// The error code, e.g. `not_found`.
+    pub id: String,
+    pub message: String,
+    pub request_id: Option<String>,
+}
@@ -0,0 +1,38 @@
+use super::model;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The whole body, naming the error and carrying the `request_id` that
+    /// DigitalOcean support asks for; load balancers in front of the API
+    /// answer some errors with HTML instead.
+    pub error: Option<model::ErrorResponse>,
+}
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let error = res.json::<model::ErrorResponse>().await.ok();
+    Err(ServerError {
+        status_code: status.as_u16(),
+        error,
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.error.as_ref().map(|error| error.id.clone()),
+            message: err.error.as_ref().map(|error| error.message.clone()),
+            request_id: err
+                .error
+                .as_ref()
+                .and_then(|error| error.request_id.clone()),
+        }
+    }
+}
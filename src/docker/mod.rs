@@ -0,0 +1,409 @@
+//! Docker provider implementation, talking to the Docker Engine API (or
+//! Podman's compatible one) over its Unix socket.
+//!
+//! Our IDs are the container names.
+
+use std::path::PathBuf;
+
+use hyper::{body::Buf, Body, Method, StatusCode};
+use hyperlocal::{UnixClientExt, UnixConnector};
+
+mod model;
+
+const RESOURCE_TYPE: &str = "docker/container";
+
+pub struct Provider {
+    client: hyper::Client<UnixConnector>,
+    socket: PathBuf,
+    label: Option<String>,
+    stop_timeout: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The message reported in the response body, if it could be parsed.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("hyper: {0}")]
+    Hyper(#[from] hyper::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Hyper(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::Json(_) => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(crate::core::ProviderErrorDetails {
+                status_code: Some(err.status_code),
+                message: err.message.clone(),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Provider {
+    /// A provider managing the containers behind a socket, only those with
+    /// the given label (`key` or `key=value`) if any.
+    pub fn new(socket: impl Into<PathBuf>, label: Option<String>) -> Self {
+        Self {
+            client: hyper::Client::unix(),
+            socket: socket.into(),
+            label,
+            stop_timeout: None,
+        }
+    }
+
+    /// How long containers are given to stop before being killed, instead of
+    /// their own setting.
+    pub fn with_stop_timeout(mut self, secs: u64) -> Self {
+        self.stop_timeout = Some(secs);
+        self
+    }
+
+    /// Send a request, returning the body of a successful response, and
+    /// `None` for a "not modified" one, which the API answers to starting a
+    /// started container or stopping a stopped one.
+    async fn request(&self, method: Method, path: &str) -> Result<Option<impl Buf>, Error> {
+        let request = hyper::Request::builder()
+            .method(method)
+            .uri(hyperlocal::Uri::new(&self.socket, path))
+            .body(Body::empty())
+            .expect("the request is valid");
+        let res = self.client.request(request).await?;
+        let status = res.status();
+        let body = hyper::body::aggregate(res.into_body()).await?;
+
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let message = serde_json::from_reader::<_, model::ErrorResponse>(body.reader())
+                .ok()
+                .map(|res| res.message);
+            return Err(ServerError {
+                status_code: status.as_u16(),
+                message,
+            }
+            .into());
+        }
+        Ok(Some(body))
+    }
+
+    async fn get_json<T>(&self, path: &str) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let body = self.request(Method::GET, path).await?;
+        let value = match body {
+            Some(body) => serde_json::from_reader(body.reader())?,
+            None => serde_json::from_slice(b"null")?,
+        };
+        Ok(value)
+    }
+
+    fn has_label(&self, labels: Option<&std::collections::HashMap<String, String>>) -> bool {
+        let label = match &self.label {
+            Some(label) => label,
+            None => return true,
+        };
+        let labels = match labels {
+            Some(labels) => labels,
+            None => return false,
+        };
+        match label.split_once('=') {
+            Some((key, value)) => labels.get(key).map(String::as_str) == Some(value),
+            None => labels.contains_key(label.as_str()),
+        }
+    }
+
+    fn detect_state(state: &str) -> crate::core::State {
+        match state {
+            model::STATE_RUNNING => crate::core::State::On,
+            model::STATE_CREATED | model::STATE_EXITED => crate::core::State::Off,
+            model::STATE_RESTARTING | model::STATE_REMOVING => crate::core::State::InProgress,
+            // Paused or dead.
+            _ => crate::core::State::Other,
+        }
+    }
+
+    fn instance(name: &str, state: &str) -> crate::core::Instance {
+        let name = name.trim_start_matches('/');
+        crate::core::Instance {
+            id: name.to_owned(),
+            display_name: name.to_owned(),
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state: Self::detect_state(state),
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    async fn list_containers(&self) -> Result<Vec<crate::core::Instance>, Error> {
+        let mut query = vec![("all", "true".to_owned())];
+        if let Some(label) = &self.label {
+            let filters = serde_json::json!({ "label": [label] });
+            query.push(("filters", filters.to_string()));
+        }
+        let query =
+            serde_urlencoded::to_string(&query).expect("what kind of failure is possible here?");
+
+        let containers: Vec<model::ContainerSummary> = self
+            .get_json(&format!("/containers/json?{}", query))
+            .await?;
+        Ok(containers
+            .into_iter()
+            .filter_map(|container| {
+                let name = container.names.first()?;
+                Some(Self::instance(name, &container.state))
+            })
+            .collect())
+    }
+
+    /// Inspect a container, `None` if it does not exist or is not ours.
+    async fn get_container(&self, id: &str) -> Result<Option<model::Container>, Error> {
+        match self
+            .get_json::<model::Container>(&container_path(id, "/json"))
+            .await
+        {
+            Ok(container) if self.has_label(container.config.labels.as_ref()) => {
+                Ok(Some(container))
+            }
+            Ok(_) => Ok(None),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn container_action(&self, id: &str, action: &str) -> Result<(), Error> {
+        if self.get_container(id).await?.is_none() {
+            return Err(ServerError {
+                status_code: 404,
+                message: Some(format!("No such container: {}", id)),
+            }
+            .into());
+        }
+        self.request(Method::POST, &container_path(id, action))
+            .await?;
+        Ok(())
+    }
+}
+
+fn container_path(id: &str, suffix: &str) -> String {
+    let id: String = url_escape(id);
+    format!("/containers/{}{}", id, suffix)
+}
+
+/// Container names are restricted to `[a-zA-Z0-9][a-zA-Z0-9_.-]*`, but IDs
+/// come from clients.
+fn url_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: 8,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let instances = self.list_containers().await.map_err(Error::into_core)?;
+        Ok(instances)
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.request(Method::GET, "/_ping")
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let container = self.get_container(id).await.map_err(Error::into_core)?;
+        Ok(container.map(|container| Self::instance(&container.name, &container.state.status)))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.container_action(id, "/start")
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let action = match self.stop_timeout {
+            Some(secs) => format!("/stop?t={}", secs),
+            None => "/stop".to_owned(),
+        };
+        self.container_action(id, &action)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::{Path, Query},
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use hyperlocal::UnixServerExt;
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    async fn list(
+        Query(query): Query<std::collections::HashMap<String, String>>,
+    ) -> Json<serde_json::Value> {
+        assert_eq!(query["filters"], r#"{"label":["vm-onoff"]}"#);
+        Json(serde_json::json!([
+            {"Id": "4fa6e0f0c678", "Names": ["/db"], "State": "exited", "Labels": {"vm-onoff": ""}},
+            {"Id": "2b3c4d5e6f70", "Names": ["/web"], "State": "running", "Labels": {"vm-onoff": ""}},
+        ]))
+    }
+
+    /// `db`, which is ours and paused, and `cache`, which is not ours.
+    async fn inspect(Path(name): Path<String>) -> (StatusCode, Json<serde_json::Value>) {
+        let labels = match name.as_str() {
+            "db" => serde_json::json!({"vm-onoff": ""}),
+            "cache" => serde_json::json!({}),
+            _ => {
+                let message = format!("No such container: {}", name);
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({ "message": message })),
+                );
+            }
+        };
+        let container = serde_json::json!({
+            "Name": format!("/{}", name),
+            "State": {"Status": "paused"},
+            "Config": {"Labels": labels},
+        });
+        (StatusCode::OK, Json(container))
+    }
+
+    /// Serve `app` on a socket in a new directory, returning both.
+    fn fake_daemon(app: Router) -> (PathBuf, Provider) {
+        let dir = std::env::temp_dir().join(format!("vm-onoff-docker-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let socket = dir.join("docker.sock");
+
+        let server = hyper::Server::bind_unix(&socket)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        let provider = Provider::new(&socket, Some("vm-onoff".to_owned()));
+        (dir, provider)
+    }
+
+    #[tokio::test]
+    async fn lists_containers_from_fake_daemon() {
+        let app = Router::new().route("/containers/json", get(list));
+        let (dir, provider) = fake_daemon(app);
+        let instances = provider.list().await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].id, "db");
+        assert_eq!(instances[0].state, crate::core::State::Off);
+        assert_eq!(instances[1].state, crate::core::State::On);
+    }
+
+    #[tokio::test]
+    async fn acts_only_on_our_containers() {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route("/containers/:name/json", get(inspect))
+            .route(
+                "/containers/:name/:action",
+                post({
+                    let actions = Arc::clone(&actions);
+                    move |Path((name, action)): Path<(String, String)>,
+                          Query(query): Query<std::collections::HashMap<String, String>>| async move {
+                        actions
+                            .lock()
+                            .unwrap()
+                            .push(format!("{} {} {:?}", name, action, query.get("t")));
+                        if action == "start" {
+                            return (StatusCode::NOT_MODIFIED, Json(serde_json::json!(null)));
+                        }
+                        let message = format!(
+                            "Container {} is paused, unpause the container before stopping or killing",
+                            name
+                        );
+                        (StatusCode::CONFLICT, Json(serde_json::json!({ "message": message })))
+                    }
+                }),
+            );
+        let (dir, provider) = fake_daemon(app);
+        let provider = provider.with_stop_timeout(5);
+
+        assert!(provider.get("missing").await.unwrap().is_none());
+        assert!(provider.get("cache").await.unwrap().is_none());
+        let instance = provider.get("db").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::Other);
+
+        provider.start("db").await.unwrap();
+        let err = provider.stop("db").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("Container db is paused, unpause the container before stopping or killing")
+        );
+
+        let err = provider.start("cache").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            *actions.lock().unwrap(),
+            ["db start None", "db stop Some(\"5\")"]
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Docker Engine API models, which Podman's compatible API also serves.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+// Like the other providers' models, these only declare the fields we use.
+
+/// A container, as listed by `/containers/json`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerSummary {
+    /// The names, each with a leading slash.
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// The state, e.g. `running`.
+    #[serde(default)]
+    pub state: String,
+}
+
+/// A container, as inspected with `/containers/{id}/json`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Container {
+    /// The name, with a leading slash.
+    pub name: String,
+    #[serde(default)]
+    pub state: ContainerState,
+    #[serde(default)]
+    pub config: ContainerConfig,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+    /// The state, e.g. `running`.
+    #[serde(default)]
+    pub status: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerConfig {
+    #[serde(default)]
+    pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorResponse {
+    pub message: String,
+}
+
+pub const STATE_CREATED: &str = "created";
+pub const STATE_RUNNING: &str = "running";
+pub const STATE_RESTARTING: &str = "restarting";
+pub const STATE_REMOVING: &str = "removing";
+pub const STATE_EXITED: &str = "exited";
@@ -0,0 +1,40 @@
+//! Authorize as the service account attached to the instance we run on.
+
+use super::{Error, Token, TokenResponse};
+use crate::gcp::utils::check_status;
+
+/// The metadata server, as reachable from any Google Cloud instance.
+pub const DEFAULT_ENDPOINT: &str = "http://metadata.google.internal";
+
+pub struct MetadataServer {
+    pub client: reqwest::Client,
+    pub endpoint: String,
+}
+
+impl MetadataServer {
+    pub async fn perform(&self) -> Result<TokenResponse, Error> {
+        let url = format!(
+            "{}/computeMetadata/v1/instance/service-accounts/default/token",
+            self.endpoint
+        );
+        let req = self
+            .client
+            .get(url)
+            .header("Metadata-Flavor", "Google")
+            .build()?;
+
+        let res = self.client.execute(req).await?;
+        let res = check_status(res).await?;
+        Ok(res.json().await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl super::TokenProvider for MetadataServer {
+    type Token = Token;
+    type Error = Error;
+
+    async fn get_auth_token(&self) -> Result<Self::Token, Self::Error> {
+        Ok(self.perform().await?.into())
+    }
+}
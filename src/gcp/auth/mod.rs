@@ -0,0 +1,83 @@
+//! Authorization logic.
+//!
+//! Tokens come either from a service account key, exchanged for a token
+//! with a signed JWT, or from the metadata server when running on Google
+//! Cloud.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use super::utils::ServerError;
+
+pub mod metadata_server;
+pub mod service_account;
+
+pub use crate::auth::{token_manager, ExpiringToken, TokenProvider};
+
+/// The scope needed to manage instances.
+pub const COMPUTE_SCOPE: &str = "https://www.googleapis.com/auth/compute";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("jwt: {0}")]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    access_token: String,
+    /// The lifetime of the token, in seconds.
+    expires_in: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub access_token: String,
+    pub expires_at: Instant,
+}
+
+impl From<TokenResponse> for Token {
+    fn from(res: TokenResponse) -> Self {
+        Self {
+            access_token: res.access_token,
+            expires_at: Instant::now() + Duration::from_secs(res.expires_in),
+        }
+    }
+}
+
+impl crate::auth::Token for Token {
+    fn access_token(&self) -> &str {
+        self.access_token.as_str()
+    }
+}
+
+impl ExpiringToken for Token {
+    fn expires_at(&self) -> Instant {
+        self.expires_at
+    }
+}
+
+/// A service account key if one is configured, the metadata server
+/// otherwise.
+pub enum DefaultTokenProvider {
+    ServiceAccount(service_account::ServiceAccount),
+    MetadataServer(metadata_server::MetadataServer),
+}
+
+#[async_trait::async_trait]
+impl TokenProvider for DefaultTokenProvider {
+    type Token = Token;
+    type Error = Error;
+
+    async fn get_auth_token(&self) -> Result<Self::Token, Self::Error> {
+        match self {
+            Self::ServiceAccount(provider) => provider.get_auth_token().await,
+            Self::MetadataServer(provider) => provider.get_auth_token().await,
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Identifiers of the instances we manage.
+//!
+//! Instance names are only unique within a zone, so our IDs are the zone
+//! followed by the name (`europe-west1-b/vm0`).
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Id {
+    pub zone: String,
+    pub name: String,
+}
+
+impl Id {
+    /// The path of the instance, relative to the project.
+    pub fn path(&self) -> String {
+        format!("zones/{}/instances/{}", self.zone, self.name)
+    }
+}
+
+/// The last segment of a resource URL, such as the zone of an instance.
+pub fn last_segment(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+impl From<Id> for crate::core::Id {
+    fn from(id: Id) -> Self {
+        format!("{}/{}", id.zone, id.name)
+    }
+}
+
+impl TryFrom<&crate::core::IdRef> for Id {
+    type Error = crate::core::IdParsingError;
+
+    fn try_from(value: &crate::core::IdRef) -> Result<Self, Self::Error> {
+        match value.split_once('/') {
+            Some((zone, name)) if !zone.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Ok(Self {
+                    zone: zone.to_owned(),
+                    name: name.to_owned(),
+                })
+            }
+            _ => Err(crate::core::IdParsingError),
+        }
+    }
+}
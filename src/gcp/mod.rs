@@ -0,0 +1,451 @@
+//! Google Compute Engine provider implementation.
+
+use reqwest::Method;
+
+use self::{
+    id::Id,
+    utils::{check_status, ServerError},
+};
+use crate::auth::Token as _;
+
+pub mod auth;
+mod id;
+mod model;
+mod utils;
+
+const RESOURCE_TYPE: &str = "compute.googleapis.com/Instance";
+
+/// The largest page `aggregatedList` returns.
+const MAX_RESULTS: usize = 500;
+
+/// The public Compute Engine API.
+pub const DEFAULT_ENDPOINT: &str = "https://compute.googleapis.com/compute/v1";
+
+pub struct Provider<AuthTokenProvider> {
+    pub client: reqwest::Client,
+    /// The Compute Engine API, or a local stand-in.
+    pub endpoint: String,
+    pub project: String,
+    pub stop_mode: StopMode,
+    pub auth_token_provider: AuthTokenProvider,
+}
+
+/// How to stop an instance; suspended instances are resumed on start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Shut the instance down.
+    Stop,
+    /// Suspend the instance, keeping its memory, which is billed as storage.
+    Suspend,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(Self::Stop),
+            "suspend" => Ok(Self::Suspend),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error<AuthError> {
+    #[error("auth: {0}")]
+    Auth(#[source] AuthError),
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+}
+
+impl<AuthError> Error<AuthError>
+where
+    AuthError: std::error::Error + Send + Sync + 'static,
+{
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Auth(_) => crate::core::ErrorKind::Unauthorized,
+            Self::Reqwest(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(err.into()),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl<AuthTokenProvider> Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+{
+    fn build_instance_url(&self, id: &Id, action: &str) -> String {
+        format!(
+            "{endpoint}/projects/{project}/{path}{action}",
+            endpoint = self.endpoint,
+            project = self.project,
+            path = id.path(),
+            action = action,
+        )
+    }
+
+    fn build_aggregated_list_url(&self, max_results: usize, page_token: Option<&str>) -> String {
+        let mut url = format!(
+            "{endpoint}/projects/{project}/aggregated/instances?maxResults={maxResults}&returnPartialSuccess=true",
+            endpoint = self.endpoint,
+            project = self.project,
+            maxResults = max_results,
+        );
+        if let Some(page_token) = page_token {
+            url.push_str("&pageToken=");
+            url.push_str(page_token);
+        }
+        url
+    }
+
+    fn build_request(
+        &self,
+        auth_token: &str,
+        method: Method,
+        url: &str,
+    ) -> Result<reqwest::Request, Error<AuthTokenProvider::Error>> {
+        let builder = self
+            .client
+            .request(method.clone(), url)
+            .bearer_auth(auth_token);
+
+        let builder = if method == Method::POST {
+            builder.header(reqwest::header::CONTENT_LENGTH, 0)
+        } else {
+            builder
+        };
+
+        builder.build().map_err(Error::Reqwest)
+    }
+
+    async fn get_auth_token(&self) -> Result<String, Error<AuthTokenProvider::Error>> {
+        let token = self
+            .auth_token_provider
+            .get_auth_token()
+            .await
+            .map_err(Error::Auth)?;
+        Ok(token.access_token().to_owned())
+    }
+
+    async fn exec(
+        &self,
+        request: reqwest::Request,
+    ) -> Result<reqwest::Response, Error<AuthTokenProvider::Error>> {
+        let res = self.client.execute(request).await.map_err(Error::Reqwest)?;
+        let res = check_status(res).await?;
+        Ok(res)
+    }
+
+    async fn list_instances(
+        &self,
+    ) -> Result<Vec<model::Instance>, Error<AuthTokenProvider::Error>> {
+        let mut instances = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let auth_token = self.get_auth_token().await?;
+            let url = self.build_aggregated_list_url(MAX_RESULTS, page_token.as_deref());
+            let res = self
+                .exec(self.build_request(&auth_token, Method::GET, &url)?)
+                .await?;
+            let page: model::InstanceAggregatedList = res.json().await?;
+            instances.extend(page.items.into_values().flat_map(|scope| scope.instances));
+            page_token = page.next_page_token.filter(|token| !token.is_empty());
+            if page_token.is_none() {
+                return Ok(instances);
+            }
+        }
+    }
+
+    /// Fetch the first instance only, to check the token and the project.
+    async fn probe(&self) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_aggregated_list_url(1, None);
+        self.exec(self.build_request(&auth_token, Method::GET, &url)?)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_instance(
+        &self,
+        id: &Id,
+    ) -> Result<model::Instance, Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_instance_url(id, "");
+        let res = self
+            .exec(self.build_request(&auth_token, Method::GET, &url)?)
+            .await?;
+        Ok(res.json().await?)
+    }
+
+    async fn instance_action(
+        &self,
+        id: &Id,
+        action: &str,
+    ) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let auth_token = self.get_auth_token().await?;
+        let url = self.build_instance_url(id, action);
+        self.exec(self.build_request(&auth_token, Method::POST, &url)?)
+            .await?;
+        Ok(())
+    }
+
+    async fn start_instance(&self, id: &Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let instance = self.get_instance(id).await?;
+        let action = match instance.status.as_str() {
+            model::STATUS_SUSPENDED => "/resume",
+            _ => "/start",
+        };
+        self.instance_action(id, action).await
+    }
+
+    async fn stop_instance(&self, id: &Id) -> Result<(), Error<AuthTokenProvider::Error>> {
+        let action = match self.stop_mode {
+            StopMode::Stop => "/stop",
+            StopMode::Suspend => "/suspend",
+        };
+        self.instance_action(id, action).await
+    }
+
+    fn model_to_instance(instance: model::Instance) -> crate::core::Instance {
+        let id = Id {
+            zone: id::last_segment(&instance.zone).to_owned(),
+            name: instance.name.clone(),
+        };
+        crate::core::Instance {
+            id: id.into(),
+            display_name: instance.name,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state: Self::detect_state(&instance.status),
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    fn detect_state(status: &str) -> crate::core::State {
+        match status {
+            model::STATUS_RUNNING => crate::core::State::On,
+            model::STATUS_STOPPED | model::STATUS_SUSPENDED | model::STATUS_TERMINATED => {
+                crate::core::State::Off
+            }
+            model::STATUS_PROVISIONING
+            | model::STATUS_STAGING
+            | model::STATUS_STOPPING
+            | model::STATUS_SUSPENDING => crate::core::State::InProgress,
+            _ => crate::core::State::Other,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<AuthTokenProvider> crate::core::Provider for Provider<AuthTokenProvider>
+where
+    AuthTokenProvider: auth::TokenProvider,
+    <AuthTokenProvider as auth::TokenProvider>::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: 8,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let instances = self.list_instances().await.map_err(Error::into_core)?;
+        Ok(instances.into_iter().map(Self::model_to_instance).collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.probe().await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        match self.get_instance(&Id::try_from(id)?).await {
+            Ok(instance) => Ok(Some(Self::model_to_instance(instance))),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err.into_core().into()),
+        }
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = Id::try_from(id)?;
+        self.start_instance(&id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = Id::try_from(id)?;
+        self.stop_instance(&id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    struct StaticToken;
+
+    #[async_trait::async_trait]
+    impl auth::TokenProvider for StaticToken {
+        type Token = auth::Token;
+        type Error = std::convert::Infallible;
+
+        async fn get_auth_token(&self) -> Result<Self::Token, Self::Error> {
+            Ok(auth::Token {
+                access_token: "token".to_owned(),
+                expires_at: std::time::Instant::now(),
+            })
+        }
+    }
+
+    async fn aggregated_list(Path(project): Path<String>) -> Json<serde_json::Value> {
+        assert_eq!(project, "my-project");
+        Json(serde_json::json!({
+            "items": {
+                "zones/europe-west1-b": {
+                    "instances": [{
+                        "name": "vm0",
+                        "zone": "https://www.googleapis.com/compute/v1/projects/my-project/zones/europe-west1-b",
+                        "status": "SUSPENDED",
+                    }],
+                },
+                "zones/us-east1-c": {
+                    "warning": {"code": "NO_RESULTS_ON_PAGE"},
+                },
+            },
+        }))
+    }
+
+    fn fake_provider(app: Router, stop_mode: StopMode) -> Provider<StaticToken> {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            client: reqwest::Client::new(),
+            endpoint: format!("http://{}/compute/v1", addr),
+            project: "my-project".to_owned(),
+            stop_mode,
+            auth_token_provider: StaticToken,
+        }
+    }
+
+    /// One suspended instance, `vm0`, whose actions are recorded; anything
+    /// else is not found, and `vm0` may not be stopped.
+    fn zone(actions: Arc<Mutex<Vec<String>>>) -> Router {
+        let not_found = || {
+            let error = serde_json::json!({"error": {
+                "message": "The resource was not found",
+                "status": "NOT_FOUND",
+                "errors": [{"reason": "notFound"}],
+            }});
+            (StatusCode::NOT_FOUND, Json(error))
+        };
+        Router::new()
+            .route(
+                "/compute/v1/projects/my-project/zones/europe-west1-b/instances/:name",
+                get(move |Path(name): Path<String>| async move {
+                    if name != "vm0" {
+                        return not_found();
+                    }
+                    let instance = serde_json::json!({
+                        "name": "vm0",
+                        "zone": "https://www.googleapis.com/compute/v1/projects/my-project/zones/europe-west1-b",
+                        "status": "SUSPENDED",
+                    });
+                    (StatusCode::OK, Json(instance))
+                }),
+            )
+            .route(
+                "/compute/v1/projects/my-project/zones/europe-west1-b/instances/vm0/:action",
+                post(move |Path(action): Path<String>| async move {
+                    if action == "stop" {
+                        let error = serde_json::json!({"error": {
+                            "message": "Required 'compute.instances.stop' permission",
+                            "status": "PERMISSION_DENIED",
+                            "errors": [{"reason": "forbidden"}],
+                        }});
+                        return (StatusCode::FORBIDDEN, Json(error));
+                    }
+                    actions.lock().unwrap().push(action);
+                    (StatusCode::OK, Json(serde_json::json!({"status": "RUNNING"})))
+                }),
+            )
+    }
+
+    #[tokio::test]
+    async fn lists_instances_from_fake_server() {
+        let app = Router::new().route(
+            "/compute/v1/projects/:project/aggregated/instances",
+            get(aggregated_list),
+        );
+        let provider = fake_provider(app, StopMode::Stop);
+        let instances = provider.list().await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].id, "europe-west1-b/vm0");
+        assert_eq!(instances[0].state, crate::core::State::Off);
+    }
+
+    #[tokio::test]
+    async fn resumes_suspended_instances_and_suspends_them_back() {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let provider = fake_provider(zone(Arc::clone(&actions)), StopMode::Suspend);
+
+        provider.start("europe-west1-b/vm0").await.unwrap();
+        provider.stop("europe-west1-b/vm0").await.unwrap();
+        assert_eq!(*actions.lock().unwrap(), ["resume", "suspend"]);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_instances_and_classifies_errors() {
+        let provider = fake_provider(zone(Arc::default()), StopMode::Stop);
+
+        assert!(provider.get("europe-west1-b/vm1").await.unwrap().is_none());
+
+        let err = provider.stop("europe-west1-b/vm0").await.unwrap_err();
+        let err = err.downcast_ref::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Forbidden);
+        let details = err.details.as_ref().unwrap();
+        assert_eq!(details.code.as_deref(), Some("forbidden"));
+    }
+}
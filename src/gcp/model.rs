@@ -0,0 +1,45 @@
+//! Compute Engine models.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// Like the other providers' models, these only declare the fields we use.
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceAggregatedList {
+    /// The instances, keyed by scope, e.g. `zones/europe-west1-b`.
+    #[serde(default)]
+    pub items: HashMap<String, InstancesScopedList>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstancesScopedList {
+    /// Absent from the scopes without instances.
+    #[serde(default)]
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    pub name: String,
+    /// The URL of the zone of the instance.
+    #[serde(default)]
+    pub zone: String,
+    /// The status of the instance, e.g. `RUNNING`.
+    #[serde(default)]
+    pub status: String,
+}
+
+pub const STATUS_PROVISIONING: &str = "PROVISIONING";
+pub const STATUS_STAGING: &str = "STAGING";
+pub const STATUS_RUNNING: &str = "RUNNING";
+pub const STATUS_STOPPING: &str = "STOPPING";
+pub const STATUS_STOPPED: &str = "STOPPED";
+pub const STATUS_SUSPENDING: &str = "SUSPENDING";
+pub const STATUS_SUSPENDED: &str = "SUSPENDED";
+pub const STATUS_TERMINATED: &str = "TERMINATED";
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The `error` object Compute Engine puts in the body of failed
+    /// responses, carrying the canonical status and the reasons.
+    pub error: Option<ErrorDetail>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ErrorDetail {
+    /// The error message.
+    pub message: Option<String>,
+    /// The canonical status, e.g. `NOT_FOUND`.
+    pub status: Option<String>,
+    #[serde(default)]
+    pub errors: Vec<ErrorReason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ErrorReason {
+    /// The error reason, e.g. `resourceNotReady`.
+    pub reason: Option<String>,
+}
+
+impl ErrorDetail {
+    /// The most specific code available.
+    fn code(&self) -> Option<String> {
+        self.errors
+            .iter()
+            .find_map(|error| error.reason.clone())
+            .or_else(|| self.status.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let error = match res.bytes().await {
+        Ok(body) => serde_json::from_slice::<ErrorResponse>(&body)
+            .ok()
+            .map(|res| res.error),
+        Err(_) => None,
+    };
+
+    Err(ServerError {
+        status_code: status.as_u16(),
+        error,
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.error.as_ref().and_then(ErrorDetail::code),
+            message: err.error.as_ref().and_then(|error| error.message.clone()),
+            request_id: None,
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Hetzner Cloud provider implementation.
+//!
+//! Our IDs are the server IDs.
+
+use std::time::Duration;
+
+use reqwest::Method;
+
+use self::utils::{check_status, ServerError};
+use crate::core::action::{self, ActionStatus, WaitError};
+
+mod model;
+mod utils;
+
+const RESOURCE_TYPE: &str = "hcloud/server";
+
+/// The largest page the API returns.
+const PER_PAGE: usize = 50;
+
+/// How often to poll an action while waiting for it; the API allows 3600
+/// requests an hour.
+const ACTION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The public Hetzner Cloud API.
+pub const DEFAULT_ENDPOINT: &str = "https://api.hetzner.cloud/v1";
+
+pub struct Provider {
+    pub client: reqwest::Client,
+    /// The Hetzner Cloud API, or a local stand-in.
+    pub endpoint: String,
+    /// A project's API token.
+    pub token: String,
+    pub stop_mode: StopMode,
+    /// How long to wait for a power action to complete.
+    pub action_timeout: Duration,
+}
+
+/// How to stop a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Send an ACPI shutdown request, which the OS may ignore.
+    Shutdown,
+    /// Cut the power immediately.
+    PowerOff,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(Self::Shutdown),
+            "power-off" => Ok(Self::PowerOff),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("action {id} failed: {message}")]
+    Action { id: u64, message: String },
+    #[error("action {0} is still running")]
+    ActionTimeout(u64),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Reqwest(_) | Self::ActionTimeout(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::Action { .. } => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(err.into()),
+            Self::Action { message, .. } => Some(crate::core::ProviderErrorDetails {
+                message: Some(message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Provider {
+    async fn request<T>(&self, method: Method, path: &str) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let url = format!("{}{}", self.endpoint, path);
+        let res = self
+            .client
+            .request(method, url)
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        let res = check_status(res).await?;
+        Ok(res.json().await?)
+    }
+
+    async fn list_servers(&self) -> Result<Vec<model::Server>, Error> {
+        let mut servers = Vec::new();
+        let mut page = 1;
+        loop {
+            let path = format!("/servers?page={}&per_page={}", page, PER_PAGE);
+            let list: model::ServerList = self.request(Method::GET, &path).await?;
+            servers.extend(list.servers);
+            match list.meta.pagination.next_page {
+                Some(next_page) => page = next_page,
+                None => return Ok(servers),
+            }
+        }
+    }
+
+    async fn get_server(&self, id: &crate::core::IdRef) -> Result<Option<model::Server>, Error> {
+        let id: u64 = match id.parse() {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        match self
+            .request::<model::ServerResponse>(Method::GET, &format!("/servers/{}", id))
+            .await
+        {
+            Ok(res) => Ok(Some(res.server)),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Run a power action, such as `poweron`, and wait for it.
+    async fn server_action(&self, id: &crate::core::IdRef, action: &str) -> Result<(), Error> {
+        let id: u64 = id.parse().map_err(|_| ServerError {
+            status_code: 404,
+            error: None,
+        })?;
+        let path = format!("/servers/{}/actions/{}", id, action);
+        let res: model::ActionResponse = self.request(Method::POST, &path).await?;
+        self.wait_for_action(res.action.id).await
+    }
+
+    async fn wait_for_action(&self, id: u64) -> Result<(), Error> {
+        let path = &format!("/actions/{}", id);
+        let result = action::wait_for(ACTION_POLL_INTERVAL, self.action_timeout, || async move {
+            let res: model::ActionResponse = self.request(Method::GET, path).await?;
+            Ok(match res.action.status.as_str() {
+                model::ACTION_STATUS_RUNNING => ActionStatus::Running,
+                model::ACTION_STATUS_SUCCESS => ActionStatus::Succeeded,
+                _ => ActionStatus::Failed(
+                    res.action
+                        .error
+                        .map(|error| error.message)
+                        .unwrap_or_default(),
+                ),
+            })
+        })
+        .await;
+        result.map_err(|err| match err {
+            WaitError::Poll(err) => err,
+            WaitError::Failed(message) => Error::Action { id, message },
+            WaitError::Timeout => Error::ActionTimeout(id),
+        })
+    }
+
+    fn server_to_instance(server: model::Server) -> crate::core::Instance {
+        let state = match server.status.as_str() {
+            model::SERVER_STATUS_RUNNING => crate::core::State::On,
+            model::SERVER_STATUS_OFF => crate::core::State::Off,
+            model::SERVER_STATUS_INITIALIZING
+            | model::SERVER_STATUS_STARTING
+            | model::SERVER_STATUS_STOPPING => crate::core::State::InProgress,
+            // Migrating, rebuilding, deleting or unknown.
+            _ => crate::core::State::Other,
+        };
+        crate::core::Instance {
+            id: server.id.to_string(),
+            display_name: server.name,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: 4,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let servers = self.list_servers().await.map_err(Error::into_core)?;
+        Ok(servers.into_iter().map(Self::server_to_instance).collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.request::<model::ServerList>(Method::GET, "/servers?per_page=1")
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let server = self.get_server(id).await.map_err(Error::into_core)?;
+        Ok(server.map(Self::server_to_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.server_action(id, "poweron")
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let action = match self.stop_mode {
+            StopMode::Shutdown => "shutdown",
+            StopMode::PowerOff => "poweroff",
+        };
+        self.server_action(id, action)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::SocketAddr};
+
+    use axum::{
+        extract::{Path, Query},
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    async fn servers(Query(query): Query<HashMap<String, u64>>) -> Json<serde_json::Value> {
+        let next_page = match query["page"] {
+            1 => serde_json::json!(2),
+            _ => serde_json::Value::Null,
+        };
+        Json(serde_json::json!({
+            "servers": [{"id": query["page"], "name": "web", "status": "starting"}],
+            "meta": {"pagination": {"page": query["page"], "next_page": next_page}},
+        }))
+    }
+
+    async fn poweron() -> Json<serde_json::Value> {
+        Json(serde_json::json!({"action": {"id": 13, "status": "running", "error": null}}))
+    }
+
+    async fn action() -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "action": {"id": 13, "status": "error", "error": {"code": "action_failed", "message": "Server is locked"}},
+        }))
+    }
+
+    fn error(
+        status: StatusCode,
+        code: &str,
+        message: &str,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        (
+            status,
+            Json(serde_json::json!({
+                "error": {"code": code, "message": message, "details": {}},
+            })),
+        )
+    }
+
+    async fn server(Path(id): Path<u64>) -> (StatusCode, Json<serde_json::Value>) {
+        match id {
+            2 => (
+                StatusCode::OK,
+                Json(serde_json::json!({"server": {"id": 2, "name": "web", "status": "off"}})),
+            ),
+            _ => error(StatusCode::NOT_FOUND, "not_found", "server not found"),
+        }
+    }
+
+    async fn shutdown(Path(id): Path<u64>) -> (StatusCode, Json<serde_json::Value>) {
+        match id {
+            2 => (
+                StatusCode::CREATED,
+                Json(serde_json::json!({"action": {"id": 14, "status": "running", "error": null}})),
+            ),
+            _ => error(
+                StatusCode::LOCKED,
+                "locked",
+                "server is locked by another action",
+            ),
+        }
+    }
+
+    async fn finished_action() -> Json<serde_json::Value> {
+        Json(serde_json::json!({"action": {"id": 14, "status": "success", "error": null}}))
+    }
+
+    async fn rate_limited() -> (StatusCode, Json<serde_json::Value>) {
+        error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limit_exceeded",
+            "limit of 3600 requests per hour reached",
+        )
+    }
+
+    fn fake_provider(app: Router) -> Provider {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            client: reqwest::Client::new(),
+            endpoint: format!("http://{}", addr),
+            token: "token".to_owned(),
+            stop_mode: StopMode::Shutdown,
+            action_timeout: Duration::from_secs(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_and_starts_servers_on_fake_api() {
+        let app = Router::new()
+            .route("/servers", get(servers))
+            .route("/servers/2/actions/poweron", post(poweron))
+            .route("/actions/13", get(action));
+        let provider = fake_provider(app);
+        let instances = provider.list().await.unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[1].id, "2");
+        assert_eq!(instances[1].state, crate::core::State::InProgress);
+
+        let err = provider.start("2").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("Server is locked")
+        );
+    }
+
+    #[tokio::test]
+    async fn shuts_servers_down_and_maps_api_errors() {
+        let app = Router::new()
+            .route("/servers", get(rate_limited))
+            .route("/servers/:id", get(server))
+            .route("/servers/:id/actions/shutdown", post(shutdown))
+            .route("/actions/14", get(finished_action));
+        let provider = fake_provider(app);
+
+        provider.stop("2").await.unwrap();
+        let instance = provider.get("2").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::Off);
+        assert!(provider.get("3").await.unwrap().is_none());
+        assert!(provider.get("web").await.unwrap().is_none());
+
+        let err = provider
+            .stop("3")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Other);
+        assert_eq!(err.details.unwrap().code.as_deref(), Some("locked"));
+        let err = provider
+            .start("web")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+        let err = provider
+            .list()
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::RateLimited);
+        assert_eq!(
+            err.details.unwrap().code.as_deref(),
+            Some("rate_limit_exceeded")
+        );
+    }
+}
@@ -0,0 +1,68 @@
+//! Hetzner Cloud API models.
+
+use serde::Deserialize;
+
+// Like the other providers' models, these only declare the fields we use.
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerList {
+    pub servers: Vec<Server>,
+    #[serde(default)]
+    pub meta: Meta,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Meta {
+    #[serde(default)]
+    pub pagination: Pagination,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Pagination {
+    pub next_page: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerResponse {
+    pub server: Server,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Server {
+    pub id: u64,
+    pub name: String,
+    pub status: String,
+}
+
+pub const SERVER_STATUS_RUNNING: &str = "running";
+pub const SERVER_STATUS_OFF: &str = "off";
+pub const SERVER_STATUS_INITIALIZING: &str = "initializing";
+pub const SERVER_STATUS_STARTING: &str = "starting";
+pub const SERVER_STATUS_STOPPING: &str = "stopping";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ActionResponse {
+    pub action: Action,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Action {
+    pub id: u64,
+    pub status: String,
+    pub error: Option<ErrorDetail>,
+}
+
+pub const ACTION_STATUS_RUNNING: &str = "running";
+pub const ACTION_STATUS_SUCCESS: &str = "success";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorDetail {
+    /// The error code, e.g. `not_found`.
+    pub code: String,
+    pub message: String,
+}
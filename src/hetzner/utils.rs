@@ -0,0 +1,39 @@
+use super::model;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The body's `error`, whose `code` (such as `locked` or
+    /// `rate_limit_exceeded`) says more than the status; proxies in front of
+    /// the API may answer without one.
+    pub error: Option<model::ErrorDetail>,
+}
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let error = res
+        .json::<model::ErrorResponse>()
+        .await
+        .ok()
+        .map(|res| res.error);
+    Err(ServerError {
+        status_code: status.as_u16(),
+        error,
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.error.as_ref().map(|error| error.code.clone()),
+            message: err.error.as_ref().map(|error| error.message.clone()),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! How to reach the API server: from inside the cluster with the pod's
+//! service account, or from outside with a kubeconfig file.
+
+use std::path::{Path, PathBuf};
+
+use super::model;
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// A connection to an API server.
+pub struct Cluster {
+    pub client: reqwest::Client,
+    /// The base URL of the API server, e.g. `https://10.0.0.1:443`.
+    pub server: String,
+    pub auth: Auth,
+}
+
+/// How requests are authenticated, besides client certificates which are
+/// part of the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    None,
+    Bearer(String),
+    /// A bearer token read from a file before each request, since projected
+    /// service account tokens are rotated.
+    BearerFile(PathBuf),
+    Basic {
+        username: String,
+        password: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("invalid kubeconfig: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid base64 data: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("not running in a cluster: {0} is not set")]
+    NotInCluster(&'static str),
+    #[error("no {kind} named {name:?} in the kubeconfig")]
+    Missing { kind: &'static str, name: String },
+    #[error("credential plugins are not supported, use a token or a client certificate")]
+    UnsupportedAuth,
+}
+
+fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    std::fs::read(path).map_err(|source| Error::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Read inline base64 data, or the file it is an alternative to, whose path
+/// is relative to the kubeconfig's directory.
+fn read_data(
+    data: Option<&str>,
+    path: Option<&str>,
+    base_dir: &Path,
+) -> Result<Option<Vec<u8>>, Error> {
+    match (data, path) {
+        (Some(data), _) => Ok(Some(base64::decode(data)?)),
+        (None, Some(path)) => Ok(Some(read(base_dir.join(path))?)),
+        (None, None) => Ok(None),
+    }
+}
+
+impl Cluster {
+    /// Connect with the pod's service account.
+    pub fn in_cluster() -> Result<Self, Error> {
+        let host = std::env::var("KUBERNETES_SERVICE_HOST")
+            .map_err(|_| Error::NotInCluster("KUBERNETES_SERVICE_HOST"))?;
+        let port = std::env::var("KUBERNETES_SERVICE_PORT")
+            .map_err(|_| Error::NotInCluster("KUBERNETES_SERVICE_PORT"))?;
+        let server = if host.contains(':') {
+            format!("https://[{}]:{}", host, port)
+        } else {
+            format!("https://{}:{}", host, port)
+        };
+
+        let dir = Path::new(SERVICE_ACCOUNT_DIR);
+        let ca = reqwest::Certificate::from_pem(&read(dir.join("ca.crt"))?)?;
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(ca)
+            .build()?;
+
+        Ok(Self {
+            client,
+            server,
+            auth: Auth::BearerFile(dir.join("token")),
+        })
+    }
+
+    /// Connect as a kubeconfig file says, with its current context unless
+    /// another one is given.
+    pub fn from_kubeconfig(path: impl AsRef<Path>, context: Option<&str>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let kubeconfig: model::Kubeconfig = serde_yaml::from_slice(&read(path)?)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let context_name = context.unwrap_or(&kubeconfig.current_context);
+        let context = find(&kubeconfig.contexts, "context", context_name)?;
+        let cluster = find(&kubeconfig.clusters, "cluster", &context.cluster)?;
+        let user = match context.user.as_str() {
+            "" => model::AuthInfo::default(),
+            name => find(&kubeconfig.users, "user", name)?.clone(),
+        };
+
+        let mut client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .danger_accept_invalid_certs(cluster.insecure_skip_tls_verify);
+        let ca = read_data(
+            cluster.certificate_authority_data.as_deref(),
+            cluster.certificate_authority.as_deref(),
+            base_dir,
+        )?;
+        if let Some(ca) = ca {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(&ca)?);
+        }
+        let certificate = read_data(
+            user.client_certificate_data.as_deref(),
+            user.client_certificate.as_deref(),
+            base_dir,
+        )?;
+        let key = read_data(
+            user.client_key_data.as_deref(),
+            user.client_key.as_deref(),
+            base_dir,
+        )?;
+        if let (Some(mut certificate), Some(key)) = (certificate, key) {
+            certificate.push(b'\n');
+            certificate.extend(key);
+            client = client.identity(reqwest::Identity::from_pem(&certificate)?);
+        }
+
+        let auth = match user {
+            model::AuthInfo {
+                token: Some(token), ..
+            } => Auth::Bearer(token),
+            model::AuthInfo {
+                token_file: Some(token_file),
+                ..
+            } => Auth::BearerFile(base_dir.join(token_file)),
+            model::AuthInfo {
+                username: Some(username),
+                password: Some(password),
+                ..
+            } => Auth::Basic { username, password },
+            model::AuthInfo { exec: Some(_), .. }
+            | model::AuthInfo {
+                auth_provider: Some(_),
+                ..
+            } => return Err(Error::UnsupportedAuth),
+            _ => Auth::None,
+        };
+
+        Ok(Self {
+            client: client.build()?,
+            server: cluster.server.trim_end_matches('/').to_owned(),
+            auth,
+        })
+    }
+
+    /// Authenticate a request.
+    pub(super) fn authenticate(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder, std::io::Error> {
+        Ok(match &self.auth {
+            Auth::None => request,
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::BearerFile(path) => request.bearer_auth(std::fs::read_to_string(path)?.trim()),
+            Auth::Basic { username, password } => request.basic_auth(username, Some(password)),
+        })
+    }
+}
+
+fn find<'a, T>(
+    items: &'a [model::Named<T>],
+    kind: &'static str,
+    name: &str,
+) -> Result<&'a T, Error> {
+    items
+        .iter()
+        .find(|item| item.name == name)
+        .map(|item| &item.value)
+        .ok_or_else(|| Error::Missing {
+            kind,
+            name: name.to_owned(),
+        })
+}
@@ -0,0 +1,590 @@
+//! Kubernetes provider implementation, for Deployments and StatefulSets
+//! labelled for on/off: stopping one scales it to zero, remembering its
+//! replica count in an annotation, and starting it scales it back.
+//!
+//! Our IDs are the namespace, kind and name of the workloads, e.g.
+//! `default/deployment/web`.
+
+use reqwest::Method;
+
+pub mod config;
+mod model;
+
+/// The annotation remembering the replica count of a workload scaled to
+/// zero.
+const REPLICAS_ANNOTATION: &str = "vm-onoff/replicas";
+
+/// The replica count to restore when the annotation is missing.
+const DEFAULT_REPLICAS: i32 = 1;
+
+/// How many workloads to list per request.
+const PAGE_SIZE: u32 = 500;
+
+/// The label selector used unless another one is configured.
+pub const DEFAULT_LABEL_SELECTOR: &str = "vm-onoff/enabled=true";
+
+pub struct Provider {
+    pub cluster: config::Cluster,
+    /// Only the workloads of this namespace are managed if set, otherwise
+    /// those of all namespaces.
+    pub namespace: Option<String>,
+    /// Selects the workloads to manage, e.g. `vm-onoff/enabled=true`.
+    pub label_selector: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Deployment,
+    StatefulSet,
+}
+
+impl Kind {
+    const ALL: [Self; 2] = [Self::Deployment, Self::StatefulSet];
+
+    /// The name used in our IDs.
+    fn id_name(self) -> &'static str {
+        match self {
+            Self::Deployment => "deployment",
+            Self::StatefulSet => "statefulset",
+        }
+    }
+
+    /// The name of the API resource.
+    fn resource(self) -> &'static str {
+        match self {
+            Self::Deployment => "deployments",
+            Self::StatefulSet => "statefulsets",
+        }
+    }
+
+    fn resource_type(self) -> &'static str {
+        match self {
+            Self::Deployment => "kubernetes/deployment",
+            Self::StatefulSet => "kubernetes/statefulset",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Id {
+    namespace: String,
+    kind: Kind,
+    name: String,
+}
+
+impl TryFrom<&crate::core::IdRef> for Id {
+    type Error = crate::core::IdParsingError;
+
+    fn try_from(value: &crate::core::IdRef) -> Result<Self, Self::Error> {
+        let mut parts = value.split('/');
+        let (namespace, kind, name) = match (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            (Some(namespace), Some(kind), Some(name), None)
+                if !namespace.is_empty() && !name.is_empty() =>
+            {
+                (namespace, kind, name)
+            }
+            _ => return Err(crate::core::IdParsingError),
+        };
+        let kind = Kind::ALL
+            .into_iter()
+            .find(|k| k.id_name() == kind)
+            .ok_or(crate::core::IdParsingError)?;
+        Ok(Self {
+            namespace: namespace.to_owned(),
+            kind,
+            name: name.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The status reported in the response body, if it could be parsed.
+    pub status: Option<model::Status>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("unable to read the token: {0}")]
+    Token(#[from] std::io::Error),
+    #[error("workload not found")]
+    NotFound,
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Reqwest(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::Token(_) => crate::core::ErrorKind::Unauthorized,
+            Self::NotFound => crate::core::ErrorKind::NotFound,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(crate::core::ProviderErrorDetails {
+                status_code: Some(err.status_code),
+                code: err.status.as_ref().map(|status| status.reason.clone()),
+                message: err.status.as_ref().map(|status| status.message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let body = res.json::<model::Status>().await.ok();
+    Err(ServerError {
+        status_code: status.as_u16(),
+        status: body,
+    })
+}
+
+impl Provider {
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        patch: Option<serde_json::Value>,
+    ) -> Result<reqwest::Response, Error> {
+        let url = format!("{}{}", self.cluster.server, path);
+        let mut request = self.cluster.client.request(method, url).query(query);
+        if let Some(patch) = patch {
+            request = request
+                .header(
+                    reqwest::header::CONTENT_TYPE,
+                    "application/merge-patch+json",
+                )
+                .body(patch.to_string());
+        }
+        let request = self.cluster.authenticate(request)?;
+        let res = request.send().await?;
+        Ok(check_status(res).await?)
+    }
+
+    fn collection_path(&self, namespace: Option<&str>, kind: Kind) -> String {
+        match namespace {
+            Some(namespace) => {
+                format!("/apis/apps/v1/namespaces/{}/{}", namespace, kind.resource())
+            }
+            None => format!("/apis/apps/v1/{}", kind.resource()),
+        }
+    }
+
+    /// List the selected workloads of a kind, optionally with a field
+    /// selector too.
+    async fn list_workloads(
+        &self,
+        namespace: Option<&str>,
+        kind: Kind,
+        field_selector: Option<&str>,
+    ) -> Result<Vec<model::Workload>, Error> {
+        let path = self.collection_path(namespace, kind);
+        let limit = PAGE_SIZE.to_string();
+        let mut workloads = Vec::new();
+        let mut continue_: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("labelSelector", self.label_selector.as_str()),
+                ("limit", limit.as_str()),
+            ];
+            if let Some(field_selector) = field_selector {
+                query.push(("fieldSelector", field_selector));
+            }
+            if let Some(continue_) = &continue_ {
+                query.push(("continue", continue_.as_str()));
+            }
+            let res = self.send(Method::GET, &path, &query, None).await?;
+            let list: model::List<model::Workload> = res.json().await?;
+            workloads.extend(list.items);
+            continue_ = list.metadata.continue_.filter(|token| !token.is_empty());
+            if continue_.is_none() {
+                return Ok(workloads);
+            }
+        }
+    }
+
+    /// Get a workload, `None` if it does not exist or is not selected.
+    async fn get_workload(&self, id: &Id) -> Result<Option<model::Workload>, Error> {
+        if matches!(&self.namespace, Some(namespace) if *namespace != id.namespace) {
+            return Ok(None);
+        }
+        // Listing with a field selector lets the API server apply the label
+        // selector for us.
+        let field_selector = format!("metadata.name={}", id.name);
+        let workloads = self
+            .list_workloads(Some(&id.namespace), id.kind, Some(&field_selector))
+            .await?;
+        Ok(workloads.into_iter().next())
+    }
+
+    fn detect_state(workload: &model::Workload) -> crate::core::State {
+        let replicas = workload.spec.replicas.unwrap_or(1);
+        let status = &workload.status;
+        if status.observed_generation < workload.metadata.generation {
+            return crate::core::State::InProgress;
+        }
+        match (replicas, status.replicas, status.ready_replicas) {
+            (0, 0, _) => crate::core::State::Off,
+            (0, _, _) => crate::core::State::InProgress,
+            (replicas, current, ready) if current == replicas && ready >= replicas => {
+                crate::core::State::On
+            }
+            _ => crate::core::State::InProgress,
+        }
+    }
+
+    fn workload_to_instance(kind: Kind, workload: model::Workload) -> crate::core::Instance {
+        let state = Self::detect_state(&workload);
+        let metadata = workload.metadata;
+        crate::core::Instance {
+            id: format!(
+                "{}/{}/{}",
+                metadata.namespace,
+                kind.id_name(),
+                metadata.name
+            ),
+            display_name: format!("{}/{}", metadata.namespace, metadata.name),
+            resource_type: kind.resource_type().to_owned(),
+            state,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    /// Scale a workload, guarding against concurrent changes with its
+    /// resource version.
+    async fn scale(
+        &self,
+        id: &Id,
+        workload: &model::Workload,
+        replicas: i32,
+        remembered: Option<i32>,
+    ) -> Result<(), Error> {
+        let patch = serde_json::json!({
+            "metadata": {
+                "resourceVersion": workload.metadata.resource_version,
+                "annotations": {
+                    REPLICAS_ANNOTATION: remembered.map(|replicas| replicas.to_string()),
+                },
+            },
+            "spec": {"replicas": replicas},
+        });
+        let path = format!(
+            "{}/{}",
+            self.collection_path(Some(&id.namespace), id.kind),
+            id.name
+        );
+        self.send(Method::PATCH, &path, &[], Some(patch)).await?;
+        Ok(())
+    }
+
+    async fn start_workload(&self, id: &Id) -> Result<(), Error> {
+        let workload = self.get_workload(id).await?.ok_or(Error::NotFound)?;
+        if workload.spec.replicas != Some(0) {
+            return Ok(());
+        }
+        let replicas = workload
+            .metadata
+            .annotations
+            .get(REPLICAS_ANNOTATION)
+            .and_then(|replicas| replicas.parse().ok())
+            .filter(|&replicas| replicas > 0)
+            .unwrap_or(DEFAULT_REPLICAS);
+        self.scale(id, &workload, replicas, None).await
+    }
+
+    async fn stop_workload(&self, id: &Id) -> Result<(), Error> {
+        let workload = self.get_workload(id).await?.ok_or(Error::NotFound)?;
+        let replicas = workload.spec.replicas.unwrap_or(1);
+        if replicas == 0 {
+            return Ok(());
+        }
+        self.scale(id, &workload, 0, Some(replicas)).await
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let mut instances = Vec::new();
+        for kind in Kind::ALL {
+            let workloads = self
+                .list_workloads(self.namespace.as_deref(), kind, None)
+                .await
+                .map_err(Error::into_core)?;
+            instances.extend(
+                workloads
+                    .into_iter()
+                    .map(|workload| Self::workload_to_instance(kind, workload)),
+            );
+        }
+        Ok(instances)
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        let path = self.collection_path(self.namespace.as_deref(), Kind::Deployment);
+        self.send(Method::GET, &path, &[("limit", "1")], None)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let id = Id::try_from(id)?;
+        let workload = self.get_workload(&id).await.map_err(Error::into_core)?;
+        Ok(workload.map(|workload| Self::workload_to_instance(id.kind, workload)))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = Id::try_from(id)?;
+        self.start_workload(&id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let id = Id::try_from(id)?;
+        self.stop_workload(&id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Extension, Path, Query},
+        http::StatusCode,
+        routing::get,
+        AddExtensionLayer, Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    type Patches = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    async fn deployments(
+        Path(namespace): Path<String>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Json<serde_json::Value> {
+        assert_eq!(namespace, "default");
+        assert_eq!(query["labelSelector"], DEFAULT_LABEL_SELECTOR);
+        assert_eq!(query["fieldSelector"], "metadata.name=web");
+        Json(serde_json::json!({
+            "items": [{
+                "metadata": {
+                    "name": "web",
+                    "namespace": "default",
+                    "generation": 2,
+                    "resourceVersion": "42",
+                },
+                "spec": {"replicas": 3},
+                "status": {"replicas": 3, "readyReplicas": 3, "observedGeneration": 2},
+            }],
+            "metadata": {},
+        }))
+    }
+
+    async fn patch_deployment(
+        Extension(patches): Extension<Patches>,
+        Json(patch): Json<serde_json::Value>,
+    ) -> Json<serde_json::Value> {
+        patches.lock().unwrap().push(patch);
+        Json(serde_json::json!({}))
+    }
+
+    async fn stateful_sets(
+        Path(namespace): Path<String>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        if namespace == "kube-system" {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "kind": "Status",
+                    "status": "Failure",
+                    "message": "statefulsets.apps is forbidden",
+                    "reason": "Forbidden",
+                    "code": 403,
+                })),
+            );
+        }
+        let items = match query["fieldSelector"].as_str() {
+            "metadata.name=db" => vec![serde_json::json!({
+                "metadata": {
+                    "name": "db",
+                    "namespace": "default",
+                    "generation": 5,
+                    "resourceVersion": "7",
+                    "annotations": {REPLICAS_ANNOTATION: "2"},
+                },
+                "spec": {"replicas": 0},
+                "status": {"replicas": 0, "observedGeneration": 5},
+            })],
+            _ => Vec::new(),
+        };
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({"items": items, "metadata": {}})),
+        )
+    }
+
+    async fn patch_stateful_set(
+        Extension(patches): Extension<Patches>,
+        Json(patch): Json<serde_json::Value>,
+    ) -> (StatusCode, Json<serde_json::Value>) {
+        patches.lock().unwrap().push(patch);
+        (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "kind": "Status",
+                "status": "Failure",
+                "message": "the object has been modified",
+                "reason": "Conflict",
+                "code": 409,
+            })),
+        )
+    }
+
+    fn fake_provider(app: Router) -> Provider {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            cluster: config::Cluster {
+                client: reqwest::Client::new(),
+                server: format!("http://{}", addr),
+                auth: config::Auth::None,
+            },
+            namespace: None,
+            label_selector: DEFAULT_LABEL_SELECTOR.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn scales_deployment_to_zero_on_fake_api_server() {
+        let patches = Patches::default();
+        let app = Router::new()
+            .route(
+                "/apis/apps/v1/namespaces/:namespace/deployments",
+                get(deployments),
+            )
+            .route(
+                "/apis/apps/v1/namespaces/default/deployments/web",
+                axum::routing::patch(patch_deployment),
+            )
+            .layer(AddExtensionLayer::new(patches.clone()));
+        let provider = fake_provider(app);
+        let instance = provider
+            .get("default/deployment/web")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(instance.state, crate::core::State::On);
+
+        provider.stop("default/deployment/web").await.unwrap();
+        let patches = patches.lock().unwrap();
+        assert_eq!(patches[0]["spec"]["replicas"], 0);
+        assert_eq!(patches[0]["metadata"]["resourceVersion"], "42");
+        assert_eq!(
+            patches[0]["metadata"]["annotations"][REPLICAS_ANNOTATION],
+            "3"
+        );
+        assert!(Id::try_from("default/pod/web").is_err());
+    }
+
+    #[tokio::test]
+    async fn restores_replicas_and_reports_api_errors() {
+        let patches = Patches::default();
+        let app = Router::new()
+            .route(
+                "/apis/apps/v1/namespaces/:namespace/statefulsets",
+                get(stateful_sets),
+            )
+            .route(
+                "/apis/apps/v1/namespaces/default/statefulsets/db",
+                axum::routing::patch(patch_stateful_set),
+            )
+            .layer(AddExtensionLayer::new(patches.clone()));
+        let provider = fake_provider(app);
+
+        let instance = provider
+            .get("default/statefulset/db")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(instance.state, crate::core::State::Off);
+        assert!(provider
+            .get("default/statefulset/gone")
+            .await
+            .unwrap()
+            .is_none());
+
+        // Someone scaled it in the meantime: the API server turns down our
+        // patch rather than overwrite their change.
+        let err = provider
+            .start("default/statefulset/db")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        let details = err.details.unwrap();
+        assert_eq!(details.status_code, Some(409));
+        assert_eq!(details.code.as_deref(), Some("Conflict"));
+        {
+            let patches = patches.lock().unwrap();
+            assert_eq!(patches[0]["spec"]["replicas"], 2);
+            assert_eq!(patches[0]["metadata"]["resourceVersion"], "7");
+        }
+
+        let err = provider
+            .get("kube-system/statefulset/etcd")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Forbidden);
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("statefulsets.apps is forbidden")
+        );
+        let err = provider
+            .stop("default/statefulset/gone")
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+    }
+}
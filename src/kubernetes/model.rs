@@ -0,0 +1,126 @@
+//! Kubernetes API models, for the `apps/v1` workloads.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+// Like the other providers' models, these only declare the fields we use.
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct List<T> {
+    pub items: Vec<T>,
+    #[serde(default)]
+    pub metadata: ListMeta,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ListMeta {
+    /// The token to get the next page with, empty on the last one.
+    #[serde(rename = "continue", default)]
+    pub continue_: Option<String>,
+}
+
+/// A Deployment or a StatefulSet, which share the fields we use.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Workload {
+    pub metadata: ObjectMeta,
+    #[serde(default)]
+    pub spec: WorkloadSpec,
+    #[serde(default)]
+    pub status: WorkloadStatus,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectMeta {
+    pub name: String,
+    pub namespace: String,
+    #[serde(default)]
+    pub generation: i64,
+    #[serde(default)]
+    pub resource_version: String,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct WorkloadSpec {
+    /// The desired number of pods, 1 when not set.
+    pub replicas: Option<i32>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkloadStatus {
+    /// The number of pods, ready or not.
+    #[serde(default)]
+    pub replicas: i32,
+    #[serde(default)]
+    pub ready_replicas: i32,
+    #[serde(default)]
+    pub observed_generation: i64,
+}
+
+/// The body of failed responses.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Status {
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// A kubeconfig file, as written by `kubectl`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Kubeconfig {
+    #[serde(default)]
+    pub current_context: String,
+    #[serde(default)]
+    pub contexts: Vec<Named<Context>>,
+    #[serde(default)]
+    pub clusters: Vec<Named<Cluster>>,
+    #[serde(default)]
+    pub users: Vec<Named<AuthInfo>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Named<T> {
+    pub name: String,
+    #[serde(alias = "context", alias = "cluster", alias = "user")]
+    pub value: T,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Context {
+    pub cluster: String,
+    #[serde(default)]
+    pub user: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Cluster {
+    pub server: String,
+    pub certificate_authority: Option<String>,
+    pub certificate_authority_data: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_tls_verify: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuthInfo {
+    pub token: Option<String>,
+    #[serde(rename = "tokenFile")]
+    pub token_file: Option<String>,
+    pub client_certificate: Option<String>,
+    pub client_certificate_data: Option<String>,
+    pub client_key: Option<String>,
+    pub client_key_data: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Credential plugins, which we do not run.
+    pub exec: Option<serde_yaml::Value>,
+    pub auth_provider: Option<serde_yaml::Value>,
+}
@@ -1,3 +1,17 @@
 pub mod api;
+pub mod auth;
+pub mod aws;
 pub mod azure;
 pub mod core;
+pub mod digitalocean;
+pub mod docker;
+pub mod gcp;
+pub mod hetzner;
+pub mod kubernetes;
+pub mod libvirt;
+pub mod openstack;
+pub mod plugin;
+pub mod proxmox;
+pub mod redfish;
+pub mod script;
+pub mod wol;
@@ -0,0 +1,318 @@
+//! libvirt provider implementation, driving `virsh`.
+//!
+//! Going through `virsh` supports every connection URI libvirt does, be it
+//! the local socket (`qemu:///system`), a remote host (`qemu+ssh://host/system`)
+//! or the `test:///default` driver, without linking to libvirt.
+//!
+//! Our IDs are the domain names, which are unique per connection.
+
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use tokio::process::Command;
+
+const RESOURCE_TYPE: &str = "libvirt/domain";
+
+/// How many `virsh` processes may run at once.
+const MAX_CONCURRENT_COMMANDS: usize = 8;
+
+pub struct Provider {
+    /// The connection URI, e.g. `qemu:///system`.
+    pub uri: String,
+    /// The `virsh` executable.
+    pub virsh: PathBuf,
+    pub stop_mode: StopMode,
+    /// How long a `virsh` command may take.
+    pub timeout: Duration,
+}
+
+/// How to stop a domain; starting it back always uses `virsh start`, which
+/// restores a managed save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Ask the guest to shut down (`virsh shutdown`).
+    Shutdown,
+    /// Power the domain off (`virsh destroy`).
+    Destroy,
+    /// Save the domain's memory to disk (`virsh managedsave`).
+    ManagedSave,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(Self::Shutdown),
+            "destroy" => Ok(Self::Destroy),
+            "managed-save" => Ok(Self::ManagedSave),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to run virsh: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("virsh timed out")]
+    Timeout,
+    #[error("virsh: {message}")]
+    Virsh { message: String },
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Spawn(_) | Self::Timeout => crate::core::ErrorKind::Unavailable,
+            Self::Virsh { message } => classify(message),
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Virsh { message } => Some(crate::core::ProviderErrorDetails {
+                message: Some(message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+/// Classify a `virsh` error message; libvirt has no machine-readable codes
+/// on the command line.
+fn classify(message: &str) -> crate::core::ErrorKind {
+    let message = message.to_lowercase();
+    if message.contains("domain not found") {
+        crate::core::ErrorKind::NotFound
+    } else if message.contains("is already running")
+        || message.contains("is not running")
+        || message.contains("operation is not valid")
+    {
+        crate::core::ErrorKind::Conflict
+    } else if message.contains("authentication") {
+        crate::core::ErrorKind::Unauthorized
+    } else if message.contains("access denied") || message.contains("permission denied") {
+        crate::core::ErrorKind::Forbidden
+    } else if message.contains("failed to connect") {
+        crate::core::ErrorKind::Unavailable
+    } else {
+        crate::core::ErrorKind::Other
+    }
+}
+
+/// Map the output of `virsh domstate` to our states.
+fn parse_state(output: &str) -> crate::core::State {
+    match output.trim() {
+        "running" | "idle" => crate::core::State::On,
+        "shut off" => crate::core::State::Off,
+        "in shutdown" => crate::core::State::InProgress,
+        // Paused, crashed, suspended by the guest, or no state.
+        _ => crate::core::State::Other,
+    }
+}
+
+impl Provider {
+    /// Run `virsh` against our connection, returning its output.
+    async fn virsh(&self, args: &[&str]) -> Result<String, Error> {
+        let child = Command::new(&self.virsh)
+            .arg("--quiet")
+            .arg("--connect")
+            .arg(&self.uri)
+            .args(args)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(self.timeout, child)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Spawn)?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = stderr
+                .lines()
+                .map(|line| line.trim_start_matches("error: ").trim())
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(": ");
+            return Err(Error::Virsh { message });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    async fn get_domain(&self, name: &str) -> Result<crate::core::Instance, Error> {
+        let output = self.virsh(&["domstate", "--", name]).await?;
+        Ok(crate::core::Instance {
+            id: name.to_owned(),
+            display_name: name.to_owned(),
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state: parse_state(&output),
+            observed_at: std::time::Instant::now(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: MAX_CONCURRENT_COMMANDS,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let output = self
+            .virsh(&["list", "--all", "--name"])
+            .await
+            .map_err(Error::into_core)?;
+        let gets: Vec<_> = output
+            .lines()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| self.get_domain(name))
+            .collect();
+        let instances = stream::iter(gets)
+            .buffered(MAX_CONCURRENT_COMMANDS)
+            .try_collect()
+            .await
+            .map_err(Error::into_core)?;
+        Ok(instances)
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        // Connecting is the check; `list` would run a command per domain.
+        self.virsh(&["uri"]).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        match self.get_domain(id).await {
+            Ok(instance) => Ok(Some(instance)),
+            Err(err) if err.kind() == crate::core::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into_core().into()),
+        }
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.virsh(&["start", "--", id])
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let command = match self.stop_mode {
+            StopMode::Shutdown => "shutdown",
+            StopMode::Destroy => "destroy",
+            StopMode::ManagedSave => "managedsave",
+        };
+        self.virsh(&[command, "--", id])
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Provider as _;
+
+    /// A stand-in for `virsh` knowing a single domain, `vm0`, which is shut
+    /// off; the commands it gets are logged to `{log}`.
+    const FAKE_VIRSH: &str = r#"#!/bin/sh
+        shift 3
+        echo "$*" >> '{log}'
+        if [ "$3" != vm0 ]; then
+            echo "error: failed to get domain '$3'" >&2
+            echo "error: Domain not found: no domain with matching name '$3'" >&2
+            exit 1
+        fi
+        case "$1" in
+            domstate) echo 'shut off' ;;
+            start) echo "Domain '$3' started" ;;
+            *)
+                echo "error: Failed to save domain '$3' state" >&2
+                echo 'error: Requested operation is not valid: domain is not running' >&2
+                exit 1
+                ;;
+        esac
+    "#;
+
+    #[tokio::test]
+    async fn runs_virsh_commands_and_classifies_their_errors() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("vm-onoff-virsh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("commands");
+        let virsh = dir.join("virsh");
+        let script = FAKE_VIRSH.replace("{log}", &log.to_string_lossy());
+        std::fs::write(&virsh, script.trim_start()).unwrap();
+        std::fs::set_permissions(&virsh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let provider = Provider {
+            uri: "test:///default".to_owned(),
+            virsh,
+            stop_mode: StopMode::ManagedSave,
+            timeout: Duration::from_secs(10),
+        };
+        assert!(provider.get("vm1").await.unwrap().is_none());
+        let instance = provider.get("vm0").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::Off);
+        provider.start("vm0").await.unwrap();
+
+        let err = provider.stop("vm0").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("Failed to save domain 'vm0' state: Requested operation is not valid: domain is not running")
+        );
+
+        let commands = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            commands.lines().collect::<Vec<_>>(),
+            [
+                "domstate -- vm1",
+                "domstate -- vm0",
+                "start -- vm0",
+                "managedsave -- vm0"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_virsh_output() {
+        assert_eq!(parse_state("running\n\n"), crate::core::State::On);
+        assert_eq!(parse_state("shut off\n"), crate::core::State::Off);
+        assert_eq!(parse_state("paused\n"), crate::core::State::Other);
+        assert_eq!(
+            classify(
+                "failed to get domain 'vm0': Domain not found: no domain with matching name 'vm0'"
+            ),
+            crate::core::ErrorKind::NotFound
+        );
+        assert_eq!(
+            classify("Failed to start domain 'test': Requested operation is not valid: domain is already running"),
+            crate::core::ErrorKind::Conflict
+        );
+    }
+}
@@ -1,62 +1,1031 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
+use anyhow::Context as _;
+use async_graphql::dataloader::DataLoader;
 use axum::{Router, Server};
-use tracing::info;
+use tracing::{info, warn};
 use vm_onoff::{
-    api::http::{axum::GraphQL, graphql},
-    azure,
+    api::http::{
+        axum::{GraphQL, TrustedProxies},
+        graphql,
+    },
+    auth::token_manager::TokenManager,
+    aws, azure,
+    core::{
+        cache::{CacheConfig, Cached},
+        health::{HealthConfig, Monitored},
+        limit::{LimitConfig, Limited},
+        reload::{ProviderFactory, Reloader},
+        Providers,
+    },
+    digitalocean, docker, gcp, hetzner, kubernetes, libvirt, openstack, plugin, proxmox, redfish,
+    script, wol,
 };
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), anyhow::Error> {
     tracing_subscriber::fmt::init();
 
     let reqwest_client = reqwest::Client::builder()
         .connection_verbose(true)
         .build()
-        .unwrap();
+        .context("unable to build the HTTP client")?;
+
+    let factory = Arc::new(Factory::new(reqwest_client));
+    let providers = factory.build().await?;
+    let core = Arc::new(vm_onoff::core::Core::new(providers));
+    let reloader = Arc::new(Reloader::new(Arc::clone(&core), factory));
+
+    tokio::spawn({
+        let reloader = Arc::clone(&reloader);
+        async move {
+            if let Err(err) = reloader.reload_on_sighup().await {
+                warn!("Unable to handle SIGHUP: {}", err);
+            }
+        }
+    });
+    if let Some(interval) = reload_poll_interval(&Env::current())? {
+        let reloader = Arc::clone(&reloader);
+        tokio::spawn(async move { reloader.watch_files(interval).await });
+    }
+
+    // Shared by all requests so that concurrent ones are batched together,
+    // a batch is queued by the limiters as the user whose request started
+    // it, including the IDs the other users asked for.
+    let instance_loader = DataLoader::new(graphql::loader::InstanceLoader {
+        core: Arc::clone(&core),
+        max_gets_per_batch: 16,
+    });
+    let mut schema = graphql::schema()
+        .data(core)
+        .data(reloader)
+        .data(instance_loader);
+    if let Some(token) = admin_token(&Env::current()) {
+        schema = schema.data(graphql::AdminToken(token));
+    }
+    let schema = schema.finish();
+
+    let app = Router::new();
+    let app = GraphQL::routes(app, schema, trusted_proxies(&Env::current())?);
+
+    info!("Playground: http://localhost:8000");
+
+    Server::bind(&"0.0.0.0:8000".parse().unwrap())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr, _>())
+        .await?;
+    Ok(())
+}
+
+/// Builds the providers from the environment and the configuration files it
+/// points to, all of which are read again on every reload; only the providers
+/// whose variables or files changed are built again.
+struct Factory {
+    reqwest_client: reqwest::Client,
+    /// The providers of the last successful build, kept by the next ones
+    /// unless what they were built from changed.
+    parts: std::sync::Mutex<HashMap<Part, Built>>,
+}
+
+/// A part of the configuration, building one provider or, for scripts and
+/// plugins, several.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Part {
+    Azure,
+    Aws,
+    Gcp,
+    Hetzner,
+    DigitalOcean,
+    OpenStack,
+    Libvirt,
+    Redfish,
+    Wol,
+    Kubernetes,
+    Docker,
+    Proxmox,
+    Scripts,
+    Plugins,
+}
+
+impl Part {
+    /// In order of precedence, the later parts winning key conflicts.
+    const ALL: [Self; 14] = [
+        Self::Azure,
+        Self::Aws,
+        Self::Gcp,
+        Self::Hetzner,
+        Self::DigitalOcean,
+        Self::OpenStack,
+        Self::Libvirt,
+        Self::Redfish,
+        Self::Wol,
+        Self::Kubernetes,
+        Self::Docker,
+        Self::Proxmox,
+        Self::Scripts,
+        Self::Plugins,
+    ];
+}
+
+#[derive(Clone)]
+struct Built {
+    inputs: Inputs,
+    providers: Vec<(String, Arc<dyn vm_onoff::core::Provider>)>,
+}
+
+impl Factory {
+    fn new(reqwest_client: reqwest::Client) -> Self {
+        Self {
+            reqwest_client,
+            parts: Default::default(),
+        }
+    }
+
+    async fn build_part(
+        &self,
+        part: Part,
+        env: &Env,
+    ) -> Result<Vec<(String, Arc<dyn vm_onoff::core::Provider>)>, anyhow::Error> {
+        let layers = layers(env)?;
+        let client = self.reqwest_client.clone();
+        let mut providers = Vec::new();
+        match part {
+            Part::Azure => {
+                let provider = azure_provider(env, client).await?;
+                providers.push(layered(env, "azure", provider, &layers)?);
+            }
+            Part::Aws => {
+                if let Some(provider) = aws_provider(env, client)? {
+                    providers.push(layered(env, "aws", provider, &layers)?);
+                }
+            }
+            Part::Gcp => {
+                if let Some(provider) = gcp_provider(env, client)? {
+                    providers.push(layered(env, "gcp", provider, &layers)?);
+                }
+            }
+            Part::Hetzner => {
+                if let Some(provider) = hetzner_provider(env, client)? {
+                    providers.push(layered(env, "hetzner", provider, &layers)?);
+                }
+            }
+            Part::DigitalOcean => {
+                if let Some(provider) = digitalocean_provider(env, client)? {
+                    providers.push(layered(env, "digitalocean", provider, &layers)?);
+                }
+            }
+            Part::OpenStack => {
+                if let Some(provider) = openstack_provider(env, client)? {
+                    providers.push(layered(env, "openstack", provider, &layers)?);
+                }
+            }
+            Part::Libvirt => {
+                if let Some(provider) = libvirt_provider(env)? {
+                    providers.push(layered(env, "libvirt", provider, &layers)?);
+                }
+            }
+            Part::Redfish => {
+                if let Some(provider) = redfish_provider(env)? {
+                    providers.push(layered(env, "redfish", provider, &layers)?);
+                }
+            }
+            Part::Wol => {
+                if let Some(provider) = wol_provider(env)? {
+                    providers.push(layered(env, "wol", provider, &layers)?);
+                }
+            }
+            Part::Kubernetes => {
+                if let Some(provider) = kubernetes_provider(env)? {
+                    providers.push(layered(env, "kubernetes", provider, &layers)?);
+                }
+            }
+            Part::Docker => {
+                if let Some(provider) = docker_provider(env)? {
+                    providers.push(layered(env, "docker", provider, &layers)?);
+                }
+            }
+            Part::Proxmox => {
+                if let Some(provider) = proxmox_provider(env)? {
+                    providers.push(layered(env, "proxmox", provider, &layers)?);
+                }
+            }
+            Part::Scripts => {
+                for (key, provider) in script_providers(env)? {
+                    providers.push(layered(env, &key, provider, &layers)?);
+                }
+            }
+            Part::Plugins => {
+                for (key, provider) in plugin_providers(env).await? {
+                    providers.push(layered(env, &key, provider, &layers)?);
+                }
+            }
+        }
+        Ok(providers)
+    }
+}
+
+#[async_trait::async_trait]
+impl ProviderFactory for Factory {
+    /// Build the parts of the configuration that changed since the last
+    /// build, keeping the providers of the others along with their cache,
+    /// circuit breaker and queue, and the plugins running.
+    async fn build(&self) -> Result<Providers, anyhow::Error> {
+        let env = Env::load()?;
+        let previous = self.parts.lock().unwrap().clone();
+        let mut parts = HashMap::new();
+        for part in Part::ALL {
+            let built = match previous.get(&part) {
+                Some(built) if env.is_unchanged(&built.inputs) => built.clone(),
+                _ => {
+                    env.take_inputs();
+                    let providers = self.build_part(part, &env).await?;
+                    Built {
+                        inputs: env.take_inputs(),
+                        providers,
+                    }
+                }
+            };
+            parts.insert(part, built);
+        }
+
+        let providers = Part::ALL
+            .iter()
+            .flat_map(|part| parts[part].providers.iter().cloned())
+            .collect();
+        // Only now that it is valid does the configuration take effect.
+        *self.parts.lock().unwrap() = parts;
+        env.publish();
+        Ok(providers)
+    }
+
+    /// `ENV_FILE`, and the files the providers were built from.
+    fn watched_files(&self) -> Vec<PathBuf> {
+        let mut files: BTreeSet<PathBuf> = std::env::var_os("ENV_FILE")
+            .map(PathBuf::from)
+            .into_iter()
+            .collect();
+        for built in self.parts.lock().unwrap().values() {
+            files.extend(built.inputs.files.keys().cloned());
+        }
+        files.into_iter().collect()
+    }
+}
+
+/// The variables read from `ENV_FILE` by the last successful build.
+static ENV_FILE_VARS: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// The configuration variables: those read from `ENV_FILE`, which take
+/// precedence over the environment and, unlike it, can be changed without a
+/// restart.
+struct Env {
+    file_vars: BTreeMap<String, String>,
+    /// What was read since the last call to [`Env::take_inputs`].
+    inputs: std::sync::Mutex<Inputs>,
+}
+
+/// What a part of the configuration was built from: the variables it read,
+/// set or not, and the modification times of the files it read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Inputs {
+    vars: BTreeMap<String, Option<String>>,
+    files: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Env {
+    /// Read `ENV_FILE`, made of `KEY=value` lines, optionally prefixed with
+    /// `export` and quoted, along with blank lines and `#` comments.
+    fn load() -> Result<Self, anyhow::Error> {
+        let mut file_vars = BTreeMap::new();
+        if let Some(path) = std::env::var_os("ENV_FILE") {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read {}", path.to_string_lossy()))?;
+            for (index, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let line = line.strip_prefix("export ").unwrap_or(line);
+                let (key, value) = line
+                    .split_once('=')
+                    .with_context(|| format!("line {} of ENV_FILE is not KEY=value", index + 1))?;
+                let value = value.trim();
+                let value = ['"', '\'']
+                    .iter()
+                    .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+                    .unwrap_or(value);
+                file_vars.insert(key.trim().to_owned(), value.to_owned());
+            }
+        }
+        Ok(Self {
+            file_vars,
+            inputs: Default::default(),
+        })
+    }
+
+    /// The variables the providers in use were built with.
+    fn current() -> Self {
+        let file_vars = ENV_FILE_VARS.read().unwrap_or_else(|err| err.into_inner());
+        Self {
+            file_vars: file_vars.clone(),
+            inputs: Default::default(),
+        }
+    }
+
+    /// Make these the variables the providers in use were built with.
+    fn publish(self) {
+        *ENV_FILE_VARS.write().unwrap_or_else(|err| err.into_inner()) = self.file_vars;
+    }
+
+    fn var(&self, key: &str) -> Result<String, anyhow::Error> {
+        self.var_opt(key)
+            .with_context(|| format!("env var {} is not set", key))
+    }
+
+    fn var_opt(&self, key: &str) -> Option<String> {
+        let value = self.lookup(key);
+        let mut inputs = self.inputs.lock().unwrap();
+        inputs.vars.insert(key.to_owned(), value.clone());
+        value
+    }
+
+    /// A variable naming a file, which is read too.
+    fn path_opt(&self, key: &str) -> Option<String> {
+        let path = self.var_opt(key)?;
+        self.read_file(Path::new(&path));
+        Some(path)
+    }
+
+    /// Note that a file is read, to be built again when it changes.
+    fn read_file(&self, path: &Path) {
+        let mut inputs = self.inputs.lock().unwrap();
+        inputs.files.insert(path.to_owned(), modified(path));
+    }
+
+    fn lookup(&self, key: &str) -> Option<String> {
+        self.file_vars
+            .get(key)
+            .cloned()
+            .or_else(|| std::env::var(key).ok())
+    }
+
+    /// What was read since the last call.
+    fn take_inputs(&self) -> Inputs {
+        std::mem::take(&mut *self.inputs.lock().unwrap())
+    }
+
+    /// Whether something built from `inputs` would be built the same now.
+    fn is_unchanged(&self, inputs: &Inputs) -> bool {
+        inputs
+            .vars
+            .iter()
+            .all(|(key, value)| self.lookup(key) == *value)
+            && inputs
+                .files
+                .iter()
+                .all(|(path, modified_at)| modified(path) == *modified_at)
+    }
+
+    fn parse_opt<T>(&self, key: &str) -> Result<Option<T>, anyhow::Error>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.var_opt(key)
+            .map(|value| value.parse().with_context(|| format!("invalid {}", key)))
+            .transpose()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The configuration files are checked for changes every `RELOAD_POLL_SECS`,
+/// 10 seconds by default, 0 disabling it; `SIGHUP` and the `reloadProviders`
+/// mutation reload the providers regardless.
+fn reload_poll_interval(env: &Env) -> Result<Option<Duration>, anyhow::Error> {
+    Ok(match env.parse_opt("RELOAD_POLL_SECS")?.unwrap_or(10) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    })
+}
+
+/// `ADMIN_TOKEN` is the bearer token granting the `reloadProviders`
+/// mutation, which is disabled when it is not set; unlike the providers, it
+/// is only read at startup.
+fn admin_token(env: &Env) -> Option<String> {
+    env.var_opt("ADMIN_TOKEN").filter(|token| !token.is_empty())
+}
+
+/// `TRUSTED_PROXIES` is a comma-separated list of the addresses of the
+/// authenticating proxies whose `X-Forwarded-User` names the user the
+/// limiters queue calls for; other clients are told apart by their address.
+/// Like `ADMIN_TOKEN`, it is only read at startup.
+fn trusted_proxies(env: &Env) -> Result<TrustedProxies, anyhow::Error> {
+    let proxies = env.var_opt("TRUSTED_PROXIES").unwrap_or_default();
+    let proxies = proxies
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .map(|proxy| {
+            proxy
+                .parse()
+                .with_context(|| format!("invalid TRUSTED_PROXIES address {:?}", proxy))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(TrustedProxies(proxies))
+}
+
+/// The layers wrapped around every provider.
+struct Layers {
+    cache: Option<CacheConfig>,
+    health: Option<HealthConfig>,
+}
+
+fn layers(env: &Env) -> Result<Layers, anyhow::Error> {
+    Ok(Layers {
+        cache: cache_config(env)?,
+        health: health_config(env)?,
+    })
+}
+
+/// The inventory cache is configured with `CACHE_TTL_SECS`, 30 seconds by
+/// default, 0 disabling it. Expired listings are served for as long again
+/// while being refreshed.
+fn cache_config(env: &Env) -> Result<Option<CacheConfig>, anyhow::Error> {
+    let ttl = env.parse_opt("CACHE_TTL_SECS")?.unwrap_or(30);
+    if ttl == 0 {
+        return Ok(None);
+    }
+    let ttl = Duration::from_secs(ttl);
+    Ok(Some(CacheConfig {
+        ttl,
+        max_stale: ttl * 2,
+    }))
+}
+
+/// The circuit breaker opens after `CIRCUIT_FAILURE_THRESHOLD` consecutive
+/// failures, 5 by default, 0 disabling it along with the health probes, and
+/// stays open for `CIRCUIT_OPEN_SECS`, 30 seconds by default. The backends are
+/// probed every `HEALTH_PROBE_SECS`, 60 seconds by default, 0 disabling it,
+/// and the probes time out after `HEALTH_PROBE_TIMEOUT_SECS`, 10 seconds by
+/// default.
+fn health_config(env: &Env) -> Result<Option<HealthConfig>, anyhow::Error> {
+    let secs = |key: &str, default: u64| -> Result<u64, anyhow::Error> {
+        Ok(env.parse_opt(key)?.unwrap_or(default))
+    };
+    let failure_threshold = env.parse_opt("CIRCUIT_FAILURE_THRESHOLD")?.unwrap_or(5);
+    if failure_threshold == 0 {
+        return Ok(None);
+    }
+    let probe_interval = match secs("HEALTH_PROBE_SECS", 60)? {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    Ok(Some(HealthConfig {
+        probe_interval,
+        probe_timeout: Duration::from_secs(secs("HEALTH_PROBE_TIMEOUT_SECS", 10)?),
+        failure_threshold,
+        open_duration: Duration::from_secs(secs("CIRCUIT_OPEN_SECS", 30)?),
+    }))
+}
+
+/// The calls to a provider are limited as configured by `LIMIT_MAX_CONCURRENT`,
+/// 16 by default, along with `LIMIT_READS_PER_SEC` and `LIMIT_WRITES_PER_SEC`,
+/// unlimited by default. Each can be overridden for a single provider by
+/// adding its key, e.g. `LIMIT_AZURE_WRITES_PER_SEC`.
+fn limit_config(env: &Env, key: &str) -> Result<LimitConfig, anyhow::Error> {
+    let key = key.to_uppercase().replace('-', "_");
+    Ok(LimitConfig {
+        max_concurrent: limit_var(env, &key, "MAX_CONCURRENT")?.unwrap_or(16),
+        reads_per_second: limit_var(env, &key, "READS_PER_SEC")?,
+        writes_per_second: limit_var(env, &key, "WRITES_PER_SEC")?,
+    })
+}
+
+/// A limit, set for the provider itself or else for all of them.
+fn limit_var<T>(env: &Env, key: &str, name: &str) -> Result<Option<T>, anyhow::Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let own = format!("LIMIT_{}_{}", key, name);
+    if env.var_opt(&own).is_some() {
+        return env.parse_opt(&own);
+    }
+    env.parse_opt(&format!("LIMIT_{}", name))
+}
+
+/// Wrap a provider in the limiter, the circuit breaker, then the cache, so
+/// that cached listings are still served while the circuit is open, and
+/// neither cache hits nor calls failing fast wait in the queue.
+fn layered<P>(
+    env: &Env,
+    key: &str,
+    provider: P,
+    layers: &Layers,
+) -> Result<(String, Arc<dyn vm_onoff::core::Provider>), anyhow::Error>
+where
+    P: vm_onoff::core::Provider + 'static,
+{
+    let provider = Limited::new(provider, limit_config(env, key)?);
+    let provider = match layers.health {
+        Some(config) => with_cache(
+            Monitored::new(key.to_owned(), provider, config),
+            layers.cache,
+        ),
+        None => with_cache(provider, layers.cache),
+    };
+    Ok((key.to_owned(), provider))
+}
+
+fn with_cache<P>(provider: P, config: Option<CacheConfig>) -> Arc<dyn vm_onoff::core::Provider>
+where
+    P: vm_onoff::core::Provider + 'static,
+{
+    match config {
+        Some(config) => Arc::new(Cached::new(provider, config)),
+        None => Arc::new(provider),
+    }
+}
 
-    let azure_client_id = getenv("AZURE_CLIENT_ID");
-    let azure_client_secret = getenv("AZURE_CLIENT_SECRET");
-    let azure_tenant_id = getenv("AZURE_TENANT_ID");
-    let azure_subscription_id = getenv("AZURE_SUBSCRIPTION_ID");
+/// The Azure provider is always enabled, and configured with
+/// `AZURE_CLIENT_ID`, `AZURE_CLIENT_SECRET`, `AZURE_TENANT_ID` and
+/// `AZURE_SUBSCRIPTION_ID`.
+async fn azure_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<
+    azure::Provider<
+        azure::auth::token_manager::TokenManager<
+            azure::auth::client_credentials::ClientCredentials,
+        >,
+    >,
+    anyhow::Error,
+> {
+    let azure_client_id = env.var("AZURE_CLIENT_ID")?;
+    let azure_client_secret = env.var("AZURE_CLIENT_SECRET")?;
+    let azure_tenant_id = env.var("AZURE_TENANT_ID")?;
+    let azure_subscription_id = env.var("AZURE_SUBSCRIPTION_ID")?;
+    // Comma-separated subscriptions whose virtual machines are managed too.
+    let azure_extra_subscription_ids = env
+        .var_opt("AZURE_EXTRA_SUBSCRIPTION_IDS")
+        .map(|ids| {
+            ids.split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(ToOwned::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    let azure_cloud = azure_cloud(env)?;
 
     let azure_auth_provider = azure::auth::client_credentials::ClientCredentials {
-        client: reqwest_client.clone(),
+        client: client.clone(),
+        authority_host: azure_cloud.authority_host.clone(),
         client_id: azure_client_id,
         client_secret: azure_client_secret,
         tenant_id: azure_tenant_id,
-        scopes: vec!["https://management.azure.com/.default".into()],
+        scopes: vec![azure_cloud.resource_manager_scope()],
     };
 
     let azure_auth_provider = azure::auth::token_manager::TokenManager::new(azure_auth_provider);
 
-    let azure_provider = azure::Provider {
-        client: reqwest_client,
+    let azure_api_versions = match env.var_opt("AZURE_API_VERSIONS") {
+        Some(spec) => azure::ApiVersions::parse(&spec).context("invalid AZURE_API_VERSIONS")?,
+        None => azure::ApiVersions::default(),
+    };
+
+    let azure_list_backend = env
+        .parse_opt("AZURE_LIST_BACKEND")?
+        .unwrap_or(azure::ListBackend::Arm);
+
+    // Comma-separated kinds of resources to list, all of them by default.
+    let azure_resource_kinds = match env.var_opt("AZURE_RESOURCE_KINDS") {
+        Some(kinds) => kinds
+            .split(',')
+            .map(str::trim)
+            .filter(|kind| !kind.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .context("invalid AZURE_RESOURCE_KINDS")?,
+        None => azure::ResourceKind::ALL.to_vec(),
+    };
+
+    let azure_scale_set_stop_mode = env
+        .parse_opt("AZURE_SCALE_SET_STOP_MODE")?
+        .unwrap_or(azure::ScaleSetStopMode::Deallocate);
+
+    let mut azure_provider = azure::Provider {
+        client,
+        cloud: azure_cloud,
+        api_versions: azure_api_versions,
+        list_backend: azure_list_backend,
+        resource_kinds: azure_resource_kinds,
+        scale_set_stop_mode: azure_scale_set_stop_mode,
         subscription_id: azure_subscription_id,
+        extra_subscription_ids: azure_extra_subscription_ids,
         auth_token_provider: azure_auth_provider,
     };
+    if let Err(err) = azure_provider.negotiate_api_versions().await {
+        warn!("Unable to negotiate Azure API versions: {}", err);
+    }
+    Ok(azure_provider)
+}
 
-    let providers = vec![("azure".to_owned(), Box::new(azure_provider) as _)]
-        .into_iter()
-        .collect();
-    let core = Arc::new(vm_onoff::core::Core { providers });
-    let instance_loader = graphql::loader::InstanceLoader {
-        core: Arc::clone(&core),
+/// The AWS provider is enabled by setting `AWS_REGION` (or
+/// `AWS_DEFAULT_REGION`); `AWS_ENDPOINT_URL` points it to a stand-in such as
+/// LocalStack, and `AWS_EC2_HIBERNATE=true` makes it hibernate instances.
+/// The credentials are looked for like the AWS CLI does, with the variables
+/// of `ENV_FILE` too.
+fn aws_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<Option<aws::Provider<aws::credentials::DefaultCredentials>>, anyhow::Error> {
+    let region = match env
+        .var_opt("AWS_REGION")
+        .or_else(|| env.var_opt("AWS_DEFAULT_REGION"))
+    {
+        Some(region) => region,
+        None => return Ok(None),
     };
-    let schema = graphql::schema().data(core).data(instance_loader).finish();
+    let endpoint = env
+        .var_opt("AWS_ENDPOINT_URL")
+        .unwrap_or_else(|| aws::default_endpoint(&region));
+    let hibernate = env.parse_opt("AWS_EC2_HIBERNATE")?.unwrap_or(false);
+    let var = |key: &str| env.var_opt(key);
+    if let Some(path) = aws::credentials::profile::default_path(var) {
+        env.read_file(&path);
+    }
+    let credentials_provider = aws::credentials::DefaultCredentials::resolve(client.clone(), var)
+        .context("unable to load the AWS credentials")?;
+    Ok(Some(aws::Provider {
+        client,
+        region,
+        endpoint,
+        hibernate,
+        credentials_provider,
+    }))
+}
 
-    let app = Router::new();
-    let app = GraphQL::routes(app, schema);
+/// The GCP provider is enabled by setting `GCP_PROJECT`. It authenticates
+/// with the service account key named by `GOOGLE_APPLICATION_CREDENTIALS`, or
+/// through the metadata server (`GCE_METADATA_HOST`) when there is none.
+/// `GCP_COMPUTE_ENDPOINT` points it to a stand-in, and
+/// `GCP_STOP_MODE=suspend` makes it suspend instances.
+fn gcp_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<Option<gcp::Provider<TokenManager<gcp::auth::DefaultTokenProvider>>>, anyhow::Error> {
+    let project = match env.var_opt("GCP_PROJECT") {
+        Some(project) => project,
+        None => return Ok(None),
+    };
+    let auth_token_provider = match env.path_opt("GOOGLE_APPLICATION_CREDENTIALS") {
+        Some(path) => gcp::auth::DefaultTokenProvider::ServiceAccount(
+            gcp::auth::service_account::ServiceAccount {
+                client: client.clone(),
+                key: gcp::auth::service_account::ServiceAccountKey::from_file(&path)
+                    .with_context(|| format!("unable to read {}", path))?,
+                scopes: vec![gcp::auth::COMPUTE_SCOPE.to_owned()],
+            },
+        ),
+        None => gcp::auth::DefaultTokenProvider::MetadataServer(
+            gcp::auth::metadata_server::MetadataServer {
+                client: client.clone(),
+                endpoint: match env.var_opt("GCE_METADATA_HOST") {
+                    Some(host) => format!("http://{}", host),
+                    None => gcp::auth::metadata_server::DEFAULT_ENDPOINT.to_owned(),
+                },
+            },
+        ),
+    };
+    let stop_mode = env
+        .parse_opt("GCP_STOP_MODE")?
+        .unwrap_or(gcp::StopMode::Stop);
+    Ok(Some(gcp::Provider {
+        client,
+        endpoint: env
+            .var_opt("GCP_COMPUTE_ENDPOINT")
+            .unwrap_or_else(|| gcp::DEFAULT_ENDPOINT.to_owned()),
+        project,
+        stop_mode,
+        auth_token_provider: TokenManager::new(auth_token_provider),
+    }))
+}
 
-    info!("Playground: http://localhost:8000");
+/// The Hetzner Cloud provider is enabled by setting `HCLOUD_TOKEN`, and can be
+/// pointed elsewhere with `HCLOUD_ENDPOINT`. `HCLOUD_STOP_MODE` is `shutdown`
+/// (the default) or `power-off`.
+fn hetzner_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<Option<hetzner::Provider>, anyhow::Error> {
+    let token = match env.var_opt("HCLOUD_TOKEN") {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let stop_mode = env
+        .parse_opt("HCLOUD_STOP_MODE")?
+        .unwrap_or(hetzner::StopMode::Shutdown);
+    Ok(Some(hetzner::Provider {
+        client,
+        endpoint: env
+            .var_opt("HCLOUD_ENDPOINT")
+            .unwrap_or_else(|| hetzner::DEFAULT_ENDPOINT.to_owned()),
+        token,
+        stop_mode,
+        action_timeout: Duration::from_secs(120),
+    }))
+}
 
-    Server::bind(&"0.0.0.0:8000".parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+/// The DigitalOcean provider is enabled by setting
+/// `DIGITALOCEAN_ACCESS_TOKEN`, and can be pointed elsewhere with
+/// `DIGITALOCEAN_ENDPOINT`. `DIGITALOCEAN_STOP_MODE` is `shutdown` (the
+/// default) or `power-off`.
+fn digitalocean_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<Option<digitalocean::Provider>, anyhow::Error> {
+    let token = match env.var_opt("DIGITALOCEAN_ACCESS_TOKEN") {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    let stop_mode = env
+        .parse_opt("DIGITALOCEAN_STOP_MODE")?
+        .unwrap_or(digitalocean::StopMode::Shutdown);
+    Ok(Some(digitalocean::Provider {
+        client,
+        endpoint: env
+            .var_opt("DIGITALOCEAN_ENDPOINT")
+            .unwrap_or_else(|| digitalocean::DEFAULT_ENDPOINT.to_owned()),
+        token,
+        stop_mode,
+        action_timeout: Duration::from_secs(120),
+    }))
 }
 
-fn getenv(key: &str) -> String {
-    std::env::var(key).unwrap_or_else(|_| panic!("env var {} is not set", key))
+/// The OpenStack provider is enabled by setting `OS_AUTH_URL`, and reads the
+/// other variables of an `openrc` file: `OS_APPLICATION_CREDENTIAL_ID` and
+/// `OS_APPLICATION_CREDENTIAL_SECRET`, or `OS_USERNAME`, `OS_PASSWORD`,
+/// `OS_PROJECT_NAME`, `OS_USER_DOMAIN_NAME` and `OS_PROJECT_DOMAIN_NAME`;
+/// along with `OS_REGION_NAME` and `OS_INTERFACE`. `OPENSTACK_STOP_MODE` is
+/// `stop` (the default) or `shelve`.
+fn openstack_provider(
+    env: &Env,
+    client: reqwest::Client,
+) -> Result<Option<openstack::Provider>, anyhow::Error> {
+    let auth_url = match env.var_opt("OS_AUTH_URL") {
+        Some(auth_url) => auth_url,
+        None => return Ok(None),
+    };
+    let auth_url = auth_url.trim_end_matches('/');
+    let auth_url = if auth_url.ends_with("/v3") {
+        auth_url.to_owned()
+    } else {
+        format!("{}/v3", auth_url)
+    };
+    let credentials = match env.var_opt("OS_APPLICATION_CREDENTIAL_ID") {
+        Some(id) => openstack::auth::Credentials::ApplicationCredential {
+            id,
+            secret: env.var("OS_APPLICATION_CREDENTIAL_SECRET")?,
+        },
+        None => openstack::auth::Credentials::Password {
+            user_name: env.var("OS_USERNAME")?,
+            user_domain_name: env
+                .var_opt("OS_USER_DOMAIN_NAME")
+                .unwrap_or_else(|| "Default".to_owned()),
+            password: env.var("OS_PASSWORD")?,
+            project_name: env.var("OS_PROJECT_NAME")?,
+            project_domain_name: env
+                .var_opt("OS_PROJECT_DOMAIN_NAME")
+                .unwrap_or_else(|| "Default".to_owned()),
+        },
+    };
+    let keystone = openstack::auth::Keystone::new(
+        client.clone(),
+        auth_url,
+        credentials,
+        env.var_opt("OS_REGION_NAME"),
+        env.var_opt("OS_INTERFACE")
+            .unwrap_or_else(|| "public".to_owned()),
+    );
+    let stop_mode = env
+        .parse_opt("OPENSTACK_STOP_MODE")?
+        .unwrap_or(openstack::StopMode::Stop);
+    Ok(Some(openstack::Provider {
+        client,
+        keystone,
+        stop_mode,
+    }))
+}
+
+/// The libvirt provider is enabled by setting `LIBVIRT_URI`, e.g.
+/// `qemu:///system` or `qemu+ssh://host/system`; `LIBVIRT_STOP_MODE` is one of
+/// `shutdown` (the default), `destroy` and `managed-save`.
+fn libvirt_provider(env: &Env) -> Result<Option<libvirt::Provider>, anyhow::Error> {
+    let uri = match env.var_opt("LIBVIRT_URI") {
+        Some(uri) => uri,
+        None => return Ok(None),
+    };
+    let stop_mode = env
+        .parse_opt("LIBVIRT_STOP_MODE")?
+        .unwrap_or(libvirt::StopMode::Shutdown);
+    Ok(Some(libvirt::Provider {
+        uri,
+        virsh: "virsh".into(),
+        stop_mode,
+        timeout: Duration::from_secs(60),
+    }))
+}
+
+/// The Proxmox VE provider is enabled by setting `PROXMOX_ENDPOINT`, e.g.
+/// `https://pve1:8006`, along with `PROXMOX_TOKEN_ID` and
+/// `PROXMOX_TOKEN_SECRET`. `PROXMOX_INSECURE=true` accepts self-signed
+/// certificates, and `PROXMOX_STOP_MODE` is one of `shutdown` (the default),
+/// `stop` and `suspend`.
+fn proxmox_provider(env: &Env) -> Result<Option<proxmox::Provider>, anyhow::Error> {
+    let endpoint = match env.var_opt("PROXMOX_ENDPOINT") {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let insecure = env.parse_opt("PROXMOX_INSECURE")?.unwrap_or(false);
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(insecure)
+        .build()
+        .context("unable to build the Proxmox HTTP client")?;
+    let stop_mode = env
+        .parse_opt("PROXMOX_STOP_MODE")?
+        .unwrap_or(proxmox::StopMode::Shutdown);
+    Ok(Some(proxmox::Provider {
+        client,
+        endpoint,
+        token_id: env.var("PROXMOX_TOKEN_ID")?,
+        token_secret: env.var("PROXMOX_TOKEN_SECRET")?,
+        stop_mode,
+        task_timeout: Duration::from_secs(120),
+    }))
+}
+
+/// The Azure cloud is either picked by name with `AZURE_CLOUD` (defaulting to
+/// the public cloud) or fully specified with `AZURE_RESOURCE_MANAGER_ENDPOINT`
+/// and `AZURE_AUTHORITY_HOST`.
+fn azure_cloud(env: &Env) -> Result<azure::Cloud, anyhow::Error> {
+    let base = match env.var_opt("AZURE_CLOUD") {
+        Some(name) => azure::Cloud::from_name(&name)
+            .with_context(|| format!("unknown Azure cloud {}", name))?,
+        None => azure::Cloud::public(),
+    };
+    Ok(azure::Cloud::custom(
+        &env.var_opt("AZURE_RESOURCE_MANAGER_ENDPOINT")
+            .unwrap_or(base.resource_manager_endpoint),
+        &env.var_opt("AZURE_AUTHORITY_HOST")
+            .unwrap_or(base.authority_host),
+    ))
+}
+
+/// The Redfish provider is enabled by setting `REDFISH_ENDPOINT` to the
+/// BMC's base URL, e.g. `https://bmc1`, along with `REDFISH_USERNAME` and
+/// `REDFISH_PASSWORD`. `REDFISH_AUTH` is `basic` (the default) or `session`,
+/// and `REDFISH_STOP_MODE` is `graceful-shutdown` (the default) or `force-off`.
+/// BMCs often have self-signed certificates: `REDFISH_CA_CERT` is the path to
+/// a PEM certificate to trust, and `REDFISH_INSECURE` disables verification.
+fn redfish_provider(env: &Env) -> Result<Option<redfish::Provider>, anyhow::Error> {
+    let endpoint = match env.var_opt("REDFISH_ENDPOINT") {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let insecure = env.parse_opt("REDFISH_INSECURE")?.unwrap_or(false);
+    let mut client = reqwest::Client::builder().danger_accept_invalid_certs(insecure);
+    if let Some(path) = env.path_opt("REDFISH_CA_CERT") {
+        let pem = std::fs::read(&path).context("cannot read REDFISH_CA_CERT")?;
+        client = client.add_root_certificate(
+            reqwest::Certificate::from_pem(&pem)
+                .context("REDFISH_CA_CERT must be a PEM certificate")?,
+        );
+    }
+    let mut provider = redfish::Provider::new(
+        client
+            .build()
+            .context("unable to build the Redfish HTTP client")?,
+        endpoint,
+        env.var("REDFISH_USERNAME")?,
+        env.var("REDFISH_PASSWORD")?,
+    );
+    if let Some(mode) = env.parse_opt("REDFISH_AUTH")? {
+        provider = provider.with_auth_mode(mode);
+    }
+
+    if let Some(mode) = env.parse_opt("REDFISH_STOP_MODE")? {
+        provider = provider.with_stop_mode(mode);
+    }
+    Ok(Some(provider))
+}
+
+/// The Wake-on-LAN provider is enabled by setting `WOL_CONFIG` to the path of
+/// the TOML file declaring the machines (see `wol::config`).
+fn wol_provider(env: &Env) -> Result<Option<wol::Provider>, anyhow::Error> {
+    let path = match env.path_opt("WOL_CONFIG") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let config =
+        wol::Config::from_file(&path).with_context(|| format!("invalid WOL_CONFIG {}", path))?;
+    Ok(Some(wol::Provider::new(config)))
+}
+
+/// The Kubernetes provider is enabled by setting `KUBERNETES_CONFIG` to
+/// `in-cluster`, to use the pod's service account, or to the path of a
+/// kubeconfig file, whose context can be chosen with `KUBERNETES_CONTEXT`.
+/// It manages the workloads matching `KUBERNETES_LABEL_SELECTOR`
+/// (`vm-onoff/enabled=true` by default), in `KUBERNETES_NAMESPACE` only if set.
+fn kubernetes_provider(env: &Env) -> Result<Option<kubernetes::Provider>, anyhow::Error> {
+    let config = match env.var_opt("KUBERNETES_CONFIG") {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    let cluster = match config.as_str() {
+        "in-cluster" => kubernetes::config::Cluster::in_cluster(),
+        path => {
+            env.read_file(Path::new(path));
+            kubernetes::config::Cluster::from_kubeconfig(
+                path,
+                env.var_opt("KUBERNETES_CONTEXT").as_deref(),
+            )
+        }
+    };
+    Ok(Some(kubernetes::Provider {
+        cluster: cluster.context("invalid KUBERNETES_CONFIG")?,
+        namespace: env.var_opt("KUBERNETES_NAMESPACE"),
+        label_selector: env
+            .var_opt("KUBERNETES_LABEL_SELECTOR")
+            .unwrap_or_else(|| kubernetes::DEFAULT_LABEL_SELECTOR.to_owned()),
+    }))
+}
+
+/// The Docker provider is enabled by setting `DOCKER_HOST` to the Unix socket
+/// of a Docker or Podman daemon, e.g. `unix:///var/run/docker.sock`.
+/// `DOCKER_LABEL` restricts it to the containers with a label, given as `key`
+/// or `key=value`, and `DOCKER_STOP_TIMEOUT` overrides how many seconds they
+/// are given to stop.
+fn docker_provider(env: &Env) -> Result<Option<docker::Provider>, anyhow::Error> {
+    let host = match env.var_opt("DOCKER_HOST") {
+        Some(host) => host,
+        None => return Ok(None),
+    };
+    let socket = host
+        .strip_prefix("unix://")
+        .context("DOCKER_HOST must be a unix:// socket")?;
+    let provider = docker::Provider::new(socket, env.var_opt("DOCKER_LABEL"));
+    Ok(Some(match env.parse_opt("DOCKER_STOP_TIMEOUT")? {
+        Some(secs) => provider.with_stop_timeout(secs),
+        None => provider,
+    }))
+}
+
+/// Scripted providers are declared in the TOML file at `SCRIPTS_CONFIG` (see
+/// `script::config`).
+fn script_providers(env: &Env) -> Result<Vec<(String, script::Provider)>, anyhow::Error> {
+    let path = match env.path_opt("SCRIPTS_CONFIG") {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let config = script::config::Config::from_file(&path)
+        .with_context(|| format!("invalid SCRIPTS_CONFIG {}", path))?;
+    Ok(config
+        .providers
+        .into_iter()
+        .map(|script| (script.key.clone(), script::Provider { script }))
+        .collect())
+}
+
+/// Plugin providers are declared in the TOML file at `PLUGINS_CONFIG` (see
+/// `plugin::config`).
+async fn plugin_providers(env: &Env) -> Result<Vec<(String, plugin::Provider)>, anyhow::Error> {
+    let path = match env.path_opt("PLUGINS_CONFIG") {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let config = plugin::config::Config::from_file(&path)
+        .with_context(|| format!("invalid PLUGINS_CONFIG {}", path))?;
+    let mut providers = Vec::new();
+    for plugin in config.plugins {
+        env.read_file(&plugin.command);
+        let provider = plugin::Provider::spawn(
+            plugin.command,
+            plugin.args,
+            Duration::from_secs(plugin.timeout_secs),
+        )
+        .await
+        .with_context(|| format!("unable to start plugin {}", plugin.key))?;
+        providers.push((plugin.key, provider));
+    }
+    Ok(providers)
 }
@@ -0,0 +1,202 @@
+//! Keystone v3 authentication, with a password or an application
+//! credential; the token comes with the service catalog, where we find the
+//! compute endpoint.
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use super::{model, utils::check_status, ServerError};
+
+/// How long before its expiration a token is renewed.
+const EXPIRATION_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+const SUBJECT_TOKEN_HEADER: &str = "X-Subject-Token";
+
+pub enum Credentials {
+    Password {
+        user_name: String,
+        user_domain_name: String,
+        password: String,
+        project_name: String,
+        project_domain_name: String,
+    },
+    /// Application credentials are scoped to their project already.
+    ApplicationCredential { id: String, secret: String },
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Password {
+                user_name,
+                project_name,
+                ..
+            } => f
+                .debug_struct("Password")
+                .field("user_name", user_name)
+                .field("project_name", project_name)
+                .finish_non_exhaustive(),
+            Self::ApplicationCredential { id, .. } => f
+                .debug_struct("ApplicationCredential")
+                .field("id", id)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl Credentials {
+    fn to_request(&self) -> serde_json::Value {
+        match self {
+            Self::Password {
+                user_name,
+                user_domain_name,
+                password,
+                project_name,
+                project_domain_name,
+            } => serde_json::json!({
+                "auth": {
+                    "identity": {
+                        "methods": ["password"],
+                        "password": {
+                            "user": {
+                                "name": user_name,
+                                "domain": {"name": user_domain_name},
+                                "password": password,
+                            },
+                        },
+                    },
+                    "scope": {
+                        "project": {
+                            "name": project_name,
+                            "domain": {"name": project_domain_name},
+                        },
+                    },
+                },
+            }),
+            Self::ApplicationCredential { id, secret } => serde_json::json!({
+                "auth": {
+                    "identity": {
+                        "methods": ["application_credential"],
+                        "application_credential": {"id": id, "secret": secret},
+                    },
+                },
+            }),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("the token was issued without a {SUBJECT_TOKEN_HEADER} header")]
+    MissingToken,
+    #[error("invalid token expiration {0:?}")]
+    Expiration(String),
+    #[error("no {interface} compute endpoint in the catalog")]
+    NoComputeEndpoint { interface: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub token: String,
+    /// The base URL of the compute API, e.g.
+    /// `https://nova.example.com:8774/v2.1`.
+    pub compute_endpoint: String,
+    pub expires_at: Instant,
+}
+
+pub struct Keystone {
+    pub client: reqwest::Client,
+    /// The identity API, e.g. `https://keystone.example.com:5000/v3`.
+    pub auth_url: String,
+    pub credentials: Credentials,
+    /// The region whose compute endpoint to use, if there are several.
+    pub region: Option<String>,
+    /// The kind of endpoint to use, usually `public`.
+    pub interface: String,
+    cached: Mutex<Option<Token>>,
+}
+
+impl Keystone {
+    pub fn new(
+        client: reqwest::Client,
+        auth_url: String,
+        credentials: Credentials,
+        region: Option<String>,
+        interface: String,
+    ) -> Self {
+        Self {
+            client,
+            auth_url,
+            credentials,
+            region,
+            interface,
+            cached: Mutex::new(None),
+        }
+    }
+
+    fn find_compute_endpoint(&self, catalog: &[model::CatalogEntry]) -> Option<String> {
+        catalog
+            .iter()
+            .filter(|entry| entry.service_type == model::SERVICE_TYPE_COMPUTE)
+            .flat_map(|entry| &entry.endpoints)
+            .find(|endpoint| {
+                endpoint.interface == self.interface
+                    && (self.region.is_none() || endpoint.region_id == self.region)
+            })
+            .map(|endpoint| endpoint.url.trim_end_matches('/').to_owned())
+    }
+
+    async fn issue_token(&self) -> Result<Token, Error> {
+        let url = format!("{}/auth/tokens", self.auth_url);
+        let res = self
+            .client
+            .post(url)
+            .json(&self.credentials.to_request())
+            .send()
+            .await?;
+        let res = check_status(res).await?;
+        let token = res
+            .headers()
+            .get(SUBJECT_TOKEN_HEADER)
+            .and_then(|token| token.to_str().ok())
+            .ok_or(Error::MissingToken)?
+            .to_owned();
+        let body: model::TokenResponse = res.json().await?;
+
+        let expiration = chrono::DateTime::parse_from_rfc3339(&body.token.expires_at)
+            .map_err(|_| Error::Expiration(body.token.expires_at.clone()))?;
+        let valid_for = (expiration.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default();
+        let compute_endpoint =
+            self.find_compute_endpoint(&body.token.catalog)
+                .ok_or_else(|| Error::NoComputeEndpoint {
+                    interface: self.interface.clone(),
+                })?;
+
+        Ok(Token {
+            token,
+            compute_endpoint,
+            expires_at: Instant::now() + valid_for,
+        })
+    }
+
+    /// A token valid for a few more minutes at least.
+    pub async fn get_token(&self) -> Result<Token, Error> {
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = &*cached {
+            if token.expires_at > Instant::now() + EXPIRATION_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.issue_token().await?;
+        *cached = Some(token.clone());
+        Ok(token)
+    }
+}
@@ -0,0 +1,437 @@
+//! OpenStack provider implementation, for Nova servers.
+//!
+//! Our IDs are the server UUIDs.
+
+use reqwest::Method;
+
+use self::utils::{check_status, ServerError};
+
+pub mod auth;
+mod model;
+mod utils;
+
+const RESOURCE_TYPE: &str = "OS::Nova::Server";
+
+/// How many servers to list per request.
+const PAGE_SIZE: usize = 1000;
+
+pub struct Provider {
+    pub client: reqwest::Client,
+    pub keystone: auth::Keystone,
+    pub stop_mode: StopMode,
+}
+
+/// How to stop a server; shelved servers are unshelved on start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Power the server off (`os-stop`), keeping it on its host.
+    Stop,
+    /// Shelve the server, which frees its host's resources once offloaded.
+    Shelve,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(Self::Stop),
+            "shelve" => Ok(Self::Shelve),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("auth: {0}")]
+    Auth(#[from] auth::Error),
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Auth(auth::Error::Reqwest(_)) | Self::Reqwest(_) => {
+                crate::core::ErrorKind::Unavailable
+            }
+            Self::Auth(_) => crate::core::ErrorKind::Unauthorized,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Auth(auth::Error::Server(err)) | Self::Server(err) => Some(err.into()),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Provider {
+    /// Send a request to a compute API URL, relative to the endpoint unless
+    /// absolute.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<reqwest::Response, Error> {
+        let token = self.keystone.get_token().await?;
+        let url = if url.starts_with("http://") || url.starts_with("https://") {
+            url.to_owned()
+        } else {
+            format!("{}{}", token.compute_endpoint, url)
+        };
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("X-Auth-Token", &token.token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let res = request.send().await?;
+        Ok(check_status(res).await?)
+    }
+
+    async fn list_servers(&self) -> Result<Vec<model::Server>, Error> {
+        let mut servers = Vec::new();
+        let mut url = format!("/servers/detail?limit={}", PAGE_SIZE);
+        loop {
+            let res = self.send(Method::GET, &url, None).await?;
+            let page: model::ServerList = res.json().await?;
+            servers.extend(page.servers);
+            match page
+                .servers_links
+                .into_iter()
+                .find(|link| link.rel == model::LINK_REL_NEXT)
+            {
+                Some(link) => url = link.href,
+                None => return Ok(servers),
+            }
+        }
+    }
+
+    async fn get_server(&self, id: &crate::core::IdRef) -> Result<Option<model::Server>, Error> {
+        // Server IDs are UUIDs, which need no escaping.
+        if !id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            return Ok(None);
+        }
+        match self
+            .send(Method::GET, &format!("/servers/{}", id), None)
+            .await
+        {
+            Ok(res) => {
+                let res: model::ServerResponse = res.json().await?;
+                Ok(Some(res.server))
+            }
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn server_action(&self, id: &crate::core::IdRef, action: &str) -> Result<(), Error> {
+        let url = format!("/servers/{}/action", id);
+        let body = serde_json::json!({ action: null });
+        self.send(Method::POST, &url, Some(body)).await?;
+        Ok(())
+    }
+
+    fn detect_state(server: &model::Server) -> crate::core::State {
+        // Starting, stopping, shelving and the like all have a task.
+        if server.task_state.is_some() {
+            return crate::core::State::InProgress;
+        }
+        match (server.status.as_str(), server.power_state) {
+            (model::STATUS_ACTIVE, Some(model::POWER_STATE_RUNNING)) => crate::core::State::On,
+            (model::STATUS_SHUTOFF, _)
+            | (model::STATUS_SHELVED | model::STATUS_SHELVED_OFFLOADED, _) => {
+                crate::core::State::Off
+            }
+            (model::STATUS_ACTIVE, Some(model::POWER_STATE_SHUTDOWN))
+            | (model::STATUS_BUILD | model::STATUS_REBOOT | model::STATUS_HARD_REBOOT, _) => {
+                crate::core::State::InProgress
+            }
+            // Paused, suspended, in error, resizing, migrating…
+            _ => crate::core::State::Other,
+        }
+    }
+
+    fn server_to_instance(server: model::Server) -> crate::core::Instance {
+        crate::core::Instance {
+            state: Self::detect_state(&server),
+            display_name: server.name,
+            id: server.id,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    async fn start_server(&self, id: &crate::core::IdRef) -> Result<(), Error> {
+        let server = self.get_server(id).await?;
+        let action = match server.as_ref().map(|server| server.status.as_str()) {
+            Some(model::STATUS_SHELVED | model::STATUS_SHELVED_OFFLOADED) => "unshelve",
+            _ => "os-start",
+        };
+        self.server_action(id, action).await
+    }
+
+    async fn stop_server(&self, id: &crate::core::IdRef) -> Result<(), Error> {
+        let action = match self.stop_mode {
+            StopMode::Stop => "os-stop",
+            StopMode::Shelve => "shelve",
+        };
+        self.server_action(id, action).await
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: 8,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let servers = self.list_servers().await.map_err(Error::into_core)?;
+        Ok(servers.into_iter().map(Self::server_to_instance).collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        // Getting a token is most of the check; the listing only needs to
+        // reach Nova.
+        self.send(Method::GET, "/servers?limit=1", None)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let server = self.get_server(id).await.map_err(Error::into_core)?;
+        Ok(server.map(Self::server_to_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.start_server(id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.stop_server(id).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Extension, Path},
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+        AddExtensionLayer, Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    async fn issue_token(
+        Json(body): Json<serde_json::Value>,
+        request_headers: HeaderMap,
+    ) -> (HeaderMap, Json<serde_json::Value>) {
+        assert_eq!(
+            body["auth"]["identity"]["application_credential"]["id"],
+            "app"
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Subject-Token", "token".parse().unwrap());
+        let host = request_headers["host"].to_str().unwrap();
+        let endpoint = format!("http://{}/compute/v2.1", host);
+        (
+            headers,
+            Json(serde_json::json!({
+                "token": {
+                    "expires_at": "2999-01-01T00:00:00.000000Z",
+                    "catalog": [{
+                        "type": "compute",
+                        "endpoints": [
+                            {"interface": "internal", "region_id": "RegionOne", "url": "http://internal"},
+                            {"interface": "public", "region_id": "RegionOne", "url": endpoint},
+                        ],
+                    }],
+                },
+            })),
+        )
+    }
+
+    async fn servers(headers: HeaderMap) -> Result<Json<serde_json::Value>, StatusCode> {
+        if headers.get("X-Auth-Token").map(|token| token == "token") != Some(true) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(serde_json::json!({
+            "servers": [
+                {"id": "6c3b2a6e-9d42-4c4d-9e6b-1f0b0d4a8c11", "name": "web", "status": "ACTIVE", "OS-EXT-STS:power_state": 1, "OS-EXT-STS:task_state": null},
+                {"id": "0d2e4f6a-8b1c-4d3e-a5f7-9b0c1d2e3f40", "name": "db", "status": "SHELVED_OFFLOADED", "OS-EXT-STS:power_state": 4},
+                {"id": "1a2b3c4d-5e6f-4a8b-9c0d-1e2f3a4b5c6d", "name": "ci", "status": "ACTIVE", "OS-EXT-STS:power_state": 1, "OS-EXT-STS:task_state": "powering-off"},
+            ],
+        })))
+    }
+
+    const SHELVED_ID: &str = "0d2e4f6a-8b1c-4d3e-a5f7-9b0c1d2e3f40";
+    const BUSY_ID: &str = "1a2b3c4d-5e6f-4a8b-9c0d-1e2f3a4b5c6d";
+
+    type Actions = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    fn fault(
+        status: StatusCode,
+        name: &str,
+        message: &str,
+    ) -> (StatusCode, HeaderMap, Json<serde_json::Value>) {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-compute-request-id", "req-5d1c".parse().unwrap());
+        (
+            status,
+            headers,
+            Json(serde_json::json!({
+                name: {"message": message, "code": status.as_u16()},
+            })),
+        )
+    }
+
+    async fn server(Path(id): Path<String>) -> (StatusCode, HeaderMap, Json<serde_json::Value>) {
+        if id != SHELVED_ID {
+            return fault(
+                StatusCode::NOT_FOUND,
+                "itemNotFound",
+                &format!("Instance {} could not be found.", id),
+            );
+        }
+        (
+            StatusCode::OK,
+            HeaderMap::new(),
+            Json(serde_json::json!({
+                "server": {"id": SHELVED_ID, "name": "db", "status": "SHELVED_OFFLOADED", "OS-EXT-STS:power_state": 4},
+            })),
+        )
+    }
+
+    async fn server_action(
+        Path(id): Path<String>,
+        Json(body): Json<serde_json::Value>,
+        Extension(actions): Extension<Actions>,
+    ) -> (StatusCode, HeaderMap, Json<serde_json::Value>) {
+        actions.lock().unwrap().push((id.clone(), body));
+        if id == BUSY_ID {
+            return fault(
+                StatusCode::CONFLICT,
+                "conflictingRequest",
+                "Cannot 'shelve' instance while it is in task_state powering-off",
+            );
+        }
+        (
+            StatusCode::ACCEPTED,
+            HeaderMap::new(),
+            Json(serde_json::json!({})),
+        )
+    }
+
+    fn fake_provider(app: Router) -> Provider {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            client: reqwest::Client::new(),
+            keystone: auth::Keystone::new(
+                reqwest::Client::new(),
+                format!("http://{}/identity/v3", addr),
+                auth::Credentials::ApplicationCredential {
+                    id: "app".to_owned(),
+                    secret: "secret".to_owned(),
+                },
+                Some("RegionOne".to_owned()),
+                "public".to_owned(),
+            ),
+            stop_mode: StopMode::Shelve,
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_servers_from_fake_cloud() {
+        let app = Router::new()
+            .route("/identity/v3/auth/tokens", post(issue_token))
+            .route("/compute/v2.1/servers/detail", get(servers));
+        let provider = fake_provider(app);
+        let instances = provider.list().await.unwrap();
+        let states: Vec<_> = instances.iter().map(|instance| instance.state).collect();
+        assert_eq!(
+            states,
+            [
+                crate::core::State::On,
+                crate::core::State::Off,
+                crate::core::State::InProgress
+            ]
+        );
+        assert_eq!(instances[1].display_name, "db");
+    }
+
+    #[tokio::test]
+    async fn unshelves_on_start_and_reports_nova_faults() {
+        let actions = Actions::default();
+        let app = Router::new()
+            .route("/identity/v3/auth/tokens", post(issue_token))
+            .route("/compute/v2.1/servers/:id", get(server))
+            .route("/compute/v2.1/servers/:id/action", post(server_action))
+            .layer(AddExtensionLayer::new(actions.clone()));
+        let provider = fake_provider(app);
+
+        provider.start(SHELVED_ID).await.unwrap();
+        let err = provider
+            .stop(BUSY_ID)
+            .await
+            .unwrap_err()
+            .downcast::<crate::core::ProviderError>()
+            .unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        let details = err.details.unwrap();
+        assert_eq!(details.code.as_deref(), Some("conflictingRequest"));
+        assert_eq!(details.request_id.as_deref(), Some("req-5d1c"));
+        assert_eq!(
+            *actions.lock().unwrap(),
+            [
+                (SHELVED_ID.to_owned(), serde_json::json!({"unshelve": null})),
+                (BUSY_ID.to_owned(), serde_json::json!({"shelve": null})),
+            ]
+        );
+
+        let instance = provider.get(SHELVED_ID).await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::Off);
+        assert!(provider.get(BUSY_ID).await.unwrap().is_none());
+        assert!(provider.get("../flavors").await.unwrap().is_none());
+    }
+}
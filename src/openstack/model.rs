@@ -0,0 +1,92 @@
+//! Keystone and Nova models.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+// Like the other providers' models, these only declare the fields we use.
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct TokenResponse {
+    pub token: TokenBody,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct TokenBody {
+    /// When the token expires, in RFC 3339 format.
+    pub expires_at: String,
+    #[serde(default)]
+    pub catalog: Vec<CatalogEntry>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct CatalogEntry {
+    #[serde(rename = "type")]
+    pub service_type: String,
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Endpoint {
+    /// `public`, `internal` or `admin`.
+    pub interface: String,
+    #[serde(default)]
+    pub region_id: Option<String>,
+    pub url: String,
+}
+
+pub const SERVICE_TYPE_COMPUTE: &str = "compute";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerList {
+    pub servers: Vec<Server>,
+    #[serde(default)]
+    pub servers_links: Vec<Link>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Link {
+    pub href: String,
+    pub rel: String,
+}
+
+pub const LINK_REL_NEXT: &str = "next";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerResponse {
+    pub server: Server,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Server {
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub status: String,
+    #[serde(rename = "OS-EXT-STS:power_state", default)]
+    pub power_state: Option<u8>,
+    #[serde(rename = "OS-EXT-STS:task_state", default)]
+    pub task_state: Option<String>,
+}
+
+pub const STATUS_ACTIVE: &str = "ACTIVE";
+pub const STATUS_SHUTOFF: &str = "SHUTOFF";
+pub const STATUS_SHELVED: &str = "SHELVED";
+pub const STATUS_SHELVED_OFFLOADED: &str = "SHELVED_OFFLOADED";
+pub const STATUS_BUILD: &str = "BUILD";
+pub const STATUS_REBOOT: &str = "REBOOT";
+pub const STATUS_HARD_REBOOT: &str = "HARD_REBOOT";
+
+pub const POWER_STATE_RUNNING: u8 = 1;
+pub const POWER_STATE_SHUTDOWN: u8 = 4;
+
+/// The body of failed responses, a single fault keyed by its name, e.g.
+/// `itemNotFound`; Keystone always names it `error`.
+pub type ErrorResponse = HashMap<String, Fault>;
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Fault {
+    #[serde(default)]
+    pub message: String,
+}
@@ -0,0 +1,48 @@
+use super::model;
+
+/// The headers carrying the ID of the request, for Nova and Keystone.
+const REQUEST_ID_HEADERS: [&str; 2] = ["x-openstack-request-id", "x-compute-request-id"];
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    pub request_id: Option<String>,
+    /// The name of the fault, e.g. `itemNotFound`.
+    pub code: Option<String>,
+    pub message: Option<String>,
+}
+
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let request_id = REQUEST_ID_HEADERS
+        .iter()
+        .find_map(|header| res.headers().get(*header)?.to_str().ok())
+        .map(str::to_owned);
+    let fault = res
+        .json::<model::ErrorResponse>()
+        .await
+        .ok()
+        .and_then(|faults| faults.into_iter().next());
+    Err(ServerError {
+        status_code: status.as_u16(),
+        request_id,
+        code: fault.as_ref().map(|(code, _)| code.clone()),
+        message: fault.map(|(_, fault)| fault.message),
+    })
+}
+
+impl From<&ServerError> for crate::core::ProviderErrorDetails {
+    fn from(err: &ServerError) -> Self {
+        Self {
+            status_code: Some(err.status_code),
+            code: err.code.clone(),
+            message: err.message.clone(),
+            request_id: err.request_id.clone(),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! The plugins, declared in a TOML file:
+//!
+//! ```toml
+//! [[plugins]]
+//! key = "lab"
+//! command = "/usr/local/bin/vm-onoff-lab"
+//! args = ["--site", "paris"]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub plugins: Vec<Plugin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plugin {
+    /// The key of the provider, as used in the API.
+    pub key: String,
+    pub command: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// How long a request may take.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to read the configuration: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
@@ -0,0 +1,484 @@
+//! Providers implemented out of process, by executables speaking JSON-RPC
+//! 2.0 over their standard input and output.
+//!
+//! The plugin is started once and kept running, and started again if it
+//! exits, stops reading its input, or leaves several requests in a row
+//! unanswered. Each message is a single line of JSON; requests may be sent before
+//! earlier ones are answered, and responses may come in any order. Whatever
+//! the plugin writes to its standard error goes to ours.
+//!
+//! The methods mirror [`crate::core::Provider`]:
+//!
+//! - `initialize`, sent first with `{"protocolVersion": 1}`, returns
+//!   `{"protocolVersion": 1, "capabilities": {"efficientGet": bool,
+//!   "maxConcurrentGets": n}}`, the capabilities being optional;
+//! - `list` returns `{"instances": [instance…]}`;
+//! - `get`, with `{"id": id}`, returns `{"instance": instance}`, or
+//!   `{"instance": null}` if there is no such instance;
+//! - `start` and `stop`, with `{"id": id}`, return once the action is
+//!   accepted, with any result.
+//!
+//! Instances are `{"id", "displayName", "resourceType", "state"}` objects,
+//! the state being `on`, `off`, `inProgress` or `other`. Failures are
+//! reported as JSON-RPC errors, with a `{"kind"}` object as data to classify
+//! them: `notFound`, `unauthorized`, `forbidden`, `unavailable`,
+//! `conflict`, `rateLimited` or `invalidId`.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::{oneshot, Mutex},
+};
+use tracing::warn;
+
+pub mod config;
+pub mod protocol;
+
+/// How many requests in a row may time out before the plugin is deemed hung,
+/// and restarted.
+const MAX_CONSECUTIVE_TIMEOUTS: u32 = 3;
+
+type Pending = Arc<std::sync::Mutex<Option<HashMap<u64, oneshot::Sender<protocol::Response>>>>>;
+
+pub struct Provider {
+    pub command: PathBuf,
+    pub args: Vec<String>,
+    /// How long a request may take.
+    pub timeout: Duration,
+    capabilities: crate::core::Capabilities,
+    connection: Mutex<Option<Arc<Connection>>>,
+}
+
+/// A running plugin.
+struct Connection {
+    stdin: Mutex<ChildStdin>,
+    /// The senders waiting for responses, by request ID; `None` once the
+    /// plugin has exited.
+    pending: Pending,
+    next_id: AtomicU64,
+    /// How many requests in a row timed out.
+    timeouts: AtomicU32,
+    _child: Child,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to start the plugin: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("unable to write to the plugin: {0}")]
+    Write(#[source] std::io::Error),
+    #[error("invalid message: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("the plugin exited")]
+    Exited,
+    #[error("the plugin did not answer in time")]
+    Timeout,
+    #[error("the plugin speaks protocol version {0}")]
+    UnsupportedProtocol(u32),
+    #[error("plugin: {}", .0.message)]
+    Rpc(protocol::RpcError),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Spawn(_) | Self::Write(_) | Self::Exited | Self::Timeout => {
+                crate::core::ErrorKind::Unavailable
+            }
+            Self::Json(_) | Self::UnsupportedProtocol(_) => crate::core::ErrorKind::Other,
+            Self::Rpc(error) => {
+                let kind = error.data.as_ref().and_then(|data| data.kind.as_deref());
+                match kind {
+                    Some("notFound") => crate::core::ErrorKind::NotFound,
+                    Some("unauthorized") => crate::core::ErrorKind::Unauthorized,
+                    Some("forbidden") => crate::core::ErrorKind::Forbidden,
+                    Some("unavailable") => crate::core::ErrorKind::Unavailable,
+                    Some("conflict") => crate::core::ErrorKind::Conflict,
+                    Some("rateLimited") => crate::core::ErrorKind::RateLimited,
+                    Some("invalidId") => crate::core::ErrorKind::InvalidId,
+                    _ => crate::core::ErrorKind::Other,
+                }
+            }
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Rpc(error) => Some(crate::core::ProviderErrorDetails {
+                code: Some(error.code.to_string()),
+                message: Some(error.message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+/// Hand the responses read from the plugin to their requests, until it
+/// exits.
+async fn read_responses(stdout: ChildStdout, pending: Pending) {
+    let mut lines = BufReader::new(stdout).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) if line.trim().is_empty() => {}
+            Ok(Some(line)) => match serde_json::from_str::<protocol::Response>(&line) {
+                Ok(res) => {
+                    let sender = pending
+                        .lock()
+                        .unwrap()
+                        .as_mut()
+                        .and_then(|pending| pending.remove(&res.id));
+                    if let Some(sender) = sender {
+                        let _ = sender.send(res);
+                    }
+                }
+                Err(err) => warn!("Ignoring invalid message from plugin: {}", err),
+            },
+            Ok(None) => break,
+            Err(err) => {
+                warn!("Unable to read from plugin: {}", err);
+                break;
+            }
+        }
+    }
+    // Dropping the senders fails the requests still waiting.
+    pending.lock().unwrap().take();
+}
+
+impl Connection {
+    fn spawn(command: &PathBuf, args: &[String]) -> Result<Self, Error> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::Spawn)?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let pending = Pending::new(std::sync::Mutex::new(Some(HashMap::new())));
+        tokio::spawn(read_responses(stdout, Arc::clone(&pending)));
+
+        Ok(Self {
+            stdin: Mutex::new(stdin),
+            pending,
+            next_id: AtomicU64::new(1),
+            timeouts: AtomicU32::new(0),
+            _child: child,
+        })
+    }
+
+    fn is_closed(&self) -> bool {
+        self.pending.lock().unwrap().is_none()
+    }
+
+    /// Fail the requests still waiting, and have the plugin restarted.
+    fn close(&self) {
+        self.pending.lock().unwrap().take();
+    }
+
+    fn forget(&self, id: u64) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&id);
+        }
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<serde_json::Value, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or(Error::Exited)?
+            .insert(id, sender);
+
+        let request = protocol::Request {
+            jsonrpc: "2.0",
+            id,
+            method,
+            params,
+        };
+        let mut line = serde_json::to_vec(&request)?;
+        line.push(b'\n');
+        let written = {
+            let mut stdin = self.stdin.lock().await;
+            match stdin.write_all(&line).await {
+                Ok(()) => stdin.flush().await,
+                Err(err) => Err(err),
+            }
+        };
+        if let Err(err) = written {
+            self.forget(id);
+            // The plugin no longer reads its input, or got part of a line
+            // that would garble the next ones.
+            self.close();
+            return Err(Error::Write(err));
+        }
+
+        let res = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(_)) => return Err(Error::Exited),
+            Err(_) => {
+                self.forget(id);
+                let timeouts = self.timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+                if timeouts >= MAX_CONSECUTIVE_TIMEOUTS {
+                    warn!("The plugin did not answer {} requests in a row", timeouts);
+                    self.close();
+                }
+                return Err(Error::Timeout);
+            }
+        };
+        self.timeouts.store(0, Ordering::Relaxed);
+        match res.error {
+            Some(error) => Err(Error::Rpc(error)),
+            None => Ok(res.result),
+        }
+    }
+}
+
+impl Provider {
+    /// Start a plugin and check that it speaks our protocol.
+    pub async fn spawn(
+        command: PathBuf,
+        args: Vec<String>,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let connection = Connection::spawn(&command, &args)?;
+        let capabilities = Self::initialize(&connection, timeout).await?;
+        Ok(Self {
+            command,
+            args,
+            timeout,
+            capabilities: crate::core::Capabilities {
+                efficient_get: capabilities.efficient_get,
+                efficient_get_many: false,
+                max_concurrent_gets: capabilities.max_concurrent_gets.max(1),
+            },
+            connection: Mutex::new(Some(Arc::new(connection))),
+        })
+    }
+
+    async fn initialize(
+        connection: &Connection,
+        timeout: Duration,
+    ) -> Result<protocol::PluginCapabilities, Error> {
+        let params = serde_json::json!({ "protocolVersion": protocol::PROTOCOL_VERSION });
+        let result = connection
+            .call(protocol::METHOD_INITIALIZE, params, timeout)
+            .await?;
+        let result: protocol::InitializeResult = serde_json::from_value(result)?;
+        if result.protocol_version != protocol::PROTOCOL_VERSION {
+            return Err(Error::UnsupportedProtocol(result.protocol_version));
+        }
+        Ok(result.capabilities)
+    }
+
+    /// The running plugin, started again if it has exited.
+    async fn connection(&self) -> Result<Arc<Connection>, Error> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = &*connection {
+            if !connection.is_closed() {
+                return Ok(Arc::clone(connection));
+            }
+        }
+
+        warn!("Restarting plugin {}", self.command.display());
+        let new_connection = Connection::spawn(&self.command, &self.args)?;
+        Self::initialize(&new_connection, self.timeout).await?;
+        let new_connection = Arc::new(new_connection);
+        *connection = Some(Arc::clone(&new_connection));
+        Ok(new_connection)
+    }
+
+    async fn call<T>(&self, method: &str, params: serde_json::Value) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let connection = self.connection().await?;
+        let result = connection.call(method, params, self.timeout).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    fn id_params(id: &crate::core::IdRef) -> serde_json::Value {
+        serde_json::to_value(protocol::IdParams { id }).expect("the params are serializable")
+    }
+
+    fn to_core_instance(instance: protocol::Instance) -> crate::core::Instance {
+        crate::core::Instance {
+            id: instance.id,
+            display_name: instance.display_name,
+            resource_type: instance.resource_type,
+            state: match instance.state {
+                protocol::State::On => crate::core::State::On,
+                protocol::State::Off => crate::core::State::Off,
+                protocol::State::InProgress => crate::core::State::InProgress,
+                protocol::State::Other => crate::core::State::Other,
+            },
+            observed_at: std::time::Instant::now(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        self.capabilities
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let result: protocol::ListResult = self
+            .call(protocol::METHOD_LIST, serde_json::json!({}))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(result
+            .instances
+            .into_iter()
+            .map(Self::to_core_instance)
+            .collect())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let result: protocol::GetResult = self
+            .call(protocol::METHOD_GET, Self::id_params(id))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(result.instance.map(Self::to_core_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.call::<serde_json::Value>(protocol::METHOD_START, Self::id_params(id))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.call::<serde_json::Value>(protocol::METHOD_STOP, Self::id_params(id))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Provider as _;
+
+    /// A plugin in shell, answering with canned results.
+    const PLUGIN: &str = r#"
+        while read -r line; do
+            id=$(echo "$line" | sed 's/^{"jsonrpc":"2.0","id":\([0-9]*\).*/\1/')
+            case "$line" in
+                *'"initialize"'*) result='{"protocolVersion":1,"capabilities":{"efficientGet":true,"maxConcurrentGets":4}}' ;;
+                *'"list"'*) result='{"instances":[{"id":"a","displayName":"A","resourceType":"test","state":"inProgress"}]}' ;;
+                *'"get"'*) result='{"instance":null}' ;;
+                *) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"error\":{\"code\":1,\"message\":\"busy\",\"data\":{\"kind\":\"conflict\"}}}"; continue ;;
+            esac
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":$result}"
+        done
+    "#;
+
+    #[tokio::test]
+    async fn talks_to_shell_plugin() {
+        let provider = Provider::spawn(
+            "sh".into(),
+            vec!["-c".to_owned(), PLUGIN.to_owned()],
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+        assert_eq!(provider.capabilities().max_concurrent_gets, 4);
+
+        let instances = provider.list().await.unwrap();
+        assert_eq!(instances[0].display_name, "A");
+        assert_eq!(instances[0].state, crate::core::State::InProgress);
+        assert!(provider.get("b").await.unwrap().is_none());
+
+        let err = provider.start("a").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+    }
+
+    #[tokio::test]
+    async fn restarts_hung_and_broken_plugins() {
+        // Only ever answers `initialize`, the first request.
+        const HUNG_PLUGIN: &str = r#"
+            while read -r line; do
+                case "$line" in
+                    *'"initialize"'*) echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}' ;;
+                esac
+            done
+        "#;
+        let provider = Provider::spawn(
+            "sh".into(),
+            vec!["-c".to_owned(), HUNG_PLUGIN.to_owned()],
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+        let hung = provider.connection().await.unwrap();
+        for _ in 0..MAX_CONSECUTIVE_TIMEOUTS {
+            assert!(!hung.is_closed());
+            let err = provider.list().await.unwrap_err();
+            let err = err.downcast::<crate::core::ProviderError>().unwrap();
+            assert_eq!(err.kind, crate::core::ErrorKind::Unavailable);
+            assert!(hung.pending.lock().unwrap().iter().all(HashMap::is_empty));
+        }
+        assert!(hung.is_closed());
+        let restarted = provider.connection().await.unwrap();
+        assert!(!Arc::ptr_eq(&hung, &restarted));
+
+        // Stops reading once initialized.
+        const DEAF_PLUGIN: &str = r#"
+            read -r line
+            exec <&-
+            echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":1}}'
+            exec sleep 60
+        "#;
+        let provider = Provider::spawn(
+            "sh".into(),
+            vec!["-c".to_owned(), DEAF_PLUGIN.to_owned()],
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+        let deaf = provider.connection().await.unwrap();
+        assert!(matches!(
+            deaf.call(
+                protocol::METHOD_LIST,
+                serde_json::json!({}),
+                provider.timeout
+            )
+            .await,
+            Err(Error::Write(_))
+        ));
+        assert!(deaf.is_closed());
+    }
+}
@@ -0,0 +1,104 @@
+//! The messages of the plugin protocol, JSON-RPC 2.0 objects sent one per
+//! line.
+
+use serde::{Deserialize, Serialize};
+
+/// The version of the protocol described here; plugins report the one they
+/// speak on `initialize`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const METHOD_INITIALIZE: &str = "initialize";
+pub const METHOD_LIST: &str = "list";
+pub const METHOD_GET: &str = "get";
+pub const METHOD_START: &str = "start";
+pub const METHOD_STOP: &str = "stop";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Request<'a> {
+    pub jsonrpc: &'static str,
+    pub id: u64,
+    pub method: &'a str,
+    pub params: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Response {
+    pub id: u64,
+    #[serde(default)]
+    pub result: serde_json::Value,
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<RpcErrorData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcErrorData {
+    /// One of `notFound`, `unauthorized`, `forbidden`, `unavailable`,
+    /// `conflict`, `rateLimited` or `invalidId`; anything else is `other`.
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub capabilities: PluginCapabilities,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PluginCapabilities {
+    pub efficient_get: bool,
+    pub max_concurrent_gets: usize,
+}
+
+impl Default for PluginCapabilities {
+    fn default() -> Self {
+        Self {
+            efficient_get: false,
+            max_concurrent_gets: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdParams<'a> {
+    pub id: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ListResult {
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GetResult {
+    /// Null when the instance does not exist.
+    pub instance: Option<Instance>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    pub id: String,
+    pub display_name: String,
+    pub resource_type: String,
+    pub state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum State {
+    On,
+    Off,
+    InProgress,
+    #[serde(other)]
+    Other,
+}
@@ -0,0 +1,462 @@
+//! Proxmox VE provider implementation, for QEMU virtual machines and LXC
+//! containers across a cluster.
+//!
+//! Our IDs are the guest type followed by the VM ID (`qemu/100`, `lxc/101`),
+//! like Proxmox's own resource IDs; VM IDs are unique across the cluster,
+//! and guests keep theirs when migrating between nodes.
+
+use std::time::Duration;
+
+use reqwest::Method;
+
+use crate::core::action::{self, ActionStatus, WaitError};
+
+mod model;
+
+const RESOURCE_TYPE_QEMU: &str = "proxmox/qemu";
+const RESOURCE_TYPE_LXC: &str = "proxmox/lxc";
+
+/// How often to poll a task while waiting for it.
+const TASK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Provider {
+    pub client: reqwest::Client,
+    /// The API of any node of the cluster, e.g. `https://pve1:8006`.
+    pub endpoint: String,
+    /// The API token, as `user@realm!token-name`.
+    pub token_id: String,
+    pub token_secret: String,
+    pub stop_mode: StopMode,
+    /// How long to wait for a start or stop task to complete.
+    pub task_timeout: Duration,
+}
+
+/// How to stop a guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Ask the guest to shut down.
+    Shutdown,
+    /// Stop the guest immediately, like pulling the plug.
+    Stop,
+    /// Suspend virtual machines to disk, freeing their memory; containers
+    /// are shut down instead.
+    Suspend,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(Self::Shutdown),
+            "stop" => Ok(Self::Stop),
+            "suspend" => Ok(Self::Suspend),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The response body, which holds the reason of the failure.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("guest not found")]
+    NotFound,
+    #[error("task {upid} failed: {exit_status}")]
+    Task { upid: String, exit_status: String },
+    #[error("task {0} is still running")]
+    TaskTimeout(String),
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Reqwest(_) | Self::TaskTimeout(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::NotFound => crate::core::ErrorKind::NotFound,
+            Self::Task { .. } => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(crate::core::ProviderErrorDetails {
+                status_code: Some(err.status_code),
+                message: err.message.clone(),
+                ..Default::default()
+            }),
+            Self::Task { upid, exit_status } => Some(crate::core::ProviderErrorDetails {
+                message: Some(exit_status.clone()),
+                request_id: Some(upid.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Id {
+    guest_type: String,
+    vmid: u64,
+}
+
+impl TryFrom<&crate::core::IdRef> for Id {
+    type Error = crate::core::IdParsingError;
+
+    fn try_from(value: &crate::core::IdRef) -> Result<Self, Self::Error> {
+        match value.split_once('/') {
+            Some((guest_type @ (model::GUEST_TYPE_QEMU | model::GUEST_TYPE_LXC), vmid)) => {
+                Ok(Self {
+                    guest_type: guest_type.to_owned(),
+                    vmid: vmid.parse().map_err(|_| crate::core::IdParsingError)?,
+                })
+            }
+            _ => Err(crate::core::IdParsingError),
+        }
+    }
+}
+
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let message = res
+        .text()
+        .await
+        .ok()
+        .map(|body| body.trim().to_owned())
+        .filter(|body| !body.is_empty());
+    Err(ServerError {
+        status_code: status.as_u16(),
+        message,
+    })
+}
+
+impl Provider {
+    async fn request<T>(&self, method: Method, path: &str) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let url = format!("{}/api2/json{}", self.endpoint, path);
+        let res = self
+            .client
+            .request(method, url)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("PVEAPIToken={}={}", self.token_id, self.token_secret),
+            )
+            .send()
+            .await?;
+        let res = check_status(res).await?;
+        let res: model::Response<T> = res.json().await?;
+        Ok(res.data)
+    }
+
+    async fn list_resources(&self) -> Result<Vec<model::Resource>, Error> {
+        let resources: Vec<model::Resource> = self
+            .request(Method::GET, "/cluster/resources?type=vm")
+            .await?;
+        Ok(resources
+            .into_iter()
+            .filter(|resource| resource.template == 0)
+            .collect())
+    }
+
+    async fn find_resource(&self, id: &Id) -> Result<Option<model::Resource>, Error> {
+        let resources = self.list_resources().await?;
+        Ok(resources
+            .into_iter()
+            .find(|resource| resource.guest_type == id.guest_type && resource.vmid == id.vmid))
+    }
+
+    fn guest_path(resource: &model::Resource) -> String {
+        format!(
+            "/nodes/{}/{}/{}",
+            resource.node, resource.guest_type, resource.vmid
+        )
+    }
+
+    /// Run a status action, such as `start`, and wait for its task.
+    async fn guest_action(&self, resource: &model::Resource, action: &str) -> Result<(), Error> {
+        let path = format!("{}/status/{}", Self::guest_path(resource), action);
+        let upid: String = self.request(Method::POST, &path).await?;
+        self.wait_for_task(&resource.node, &upid).await
+    }
+
+    async fn wait_for_task(&self, node: &str, upid: &str) -> Result<(), Error> {
+        let path = &format!("/nodes/{}/tasks/{}/status", node, upid);
+        let result = action::wait_for(TASK_POLL_INTERVAL, self.task_timeout, || async move {
+            let task: model::TaskStatus = self.request(Method::GET, path).await?;
+            Ok(if task.status != model::TASK_STATUS_STOPPED {
+                ActionStatus::Running
+            } else if task.exitstatus.as_deref() == Some(model::TASK_EXIT_STATUS_OK) {
+                ActionStatus::Succeeded
+            } else {
+                ActionStatus::Failed(task.exitstatus.unwrap_or_default())
+            })
+        })
+        .await;
+        result.map_err(|err| match err {
+            WaitError::Poll(err) => err,
+            WaitError::Failed(exit_status) => Error::Task {
+                upid: upid.to_owned(),
+                exit_status,
+            },
+            WaitError::Timeout => Error::TaskTimeout(upid.to_owned()),
+        })
+    }
+
+    async fn start_guest(&self, resource: &model::Resource) -> Result<(), Error> {
+        // A virtual machine suspended to memory is still running, and must be
+        // resumed; one suspended to disk is resumed by starting it.
+        if resource.guest_type == model::GUEST_TYPE_QEMU && resource.status == model::STATUS_RUNNING
+        {
+            let path = format!("{}/status/current", Self::guest_path(resource));
+            let status: model::GuestStatus = self.request(Method::GET, &path).await?;
+            if matches!(
+                status.qmpstatus.as_deref(),
+                Some(model::QMP_STATUS_PAUSED | model::QMP_STATUS_SUSPENDED)
+            ) {
+                return self.guest_action(resource, "resume").await;
+            }
+        }
+        self.guest_action(resource, "start").await
+    }
+
+    async fn stop_guest(&self, resource: &model::Resource) -> Result<(), Error> {
+        let action = match self.stop_mode {
+            StopMode::Shutdown => "shutdown",
+            StopMode::Stop => "stop",
+            StopMode::Suspend if resource.guest_type == model::GUEST_TYPE_QEMU => {
+                "suspend?todisk=1"
+            }
+            StopMode::Suspend => "shutdown",
+        };
+        self.guest_action(resource, action).await
+    }
+
+    fn resource_to_instance(resource: model::Resource) -> crate::core::Instance {
+        let id = format!("{}/{}", resource.guest_type, resource.vmid);
+        let state = match (resource.status.as_str(), resource.lock.as_deref()) {
+            (_, Some(model::LOCK_SUSPENDING)) => crate::core::State::InProgress,
+            (_, Some(model::LOCK_SUSPENDED)) => crate::core::State::Off,
+            (model::STATUS_RUNNING, _) => crate::core::State::On,
+            (model::STATUS_STOPPED, _) => crate::core::State::Off,
+            _ => crate::core::State::Other,
+        };
+        let resource_type = match resource.guest_type.as_str() {
+            model::GUEST_TYPE_LXC => RESOURCE_TYPE_LXC,
+            _ => RESOURCE_TYPE_QEMU,
+        };
+        crate::core::Instance {
+            display_name: resource.name.unwrap_or_else(|| id.clone()),
+            id,
+            resource_type: resource_type.to_owned(),
+            state,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+
+    async fn resource(&self, id: &crate::core::IdRef) -> Result<model::Resource, anyhow::Error> {
+        let id = Id::try_from(id)?;
+        let resource = self
+            .find_resource(&id)
+            .await
+            .map_err(Error::into_core)?
+            .ok_or_else(|| Error::NotFound.into_core())?;
+        Ok(resource)
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let resources = self.list_resources().await.map_err(Error::into_core)?;
+        Ok(resources
+            .into_iter()
+            .map(Self::resource_to_instance)
+            .collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        self.request::<serde::de::IgnoredAny>(Method::GET, "/version")
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let id = Id::try_from(id)?;
+        let resource = self.find_resource(&id).await.map_err(Error::into_core)?;
+        Ok(resource.map(Self::resource_to_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let resource = self.resource(id).await?;
+        self.start_guest(&resource)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let resource = self.resource(id).await?;
+        self.stop_guest(&resource).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    /// A cluster with a virtual machine suspended to memory and a container
+    /// we may not stop; the actions are recorded in `actions`.
+    fn cluster(actions: Arc<Mutex<Vec<String>>>) -> Router {
+        Router::new()
+            .route(
+                "/api2/json/cluster/resources",
+                get(|| async {
+                    Json(serde_json::json!({"data": [
+                        {"type": "qemu", "vmid": 100, "node": "pve1", "status": "running"},
+                        {"type": "lxc", "vmid": 101, "node": "pve2", "status": "running"},
+                    ]}))
+                }),
+            )
+            .route(
+                "/api2/json/nodes/pve1/qemu/100/status/current",
+                get(|| async {
+                    Json(serde_json::json!({"data": {"status": "running", "qmpstatus": "paused"}}))
+                }),
+            )
+            .route(
+                "/api2/json/nodes/:node/:guest_type/:vmid/status/:action",
+                post(
+                    move |Path((node, guest_type, vmid, action)): Path<(
+                        String,
+                        String,
+                        u64,
+                        String,
+                    )>| async move {
+                        if guest_type == model::GUEST_TYPE_LXC {
+                            let message = "Permission check failed (/vms/101, VM.PowerMgmt)";
+                            return (StatusCode::FORBIDDEN, message.to_owned());
+                        }
+                        actions
+                            .lock()
+                            .unwrap()
+                            .push(format!("{}/{}/{} {}", node, guest_type, vmid, action));
+                        let upid = format!("UPID:{}:0001:qm{}:{}:root@pam:", node, action, vmid);
+                        (
+                            StatusCode::OK,
+                            serde_json::json!({ "data": upid }).to_string(),
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/api2/json/nodes/:node/tasks/:upid/status",
+                get(|| async {
+                    Json(serde_json::json!({"data": {"status": "stopped", "exitstatus": "OK"}}))
+                }),
+            )
+    }
+
+    fn fake_provider(app: Router) -> Provider {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider {
+            client: reqwest::Client::new(),
+            endpoint: format!("http://{}", addr),
+            token_id: "root@pam!vm-onoff".to_owned(),
+            token_secret: "secret".to_owned(),
+            stop_mode: StopMode::Shutdown,
+            task_timeout: Duration::from_secs(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_paused_guests_and_reports_failed_actions() {
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let provider = fake_provider(cluster(Arc::clone(&actions)));
+
+        provider.start("qemu/100").await.unwrap();
+        assert_eq!(*actions.lock().unwrap(), ["pve1/qemu/100 resume"]);
+
+        let err = provider.stop("lxc/101").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Forbidden);
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("Permission check failed (/vms/101, VM.PowerMgmt)")
+        );
+
+        assert!(provider.get("qemu/999").await.unwrap().is_none());
+        let err = provider.start("qemu/999").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn maps_cluster_resources() {
+        let json = r#"{"data":[
+            {"id":"qemu/100","type":"qemu","vmid":100,"node":"pve1","name":"web","status":"running","template":0},
+            {"id":"lxc/101","type":"lxc","vmid":101,"node":"pve2","status":"stopped"},
+            {"id":"qemu/102","type":"qemu","vmid":102,"node":"pve1","name":"db","status":"stopped","lock":"suspended"}
+        ]}"#;
+        let res: model::Response<Vec<model::Resource>> = serde_json::from_str(json).unwrap();
+        let instances: Vec<_> = res
+            .data
+            .into_iter()
+            .map(Provider::resource_to_instance)
+            .collect();
+        assert_eq!(instances[0].id, "qemu/100");
+        assert_eq!(instances[0].state, crate::core::State::On);
+        assert_eq!(instances[1].display_name, "lxc/101");
+        assert_eq!(instances[1].resource_type, RESOURCE_TYPE_LXC);
+        assert_eq!(instances[2].state, crate::core::State::Off);
+        assert_eq!(Id::try_from("lxc/101").unwrap().vmid, 101);
+        assert!(Id::try_from("vm/101").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+//! Proxmox VE API models.
+
+use serde::Deserialize;
+
+// Like the other providers' models, these only declare the fields we use.
+
+/// Every response wraps its payload in `data`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Response<T> {
+    pub data: T,
+}
+
+/// A guest, as listed by `/cluster/resources?type=vm`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Resource {
+    /// `qemu` or `lxc`.
+    #[serde(rename = "type")]
+    pub guest_type: String,
+    pub vmid: u64,
+    pub node: String,
+    pub name: Option<String>,
+    /// `running` or `stopped`.
+    #[serde(default)]
+    pub status: String,
+    /// Set while an operation, such as a suspension, holds the guest.
+    pub lock: Option<String>,
+    /// 1 for templates, which cannot be started.
+    #[serde(default)]
+    pub template: u8,
+}
+
+/// The current status of a guest, from `/status/current`.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct GuestStatus {
+    #[serde(default)]
+    pub status: String,
+    /// The status as QEMU reports it, e.g. `paused` for a guest suspended
+    /// to memory.
+    pub qmpstatus: Option<String>,
+    pub lock: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct TaskStatus {
+    /// `running` or `stopped`.
+    pub status: String,
+    /// `OK`, or the error, once stopped.
+    pub exitstatus: Option<String>,
+}
+
+pub const GUEST_TYPE_QEMU: &str = "qemu";
+pub const GUEST_TYPE_LXC: &str = "lxc";
+
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_STOPPED: &str = "stopped";
+
+pub const QMP_STATUS_PAUSED: &str = "paused";
+pub const QMP_STATUS_SUSPENDED: &str = "suspended";
+
+pub const LOCK_SUSPENDING: &str = "suspending";
+pub const LOCK_SUSPENDED: &str = "suspended";
+
+pub const TASK_STATUS_STOPPED: &str = "stopped";
+pub const TASK_EXIT_STATUS_OK: &str = "OK";
@@ -0,0 +1,511 @@
+//! Redfish provider implementation, powering bare-metal servers on and off
+//! through their BMC.
+//!
+//! Our IDs are the IDs of the systems in the BMC's `Systems` collection,
+//! which is usually a single `1` or serial number.
+
+use reqwest::{Method, StatusCode};
+
+mod model;
+
+const RESOURCE_TYPE: &str = "redfish/system";
+
+const SYSTEMS_PATH: &str = "/redfish/v1/Systems";
+const SESSIONS_PATH: &str = "/redfish/v1/SessionService/Sessions";
+
+pub struct Provider {
+    client: reqwest::Client,
+    endpoint: String,
+    username: String,
+    password: String,
+    auth_mode: AuthMode,
+    stop_mode: StopMode,
+    /// The token of the current session, in session mode.
+    session_token: tokio::sync::Mutex<Option<String>>,
+}
+
+/// How to authenticate to the BMC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    /// Send the credentials with every request.
+    Basic,
+    /// Open a session once and send its token, which BMCs handle much faster
+    /// than basic authentication; a new one is opened when it expires.
+    Session,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown auth mode {0:?}")]
+pub struct UnknownAuthMode(String);
+
+impl std::str::FromStr for AuthMode {
+    type Err = UnknownAuthMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(Self::Basic),
+            "session" => Ok(Self::Session),
+            _ => Err(UnknownAuthMode(s.to_owned())),
+        }
+    }
+}
+
+/// How to power a system off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopMode {
+    /// Ask the operating system to shut down, through ACPI.
+    GracefulShutdown,
+    /// Cut the power immediately.
+    ForceOff,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown stop mode {0:?}")]
+pub struct UnknownStopMode(String);
+
+impl std::str::FromStr for StopMode {
+    type Err = UnknownStopMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graceful-shutdown" => Ok(Self::GracefulShutdown),
+            "force-off" => Ok(Self::ForceOff),
+            _ => Err(UnknownStopMode(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{status_code} status code")]
+pub struct ServerError {
+    pub status_code: u16,
+    /// The Redfish `error` object, whose `@Message.ExtendedInfo` tends to
+    /// say more than its generic code; BMCs do not always send one.
+    pub error: Option<model::ErrorBody>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("server: {0}")]
+    Server(#[from] ServerError),
+    #[error("the session was created without a token")]
+    MissingSessionToken,
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Reqwest(_) => crate::core::ErrorKind::Unavailable,
+            Self::Server(err) => crate::core::ErrorKind::from_http_status(err.status_code),
+            Self::MissingSessionToken => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Server(err) => Some(crate::core::ProviderErrorDetails {
+                status_code: Some(err.status_code),
+                // The extended info is more specific than the general error,
+                // which is often just `Base.1.x.GeneralError`.
+                code: err
+                    .error
+                    .as_ref()
+                    .map(|error| match error.extended_info.first() {
+                        Some(info) if !info.message_id.is_empty() => info.message_id.clone(),
+                        _ => error.code.clone(),
+                    }),
+                message: err
+                    .error
+                    .as_ref()
+                    .map(|error| match error.extended_info.first() {
+                        Some(info) if !info.message.is_empty() => info.message.clone(),
+                        _ => error.message.clone(),
+                    }),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, ServerError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+
+    let error = res
+        .json::<model::ErrorResponse>()
+        .await
+        .ok()
+        .map(|res| res.error);
+    Err(ServerError {
+        status_code: status.as_u16(),
+        error,
+    })
+}
+
+impl Provider {
+    /// A provider for the BMC at `endpoint`, e.g. `https://bmc1`, with
+    /// basic authentication and graceful shutdowns.
+    pub fn new(
+        client: reqwest::Client,
+        endpoint: String,
+        username: String,
+        password: String,
+    ) -> Self {
+        Self {
+            client,
+            endpoint,
+            username,
+            password,
+            auth_mode: AuthMode::Basic,
+            stop_mode: StopMode::GracefulShutdown,
+            session_token: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn with_auth_mode(mut self, auth_mode: AuthMode) -> Self {
+        self.auth_mode = auth_mode;
+        self
+    }
+
+    pub fn with_stop_mode(mut self, stop_mode: StopMode) -> Self {
+        self.stop_mode = stop_mode;
+        self
+    }
+
+    async fn create_session(&self) -> Result<String, Error> {
+        let url = format!("{}{}", self.endpoint, SESSIONS_PATH);
+        let body = model::SessionRequest {
+            user_name: &self.username,
+            password: &self.password,
+        };
+        let res = self.client.post(url).json(&body).send().await?;
+        let res = check_status(res).await?;
+        let token = res
+            .headers()
+            .get(model::AUTH_TOKEN_HEADER)
+            .and_then(|token| token.to_str().ok())
+            .ok_or(Error::MissingSessionToken)?;
+        Ok(token.to_owned())
+    }
+
+    /// Send an authenticated request to a path of the BMC, opening a new
+    /// session and retrying once if the current one has expired.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&model::ResetRequest>,
+    ) -> Result<reqwest::Response, Error> {
+        let url = format!("{}{}", self.endpoint, path);
+        let build = |token: Option<&str>| {
+            let mut request = self.client.request(method.clone(), &url);
+            request = match token {
+                Some(token) => request.header(model::AUTH_TOKEN_HEADER, token),
+                None => request.basic_auth(&self.username, Some(&self.password)),
+            };
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            request
+        };
+
+        if self.auth_mode == AuthMode::Basic {
+            let res = build(None).send().await?;
+            return Ok(check_status(res).await?);
+        }
+
+        let mut session_token = self.session_token.lock().await;
+        let mut is_new = false;
+        if session_token.is_none() {
+            *session_token = Some(self.create_session().await?);
+            is_new = true;
+        }
+        let res = build(session_token.as_deref()).send().await?;
+        if res.status() == StatusCode::UNAUTHORIZED && !is_new {
+            *session_token = Some(self.create_session().await?);
+            let res = build(session_token.as_deref()).send().await?;
+            return Ok(check_status(res).await?);
+        }
+        Ok(check_status(res).await?)
+    }
+
+    async fn get_json<T>(&self, path: &str) -> Result<T, Error>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let res = self.send(Method::GET, path, None).await?;
+        Ok(res.json().await?)
+    }
+
+    async fn list_systems(&self) -> Result<Vec<model::ComputerSystem>, Error> {
+        let collection: model::Collection = self.get_json(SYSTEMS_PATH).await?;
+        let mut systems = Vec::with_capacity(collection.members.len());
+        for member in collection.members {
+            systems.push(self.get_json(&member.odata_id).await?);
+        }
+        Ok(systems)
+    }
+
+    async fn get_system(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<model::ComputerSystem>, Error> {
+        // IDs go in the path as is, and are never more than a segment.
+        if id.is_empty() || id.contains(['/', '?', '#', '%']) {
+            return Ok(None);
+        }
+        let path = format!("{}/{}", SYSTEMS_PATH, id);
+        match self.get_json(&path).await {
+            Ok(system) => Ok(Some(system)),
+            Err(Error::Server(ServerError {
+                status_code: 404, ..
+            })) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn reset(&self, id: &crate::core::IdRef, reset_type: &'static str) -> Result<(), Error> {
+        let system = self.get_system(id).await?.ok_or(ServerError {
+            status_code: 404,
+            error: None,
+        })?;
+        let target = match system.actions.get(model::ACTION_RESET) {
+            Some(action) => action.target.clone(),
+            None => format!(
+                "{}/{}/Actions/ComputerSystem.Reset",
+                SYSTEMS_PATH, system.id
+            ),
+        };
+        let body = model::ResetRequest { reset_type };
+        self.send(Method::POST, &target, Some(&body)).await?;
+        Ok(())
+    }
+
+    fn system_to_instance(system: model::ComputerSystem) -> crate::core::Instance {
+        let state = match system.power_state.as_deref() {
+            Some(model::POWER_STATE_ON) => crate::core::State::On,
+            Some(model::POWER_STATE_OFF) => crate::core::State::Off,
+            Some(model::POWER_STATE_POWERING_ON | model::POWER_STATE_POWERING_OFF) => {
+                crate::core::State::InProgress
+            }
+            // Paused, or unknown.
+            _ => crate::core::State::Other,
+        };
+        crate::core::Instance {
+            display_name: system.name.unwrap_or_else(|| system.id.clone()),
+            id: system.id,
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state,
+            observed_at: std::time::Instant::now(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            // BMCs are small embedded systems.
+            max_concurrent_gets: 2,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let systems = self.list_systems().await.map_err(Error::into_core)?;
+        Ok(systems.into_iter().map(Self::system_to_instance).collect())
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        // The collection alone, without fetching each system.
+        self.send(Method::GET, SYSTEMS_PATH, None)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let system = self.get_system(id).await.map_err(Error::into_core)?;
+        Ok(system.map(Self::system_to_instance))
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.reset(id, model::RESET_TYPE_ON)
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let reset_type = match self.stop_mode {
+            StopMode::GracefulShutdown => model::RESET_TYPE_GRACEFUL_SHUTDOWN,
+            StopMode::ForceOff => model::RESET_TYPE_FORCE_OFF,
+        };
+        self.reset(id, reset_type).await.map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::Path,
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::core::Provider as _;
+
+    fn check_token(headers: &HeaderMap) -> Result<(), StatusCode> {
+        match headers.get(model::AUTH_TOKEN_HEADER) {
+            Some(token) if token == "token" => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    async fn systems(headers: HeaderMap) -> Result<Json<serde_json::Value>, StatusCode> {
+        check_token(&headers)?;
+        Ok(Json(serde_json::json!({
+            "Members": [{"@odata.id": "/redfish/v1/Systems/437XR1138R2"}],
+        })))
+    }
+
+    async fn system(headers: HeaderMap) -> Result<Json<serde_json::Value>, StatusCode> {
+        check_token(&headers)?;
+        Ok(Json(serde_json::json!({
+            "Id": "437XR1138R2",
+            "Name": "WebFrontEnd483",
+            "PowerState": "PoweringOn",
+        })))
+    }
+
+    async fn create_session() -> (HeaderMap, Json<serde_json::Value>) {
+        let mut headers = HeaderMap::new();
+        headers.insert(model::AUTH_TOKEN_HEADER, "token".parse().unwrap());
+        (headers, Json(serde_json::json!({})))
+    }
+
+    #[tokio::test]
+    async fn lists_systems_with_session_from_fake_bmc() {
+        let app = Router::new()
+            .route(SYSTEMS_PATH, get(systems))
+            .route("/redfish/v1/Systems/437XR1138R2", get(system))
+            .route(SESSIONS_PATH, post(create_session));
+        let provider = fake_bmc(app).with_auth_mode(AuthMode::Session);
+        let instances = provider.list().await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].id, "437XR1138R2");
+        assert_eq!(instances[0].display_name, "WebFrontEnd483");
+        assert_eq!(instances[0].state, crate::core::State::InProgress);
+        assert!(provider.get("../Chassis").await.unwrap().is_none());
+    }
+
+    fn fake_bmc(app: Router) -> Provider {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Provider::new(
+            reqwest::Client::new(),
+            format!("http://{}", addr),
+            "root".to_owned(),
+            "password".to_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn resets_through_the_advertised_action() {
+        let resets = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/redfish/v1/Systems/:id",
+                get(|Path(id): Path<String>| async move {
+                    if id != "1" {
+                        let error = serde_json::json!({"error": {
+                            "code": "Base.1.8.GeneralError",
+                            "message": "A general error has occurred.",
+                            "@Message.ExtendedInfo": [{
+                                "MessageId": "Base.1.8.ResourceMissingAtURI",
+                                "Message": "The resource at the URI was not found.",
+                            }],
+                        }});
+                        return (StatusCode::NOT_FOUND, Json(error));
+                    }
+                    let system = serde_json::json!({
+                        "Id": "1",
+                        "PowerState": "PoweringOn",
+                        "Actions": {"#ComputerSystem.Reset": {"target": "/redfish/v1/Systems/1/Actions/Reset"}},
+                    });
+                    (StatusCode::OK, Json(system))
+                }),
+            )
+            .route(
+                "/redfish/v1/Systems/1/Actions/Reset",
+                post({
+                    let resets = Arc::clone(&resets);
+                    move |Json(body): Json<serde_json::Value>| async move {
+                        let reset_type = body["ResetType"].as_str().unwrap().to_owned();
+                        resets.lock().unwrap().push(reset_type.clone());
+                        if reset_type == model::RESET_TYPE_ON {
+                            return (StatusCode::NO_CONTENT, Json(serde_json::json!({})));
+                        }
+                        let error = serde_json::json!({"error": {
+                            "code": "Base.1.8.GeneralError",
+                            "message": "A general error has occurred.",
+                            "@Message.ExtendedInfo": [{
+                                "MessageId": "Base.1.8.OperationNotAllowed",
+                                "Message": "The system is powering on.",
+                            }],
+                        }});
+                        (StatusCode::CONFLICT, Json(error))
+                    }
+                }),
+            );
+        let provider = fake_bmc(app).with_stop_mode(StopMode::ForceOff);
+
+        provider.start("1").await.unwrap();
+        let err = provider.stop("1").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        let details = err.details.unwrap();
+        assert_eq!(
+            details.code.as_deref(),
+            Some("Base.1.8.OperationNotAllowed")
+        );
+        assert_eq!(
+            details.message.as_deref(),
+            Some("The system is powering on.")
+        );
+        assert_eq!(
+            *resets.lock().unwrap(),
+            [model::RESET_TYPE_ON, model::RESET_TYPE_FORCE_OFF]
+        );
+
+        assert!(provider.get("2").await.unwrap().is_none());
+        let err = provider.start("2").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+    }
+}
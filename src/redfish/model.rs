@@ -0,0 +1,89 @@
+//! Redfish models, as defined by the DMTF schemas.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// Like the other providers' models, these only declare the fields we use.
+
+/// A reference to another resource.
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Link {
+    #[serde(rename = "@odata.id")]
+    pub odata_id: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Collection {
+    #[serde(default)]
+    pub members: Vec<Link>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ComputerSystem {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The current power state, missing when the BMC cannot tell.
+    #[serde(default)]
+    pub power_state: Option<String>,
+    #[serde(default)]
+    pub actions: HashMap<String, Action>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct Action {
+    /// The URI to POST to.
+    pub target: String,
+}
+
+pub const ACTION_RESET: &str = "#ComputerSystem.Reset";
+
+pub const POWER_STATE_ON: &str = "On";
+pub const POWER_STATE_OFF: &str = "Off";
+pub const POWER_STATE_POWERING_ON: &str = "PoweringOn";
+pub const POWER_STATE_POWERING_OFF: &str = "PoweringOff";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ResetRequest {
+    pub reset_type: &'static str,
+}
+
+pub const RESET_TYPE_ON: &str = "On";
+pub const RESET_TYPE_GRACEFUL_SHUTDOWN: &str = "GracefulShutdown";
+pub const RESET_TYPE_FORCE_OFF: &str = "ForceOff";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SessionRequest<'a> {
+    pub user_name: &'a str,
+    pub password: &'a str,
+}
+
+/// The header carrying the session token.
+pub const AUTH_TOKEN_HEADER: &str = "X-Auth-Token";
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(rename = "@Message.ExtendedInfo", default)]
+    pub extended_info: Vec<Message>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Message {
+    #[serde(default)]
+    pub message_id: String,
+    #[serde(default)]
+    pub message: String,
+}
@@ -0,0 +1,79 @@
+//! The scripted providers, declared in a TOML file:
+//!
+//! ```toml
+//! [[providers]]
+//! key = "nas"
+//! list = "ssh nas vm-list --json"
+//! start = "ssh nas vm-start \"$VM_ONOFF_ID\""
+//! stop = "ssh nas vm-stop \"$VM_ONOFF_ID\""
+//!
+//! [providers.env]
+//! SSH_AUTH_SOCK = "/run/vm-onoff/ssh-agent.sock"
+//! ```
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub providers: Vec<Script>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    /// The key of the provider, as used in the API.
+    pub key: String,
+    /// The resource type reported for the instances.
+    #[serde(default = "default_resource_type")]
+    pub resource_type: String,
+    /// Prints the instances, one JSON object per line.
+    pub list: String,
+    /// Prints the instance `VM_ONOFF_ID` as a JSON object, or nothing if
+    /// there is no such instance; the instance is looked up in `list`'s
+    /// output if not set.
+    pub get: Option<String>,
+    /// Starts the instance `VM_ONOFF_ID`.
+    pub start: String,
+    /// Stops the instance `VM_ONOFF_ID`.
+    pub stop: String,
+    /// The shell running the commands, with `-c`.
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    /// Variables added to the environment of the commands.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// How long a command may take before it is killed, along with the
+    /// processes it started.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to read the configuration: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+fn default_resource_type() -> String {
+    "script".to_owned()
+}
+
+fn default_shell() -> String {
+    "sh".to_owned()
+}
+
+fn default_timeout_secs() -> u64 {
+    60
+}
@@ -0,0 +1,309 @@
+//! Providers defined entirely in configuration, by shell commands.
+//!
+//! `list` and `get` print instances as JSON objects, one per line:
+//! `{"id": "vm1", "name": "VM 1", "state": "on"}`, the name being optional
+//! and the state one of `on`, `off`, `in-progress` or `other`. The ID of the
+//! instance concerned is passed to `get`, `start` and `stop` in the
+//! `VM_ONOFF_ID` environment variable, never in the command line.
+//!
+//! Commands fail with a non-zero exit status, their standard error being
+//! the message. A few statuses classify the failure: 2 means the instance
+//! was not found, 3 a conflict with its current state, 4 missing
+//! permissions and 5 an unavailable backend.
+//!
+//! A command that times out is killed with the processes it started, which
+//! may leave the instance half started or stopped; commands are best made
+//! safe to run again.
+
+use std::{os::unix::process::CommandExt as _, process::Stdio, time::Duration};
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+pub mod config;
+
+/// The variable holding the ID of the instance a command is about.
+const ID_VARIABLE: &str = "VM_ONOFF_ID";
+
+pub struct Provider {
+    pub script: config::Script,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Line {
+    id: String,
+    name: Option<String>,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum State {
+    On,
+    Off,
+    InProgress,
+    Other,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to run the command: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("the command timed out")]
+    Timeout,
+    #[error("the command failed with {status}: {message}")]
+    Command {
+        status: std::process::ExitStatus,
+        message: String,
+    },
+    #[error("invalid output line {line:?}: {source}")]
+    Output {
+        line: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::Spawn(_) | Self::Timeout => crate::core::ErrorKind::Unavailable,
+            Self::Command { status, .. } => match status.code() {
+                Some(2) => crate::core::ErrorKind::NotFound,
+                Some(3) => crate::core::ErrorKind::Conflict,
+                Some(4) => crate::core::ErrorKind::Forbidden,
+                Some(5) => crate::core::ErrorKind::Unavailable,
+                _ => crate::core::ErrorKind::Other,
+            },
+            Self::Output { .. } => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Command { status, message } => Some(crate::core::ProviderErrorDetails {
+                code: status.code().map(|code| code.to_string()),
+                message: Some(message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+impl Provider {
+    /// Run a command, returning its standard output.
+    async fn run(&self, command: &str, id: Option<&crate::core::IdRef>) -> Result<String, Error> {
+        let mut child = std::process::Command::new(&self.script.shell);
+        child
+            .arg("-c")
+            .arg(command)
+            .envs(&self.script.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // For the processes it starts to be killed along with it.
+            .process_group(0);
+        if let Some(id) = id {
+            child.env(ID_VARIABLE, id);
+        }
+        let child = Command::from(child)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::Spawn)?;
+        let pid = child.id();
+
+        let timeout = Duration::from_secs(self.script.timeout_secs);
+        let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+            Ok(output) => output.map_err(Error::Spawn)?,
+            Err(_) => {
+                if let Some(pid) = pid {
+                    kill_process_group(pid);
+                }
+                return Err(Error::Timeout);
+            }
+        };
+        if !output.status.success() {
+            return Err(Error::Command {
+                status: output.status,
+                message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn parse_instances(&self, output: &str) -> Result<Vec<crate::core::Instance>, Error> {
+        output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let parsed: Line = serde_json::from_str(line).map_err(|source| Error::Output {
+                    line: line.to_owned(),
+                    source,
+                })?;
+                Ok(crate::core::Instance {
+                    display_name: parsed.name.unwrap_or_else(|| parsed.id.clone()),
+                    id: parsed.id,
+                    resource_type: self.script.resource_type.clone(),
+                    state: match parsed.state {
+                        State::On => crate::core::State::On,
+                        State::Off => crate::core::State::Off,
+                        State::InProgress => crate::core::State::InProgress,
+                        State::Other => crate::core::State::Other,
+                    },
+                    observed_at: std::time::Instant::now(),
+                })
+            })
+            .collect()
+    }
+
+    async fn list_instances(&self) -> Result<Vec<crate::core::Instance>, Error> {
+        let output = self.run(&self.script.list, None).await?;
+        self.parse_instances(&output)
+    }
+
+    async fn get_instance(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, Error> {
+        let get = match &self.script.get {
+            Some(get) => get,
+            None => {
+                let instances = self.list_instances().await?;
+                return Ok(instances.into_iter().find(|instance| instance.id == id));
+            }
+        };
+        match self.run(get, Some(id)).await {
+            Ok(output) => Ok(self.parse_instances(&output)?.into_iter().next()),
+            Err(err) if err.kind() == crate::core::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Kill the process group led by a command, which the processes it started
+/// belong to unless they left it.
+fn kill_process_group(pid: u32) {
+    if let Ok(pid) = libc::pid_t::try_from(pid) {
+        // SAFETY: `kill` has no memory safety requirements.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: self.script.get.is_some(),
+            ..Default::default()
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let instances = self.list_instances().await.map_err(Error::into_core)?;
+        Ok(instances)
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        let instance = self.get_instance(id).await.map_err(Error::into_core)?;
+        Ok(instance)
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.run(&self.script.start, Some(id))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        self.run(&self.script.stop, Some(id))
+            .await
+            .map_err(Error::into_core)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Provider as _;
+
+    #[tokio::test]
+    async fn runs_configured_commands() {
+        let config: config::Config = toml::from_str(
+            r#"
+            [[providers]]
+            key = "test"
+            list = """
+                echo '{"id": "vm1", "name": "VM 1", "state": "on"}'
+                echo
+                echo "{\\"id\\": \\"vm2\\", \\"state\\": \\"$INITIAL_STATE\\"}"
+            """
+            start = "echo 'vm1 is locked' >&2; exit 3"
+            stop = "test \"$VM_ONOFF_ID\" = vm1"
+
+            [providers.env]
+            INITIAL_STATE = "in-progress"
+            "#,
+        )
+        .unwrap();
+        let provider = Provider {
+            script: config.providers.into_iter().next().unwrap(),
+        };
+
+        let instances = provider.list().await.unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].display_name, "VM 1");
+        assert_eq!(instances[1].display_name, "vm2");
+        assert_eq!(instances[1].state, crate::core::State::InProgress);
+        assert!(provider.get("vm3").await.unwrap().is_none());
+
+        provider.stop("vm1").await.unwrap();
+        let err = provider.start("vm1").await.unwrap_err();
+        let err = err.downcast::<crate::core::ProviderError>().unwrap();
+        assert_eq!(err.kind, crate::core::ErrorKind::Conflict);
+        assert_eq!(
+            err.details.unwrap().message.as_deref(),
+            Some("vm1 is locked")
+        );
+    }
+
+    #[tokio::test]
+    async fn kills_what_timed_out_commands_started() {
+        let pid_file = std::env::temp_dir().join(format!("vm-onoff-{}.pid", std::process::id()));
+        let config: config::Config = toml::from_str(&format!(
+            r#"
+            [[providers]]
+            key = "test"
+            list = "true"
+            start = "sleep 30 & echo $! > '{}'; wait"
+            stop = "true"
+            timeout_secs = 1
+            "#,
+            pid_file.display()
+        ))
+        .unwrap();
+        let provider = Provider {
+            script: config.providers.into_iter().next().unwrap(),
+        };
+
+        assert!(provider.start("vm1").await.is_err());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        std::fs::remove_file(&pid_file).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Killed processes may linger as zombies until reaped.
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(stat.map_or(true, |stat| stat.contains(") Z ")));
+    }
+}
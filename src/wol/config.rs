@@ -0,0 +1,169 @@
+//! The machines, declared in a TOML file:
+//!
+//! ```toml
+//! shutdown_command = "sudo systemctl poweroff"
+//!
+//! [[machines]]
+//! id = "build1"
+//! mac = "00:11:22:33:44:55"
+//! broadcast = "192.168.1.255"
+//! ssh_host = "build1.lan"
+//! ssh_user = "ops"
+//! ```
+
+use std::{net::IpAddr, path::Path};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The command to run over SSH to shut machines down, unless they
+    /// override it.
+    #[serde(default = "default_shutdown_command")]
+    pub shutdown_command: String,
+    /// How long a machine is reported as in progress after being started or
+    /// stopped, until it becomes reachable or unreachable.
+    #[serde(default = "default_transition_timeout_secs")]
+    pub transition_timeout_secs: u64,
+    /// How long the reachability probe waits for an answer.
+    #[serde(default = "default_probe_timeout_secs")]
+    pub probe_timeout_secs: u64,
+    /// How long the shutdown command may take.
+    #[serde(default = "default_ssh_timeout_secs")]
+    pub ssh_timeout_secs: u64,
+    #[serde(default)]
+    pub machines: Vec<Machine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Machine {
+    pub id: String,
+    /// The display name, the ID by default.
+    pub name: Option<String>,
+    pub mac: MacAddress,
+    /// Where to send magic packets, the limited broadcast address by
+    /// default; routers only forward them to a subnet's broadcast address.
+    #[serde(default = "default_broadcast")]
+    pub broadcast: IpAddr,
+    #[serde(default = "default_wol_port")]
+    pub wol_port: u16,
+    pub ssh_host: String,
+    pub ssh_user: Option<String>,
+    #[serde(default = "default_ssh_port")]
+    pub ssh_port: u16,
+    pub shutdown_command: Option<String>,
+    #[serde(default)]
+    pub probe: Probe,
+    /// The port the TCP probe connects to, the SSH port by default.
+    pub probe_port: Option<u16>,
+}
+
+/// How to tell whether a machine is up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Probe {
+    /// Connect to a TCP port, which needs no privileges.
+    #[default]
+    Tcp,
+    /// Run `ping`, for machines that have no port open until fully booted.
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MacAddress(pub [u8; 6]);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid MAC address {0:?}")]
+pub struct InvalidMacAddress(String);
+
+impl std::str::FromStr for MacAddress {
+    type Err = InvalidMacAddress;
+
+    /// Parse `00:11:22:33:44:55` or `00-11-22-33-44-55`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidMacAddress(s.to_owned());
+        let mut bytes = [0; 6];
+        let mut parts = s.split([':', '-']);
+        for byte in &mut bytes {
+            let part = parts
+                .next()
+                .filter(|part| part.len() == 2)
+                .ok_or_else(invalid)?;
+            *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl TryFrom<String> for MacAddress {
+    type Error = InvalidMacAddress;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unable to read the configuration: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("machine {0:?} is declared twice")]
+    DuplicateId(String),
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let config: Self = toml::from_str(s)?;
+        let mut ids = std::collections::HashSet::new();
+        for machine in &config.machines {
+            if !ids.insert(&machine.id) {
+                return Err(Error::DuplicateId(machine.id.clone()));
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn default_shutdown_command() -> String {
+    "sudo shutdown -h now".to_owned()
+}
+
+fn default_transition_timeout_secs() -> u64 {
+    300
+}
+
+fn default_probe_timeout_secs() -> u64 {
+    2
+}
+
+fn default_ssh_timeout_secs() -> u64 {
+    30
+}
+
+fn default_broadcast() -> IpAddr {
+    IpAddr::from([255, 255, 255, 255])
+}
+
+fn default_wol_port() -> u16 {
+    9
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
@@ -0,0 +1,369 @@
+//! Provider for plain machines with no management API, declared in a
+//! configuration file: they are started with a Wake-on-LAN magic packet,
+//! stopped by running a command over SSH, and deemed on when they answer a
+//! reachability probe.
+//!
+//! Our IDs are the ones given in the configuration.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    process::Stdio,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::{stream, StreamExt};
+use tokio::{net::UdpSocket, process::Command};
+
+pub mod config;
+
+pub use config::Config;
+
+const RESOURCE_TYPE: &str = "wol/machine";
+
+/// How many machines may be probed at once.
+const MAX_CONCURRENT_PROBES: usize = 32;
+
+pub struct Provider {
+    config: Config,
+    /// The state machines are expected to reach after being started or
+    /// stopped, and until when to wait for it.
+    transitions: Mutex<HashMap<String, (crate::core::State, Instant)>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("machine not found")]
+    NotFound,
+    #[error("unable to send the magic packet: {0}")]
+    WakeOnLan(#[source] std::io::Error),
+    #[error("unable to run ssh: {0}")]
+    Spawn(#[source] std::io::Error),
+    #[error("ssh timed out")]
+    Timeout,
+    #[error("ssh: {message}")]
+    Ssh { message: String },
+}
+
+impl Error {
+    fn kind(&self) -> crate::core::ErrorKind {
+        match self {
+            Self::NotFound => crate::core::ErrorKind::NotFound,
+            Self::WakeOnLan(_) | Self::Spawn(_) | Self::Timeout => {
+                crate::core::ErrorKind::Unavailable
+            }
+            Self::Ssh { message } if message.contains("Permission denied") => {
+                crate::core::ErrorKind::Forbidden
+            }
+            Self::Ssh { .. } => crate::core::ErrorKind::Other,
+        }
+    }
+
+    fn into_core(self) -> crate::core::ProviderError {
+        let details = match &self {
+            Self::Ssh { message } => Some(crate::core::ProviderErrorDetails {
+                message: Some(message.clone()),
+                ..Default::default()
+            }),
+            _ => None,
+        };
+        crate::core::ProviderError {
+            kind: self.kind(),
+            details,
+            source: Box::new(self),
+        }
+    }
+}
+
+/// A magic packet: six `0xff` bytes followed by the MAC address sixteen
+/// times.
+fn magic_packet(mac: &config::MacAddress) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac.0);
+    }
+    packet
+}
+
+async fn send_magic_packet(machine: &config::Machine) -> std::io::Result<()> {
+    let target = SocketAddr::new(machine.broadcast, machine.wol_port);
+    let bind: SocketAddr = if target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0; 16], 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&magic_packet(&machine.mac), target).await?;
+    Ok(())
+}
+
+impl Provider {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            transitions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn machine(&self, id: &crate::core::IdRef) -> Option<&config::Machine> {
+        self.config.machines.iter().find(|machine| machine.id == id)
+    }
+
+    async fn is_reachable(&self, machine: &config::Machine) -> bool {
+        let timeout = Duration::from_secs(self.config.probe_timeout_secs);
+        match machine.probe {
+            config::Probe::Tcp => {
+                let port = machine.probe_port.unwrap_or(machine.ssh_port);
+                let connect = tokio::net::TcpStream::connect((machine.ssh_host.as_str(), port));
+                matches!(tokio::time::timeout(timeout, connect).await, Ok(Ok(_)))
+            }
+            config::Probe::Icmp => {
+                let status = Command::new("ping")
+                    .args(["-c", "1", "-W"])
+                    .arg(timeout.as_secs().max(1).to_string())
+                    .arg(&machine.ssh_host)
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .status();
+                // `ping`'s own timeout is only for the reply.
+                matches!(
+                    tokio::time::timeout(timeout * 2, status).await,
+                    Ok(Ok(status)) if status.success()
+                )
+            }
+        }
+    }
+
+    async fn machine_to_instance(&self, machine: &config::Machine) -> crate::core::Instance {
+        let observed = if self.is_reachable(machine).await {
+            crate::core::State::On
+        } else {
+            crate::core::State::Off
+        };
+
+        let state = {
+            let mut transitions = self.transitions.lock().unwrap();
+            match transitions.get(&machine.id) {
+                Some(&(expected, until)) if expected != observed && Instant::now() < until => {
+                    crate::core::State::InProgress
+                }
+                Some(_) => {
+                    transitions.remove(&machine.id);
+                    observed
+                }
+                None => observed,
+            }
+        };
+
+        crate::core::Instance {
+            id: machine.id.clone(),
+            display_name: machine.name.clone().unwrap_or_else(|| machine.id.clone()),
+            resource_type: RESOURCE_TYPE.to_owned(),
+            state,
+            observed_at: Instant::now(),
+        }
+    }
+
+    fn expect(&self, machine: &config::Machine, state: crate::core::State) {
+        let until = Instant::now() + Duration::from_secs(self.config.transition_timeout_secs);
+        self.transitions
+            .lock()
+            .unwrap()
+            .insert(machine.id.clone(), (state, until));
+    }
+
+    async fn shutdown(&self, machine: &config::Machine) -> Result<(), Error> {
+        let mut command = Command::new("ssh");
+        command
+            .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10", "-p"])
+            .arg(machine.ssh_port.to_string());
+        if let Some(user) = &machine.ssh_user {
+            command.arg("-l").arg(user);
+        }
+        let shutdown_command = machine
+            .shutdown_command
+            .as_deref()
+            .unwrap_or(&self.config.shutdown_command);
+        command
+            .arg(&machine.ssh_host)
+            .arg("--")
+            .arg(shutdown_command)
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+
+        let timeout = Duration::from_secs(self.config.ssh_timeout_secs);
+        let output = tokio::time::timeout(timeout, command.output())
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(Error::Spawn)?;
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        // The machine may well close the connection before the command
+        // returns.
+        if output.status.success() || message.contains("closed by remote host") {
+            return Ok(());
+        }
+        Err(Error::Ssh { message })
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::core::Provider for Provider {
+    fn capabilities(&self) -> crate::core::Capabilities {
+        crate::core::Capabilities {
+            efficient_get: true,
+            efficient_get_many: false,
+            max_concurrent_gets: MAX_CONCURRENT_PROBES,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<crate::core::Instance>, anyhow::Error> {
+        let probes: Vec<_> = self
+            .config
+            .machines
+            .iter()
+            .map(|machine| self.machine_to_instance(machine))
+            .collect();
+        let instances = stream::iter(probes)
+            .buffered(MAX_CONCURRENT_PROBES)
+            .collect()
+            .await;
+        Ok(instances)
+    }
+
+    async fn check_health(&self) -> Result<(), anyhow::Error> {
+        // There is no backend to reach: an unreachable machine is off, not
+        // a failure, so probing every one of them would tell us nothing.
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        id: &crate::core::IdRef,
+    ) -> Result<Option<crate::core::Instance>, anyhow::Error> {
+        Ok(match self.machine(id) {
+            Some(machine) => Some(self.machine_to_instance(machine).await),
+            None => None,
+        })
+    }
+
+    async fn start(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let machine = self
+            .machine(id)
+            .ok_or_else(|| Error::NotFound.into_core())?;
+        send_magic_packet(machine)
+            .await
+            .map_err(|err| Error::WakeOnLan(err).into_core())?;
+        self.expect(machine, crate::core::State::On);
+        Ok(())
+    }
+
+    async fn stop(&self, id: &crate::core::IdRef) -> Result<(), anyhow::Error> {
+        let machine = self
+            .machine(id)
+            .ok_or_else(|| Error::NotFound.into_core())?;
+        self.shutdown(machine).await.map_err(Error::into_core)?;
+        self.expect(machine, crate::core::State::Off);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Provider as _;
+
+    #[tokio::test]
+    async fn wakes_machine_and_probes_it() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config: Config = format!(
+            r#"
+            [[machines]]
+            id = "build1"
+            mac = "00-11-22-aa-bb-cc"
+            broadcast = "127.0.0.1"
+            wol_port = {}
+            ssh_host = "127.0.0.1"
+            ssh_port = {}
+            "#,
+            receiver.local_addr().unwrap().port(),
+            listener.local_addr().unwrap().port(),
+        )
+        .parse()
+        .unwrap();
+        let provider = Provider::new(config);
+
+        provider.start("build1").await.unwrap();
+        let mut packet = [0; 200];
+        let len = receiver.recv(&mut packet).await.unwrap();
+        assert_eq!(len, 102);
+        assert_eq!(&packet[..6], &[0xff; 6]);
+        assert_eq!(&packet[96..102], &[0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc]);
+
+        let instance = provider.get("build1").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::On);
+        drop(listener);
+        assert!(provider.get("build2").await.unwrap().is_none());
+        assert!("00:11:22:33:44".parse::<config::MacAddress>().is_err());
+    }
+
+    #[tokio::test]
+    async fn reports_unknown_machines_and_classifies_ssh_failures() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let config: Config = format!(
+            r#"
+            [[machines]]
+            id = "build1"
+            mac = "00-11-22-aa-bb-cc"
+            broadcast = "127.0.0.1"
+            wol_port = {}
+            ssh_host = "127.0.0.1"
+            ssh_port = {}
+            "#,
+            receiver.local_addr().unwrap().port(),
+            closed_port,
+        )
+        .parse()
+        .unwrap();
+        let provider = Provider::new(config);
+
+        for result in [
+            provider.start("build2").await,
+            provider.stop("build2").await,
+        ] {
+            let err = result
+                .unwrap_err()
+                .downcast::<crate::core::ProviderError>()
+                .unwrap();
+            assert_eq!(err.kind, crate::core::ErrorKind::NotFound);
+        }
+
+        // Until it answers, a machine we just woke up is on its way.
+        provider.start("build1").await.unwrap();
+        let instance = provider.get("build1").await.unwrap().unwrap();
+        assert_eq!(instance.state, crate::core::State::InProgress);
+
+        let denied = Error::Ssh {
+            message: "admin@10.0.0.5: Permission denied (publickey).".to_owned(),
+        }
+        .into_core();
+        assert_eq!(denied.kind, crate::core::ErrorKind::Forbidden);
+        assert_eq!(
+            denied.details.unwrap().message.as_deref(),
+            Some("admin@10.0.0.5: Permission denied (publickey).")
+        );
+        let refused = Error::Ssh {
+            message: "ssh: connect to host 10.0.0.5 port 22: Connection refused".to_owned(),
+        };
+        assert_eq!(refused.kind(), crate::core::ErrorKind::Other);
+        assert_eq!(Error::Timeout.kind(), crate::core::ErrorKind::Unavailable);
+    }
+}